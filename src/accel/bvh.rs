@@ -14,8 +14,7 @@ use crate::vec::{ Coord, Ray, Vec3 };
 struct BVHNode {
     bounding_box: AxisAlignedBoundingBox,
     object_indices: Option<(usize, usize)>,
-    children_indices: Option<(usize, usize)>,
-    split_axis: Option<Coord>
+    children_indices: Option<(usize, usize)>
 }
 
 #[derive(Debug)]
@@ -73,9 +72,9 @@ impl BVH {
                     const NUM_BUCKETS: usize = 12;
 
                     let mut buckets = vec![(0, AxisAlignedBoundingBox::empty()); 12];
-                    for i in 0..num_objs {
+                    for obj in &objs {
                         let mut b = (NUM_BUCKETS as f64 *
-                            centroids_bounds.point_offset(&objs[i].centroid())[split_axis]) as usize;
+                            centroids_bounds.point_offset(&obj.centroid())[split_axis]) as usize;
                         if b == NUM_BUCKETS {
                             b = NUM_BUCKETS - 1
                         }
@@ -83,7 +82,7 @@ impl BVH {
                         buckets[b].0 += 1;
                         buckets[b].1 = AxisAlignedBoundingBox::union(vec![
                             &buckets[b].1,
-                            &objs[i].bounding_box()
+                            &obj.bounding_box()
                         ]);
 
                     }
@@ -109,7 +108,7 @@ impl BVH {
                     }
 
                     let min_cost = bucket_costs.iter().enumerate().min_by(
-                        |a, b| a.1.partial_cmp(&b.1).unwrap_or_else(|| Ordering::Equal)
+                        |a, b| a.1.partial_cmp(b.1).unwrap_or(Ordering::Equal)
                     ).unwrap();
 
                     let leaf_cost = num_objs as f64;
@@ -137,9 +136,7 @@ impl BVH {
 
                 let left_child_index = self.build(start, mid);
                 let right_child_index = self.build(mid, end);
-                self.add_interior_node(
-                    split_axis, (left_child_index, right_child_index)
-                )
+                self.add_interior_node((left_child_index, right_child_index))
             }
         }
     }
@@ -149,7 +146,6 @@ impl BVH {
     {
         self.nodes.push(BVHNode {
             bounding_box,
-            split_axis: None,
             object_indices: Some(object_indices),
             children_indices: None
         });
@@ -157,7 +153,7 @@ impl BVH {
         self.nodes.len() - 1
     }
 
-    fn add_interior_node(&mut self, split_axis: Coord, children_indices: (usize, usize))
+    fn add_interior_node(&mut self, children_indices: (usize, usize))
         -> usize
     {
         let left_child = self.nodes.get(children_indices.0);
@@ -175,7 +171,6 @@ impl BVH {
         let bounds = AxisAlignedBoundingBox::union(bounds);
 
         self.nodes.push(BVHNode {
-            split_axis: Some(split_axis),
             children_indices: Some(children_indices),
             object_indices: None,
             bounding_box: bounds
@@ -1,8 +1,22 @@
 use super::vec::{ Coord, Point3, Ray, Vec3 };
 use super::math;
 
+/// The angle a camera sees across, in degrees. The other direction's angle follows from the image's
+/// aspect ratio, so it changes with the resolution while this one stays put.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FieldOfView {
+    Horizontal(f64),
+    Vertical(f64)
+}
+
 pub struct Camera {
     location: Point3,
+    look_at: Point3,
+    /// Which way is up in the image
+    up: Vec3,
+    fov: FieldOfView,
+    aperture: f64,
+    resolution: (u32, u32),
     top_left: Point3,
     vp_width: f64,
     vp_height: f64,
@@ -13,15 +27,36 @@ pub struct Camera {
 impl Camera {
     pub fn new(location: Point3, look_at: &Point3, fov_deg: f64, aperture: f64,
         vp_width: u32, vp_height: u32) -> Camera
+    {
+        Camera::oriented(location, look_at, Vec3::J, FieldOfView::Horizontal(fov_deg), aperture, vp_width, vp_height)
+    }
+
+    /// Creates a camera given its vertical field of view, which it keeps when its resolution changes
+    pub fn from_vertical_fov(location: Point3, look_at: &Point3, yfov_deg: f64, aperture: f64,
+        vp_width: u32, vp_height: u32) -> Camera
+    {
+        Camera::oriented(location, look_at, Vec3::J, FieldOfView::Vertical(yfov_deg), aperture, vp_width, vp_height)
+    }
+
+    fn oriented(location: Point3, look_at: &Point3, up: Vec3, fov: FieldOfView, aperture: f64,
+        vp_width: u32, vp_height: u32) -> Camera
     {
         let focal_length = (&location - look_at).norm();
 
         let aspect_ratio = f64::from(vp_width) / f64::from(vp_height);
-        let vp_x_max = focal_length * (f64::to_radians(fov_deg) / 2.0).tan();
-        let vp_y_max = vp_x_max / aspect_ratio;
+        let (vp_x_max, vp_y_max) = match fov {
+            FieldOfView::Horizontal(fov_deg) => {
+                let vp_x_max = focal_length * (f64::to_radians(fov_deg) / 2.0).tan();
+                (vp_x_max, vp_x_max / aspect_ratio)
+            },
+            FieldOfView::Vertical(fov_deg) => {
+                let vp_y_max = focal_length * (f64::to_radians(fov_deg) / 2.0).tan();
+                (vp_y_max * aspect_ratio, vp_y_max)
+            }
+        };
 
         let local_k = (&location - look_at).unit();
-        let local_i = Vec3::J.cross(&local_k).unit();
+        let local_i = up.cross(&local_k).unit();
         let local_j = local_k.cross(&local_i);
 
         Camera {
@@ -30,6 +65,10 @@ impl Camera {
                 + (&local_j * vp_y_max)
                 - &local_k * focal_length,
             location,
+            look_at: look_at.clone(),
+            up,
+            fov, aperture,
+            resolution: (vp_width, vp_height),
             vp_width: 2.0 * vp_x_max,
             vp_height: 2.0 * vp_y_max,
             lens_radius: 0.5 * aperture,
@@ -37,6 +76,33 @@ impl Camera {
         }
     }
 
+    pub fn location(&self) -> &Point3 {
+        &self.location
+    }
+
+    pub fn look_at(&self) -> &Point3 {
+        &self.look_at
+    }
+
+    /// Creates a camera with the same settings as this one, moved to a new location
+    pub fn with_location(&self, location: Point3) -> Camera {
+        Camera::oriented(location, &self.look_at, self.up.clone(), self.fov, self.aperture,
+            self.resolution.0, self.resolution.1)
+    }
+
+    /// Creates a camera with the same settings as this one, for an image of a different size
+    pub fn with_resolution(&self, width: u32, height: u32) -> Camera {
+        Camera::oriented(self.location.clone(), &self.look_at, self.up.clone(), self.fov, self.aperture,
+            width, height)
+    }
+
+    /// Creates a camera with the same settings as this one, rolled so that `up` points up the image.
+    /// Cameras are upright, with y up, unless they're given another direction.
+    pub fn with_up(&self, up: Vec3) -> Camera {
+        Camera::oriented(self.location.clone(), &self.look_at, up, self.fov, self.aperture,
+            self.resolution.0, self.resolution.1)
+    }
+
     pub fn ray(&self, u: f64, v: f64, rand: &mut math::Rand) -> Ray {
         let random_vec = self.lens_radius * Vec3::random_in_unit_disc(rand);
         let offset =
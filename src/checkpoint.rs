@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io::{ self, BufReader, Read, Write };
+use std::path::Path;
+
+use crate::framebuffer::Accumulator;
+use crate::ImageConfig;
+use crate::output;
+use crate::sampler::SamplerKind;
+use crate::vec::{ Color, ColorRGB };
+
+/// Identifies checkpoint files, and the version of their layout
+const MAGIC: &[u8; 8] = b"RTCKPT\x00\x03";
+
+/// The samplers a checkpoint can name, stored as their index in this list
+const SAMPLERS: [SamplerKind; 4] =
+    [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+/// The saved state of a progressive render
+#[derive(Debug, PartialEq)]
+pub struct Checkpoint {
+    pub accumulator: Accumulator,
+    /// The seed the render's random numbers are derived from
+    pub seed: u64,
+    /// The sampler that spread the samples' random numbers out
+    pub sampler: SamplerKind,
+    /// The number of samples per pixel the render was started with, which the stratified sampler
+    /// lays its strata out for
+    pub samples: u32
+}
+
+/// Writes the state of a progressive render: the image size, seed, sampler and samples per pixel,
+/// then every pixel's sum of samples as three little-endian f64s followed by its sample count as a
+/// u32.
+///
+/// Each sample's random numbers are derived from the seed, the sampler, the pixel and the sample's
+/// index, so those and the counts are all the generator state needed to carry on exactly where a
+/// render left off.
+pub fn encode(accumulator: &Accumulator, config: &ImageConfig, out: &mut dyn Write) -> io::Result<()> {
+    let sampler = SAMPLERS.iter().position(|&kind| kind == config.sampler).unwrap_or(0) as u8;
+
+    out.write_all(MAGIC)?;
+    out.write_all(&accumulator.width().to_le_bytes())?;
+    out.write_all(&accumulator.height().to_le_bytes())?;
+    out.write_all(&config.seed.to_le_bytes())?;
+    out.write_all(&[sampler])?;
+    out.write_all(&config.samples.to_le_bytes())?;
+
+    let mut bytes = Vec::with_capacity(28 * accumulator.counts().len());
+    for (sum, count) in accumulator.sums().iter().zip(accumulator.counts()) {
+        for &channel in &[Color::R, Color::G, Color::B] {
+            bytes.extend_from_slice(&sum[channel].to_le_bytes());
+        }
+        bytes.extend_from_slice(&count.to_le_bytes());
+    }
+    out.write_all(&bytes)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Not a valid checkpoint: {}", msg))
+}
+
+fn read_u32(input: &mut dyn Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut dyn Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(input: &mut dyn Read) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+/// Reads a checkpoint written by `encode`
+pub fn decode(input: &mut dyn Read) -> io::Result<Checkpoint> {
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if magic[..7] == MAGIC[..7] && magic[7] != MAGIC[7] {
+        return Err(invalid("written by a different version of the renderer"));
+    }
+    if &magic != MAGIC {
+        return Err(invalid("unrecognized header"));
+    }
+
+    let width = read_u32(input)?;
+    let height = read_u32(input)?;
+    let seed = read_u64(input)?;
+    let mut sampler = [0];
+    input.read_exact(&mut sampler)?;
+    let sampler = *SAMPLERS.get(usize::from(sampler[0])).ok_or_else(|| invalid("unknown sampler"))?;
+    let samples = read_u32(input)?;
+    // The pixels are read all at once, so that their number can be checked against the header's
+    // before anything is allocated for them
+    let mut pixels = vec![];
+    input.read_to_end(&mut pixels)?;
+    let pixel_count = u64::from(width) * u64::from(height);
+    if pixel_count > u64::from(u32::MAX) {
+        return Err(invalid("image is too large"));
+    }
+    if pixels.len() as u64 != pixel_count * 28 {
+        return Err(invalid("wrong number of pixels"));
+    }
+
+    let mut sums = Vec::with_capacity(pixel_count as usize);
+    let mut counts = Vec::with_capacity(pixel_count as usize);
+    for mut pixel in pixels.chunks_exact(28) {
+        let r = read_f64(&mut pixel)?;
+        let g = read_f64(&mut pixel)?;
+        let b = read_f64(&mut pixel)?;
+        sums.push(ColorRGB::new(r, g, b));
+        counts.push(read_u32(&mut pixel)?);
+    }
+
+    let accumulator = Accumulator::from_parts(width, height, sums, counts)
+        .ok_or_else(|| invalid("wrong number of pixels"))?;
+    Ok(Checkpoint { accumulator, seed, sampler, samples })
+}
+
+/// Saves a checkpoint, atomically replacing any earlier one at `path`
+pub fn save(path: &Path, accumulator: &Accumulator, config: &ImageConfig) -> io::Result<()> {
+    output::write_atomically(path, |out| encode(accumulator, config, out))
+}
+
+pub fn load(path: &Path) -> io::Result<Checkpoint> {
+    decode(&mut BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tile::Tile;
+
+    #[test]
+    fn checkpoint_round_trip() {
+        let mut accumulator = Accumulator::new(3, 2);
+        let tile = Tile { x: 1, y: 0, width: 2, height: 2 };
+        accumulator.add_tile(&tile, &vec![ColorRGB::new(0.5, 1.0, 1e9); 4], 3);
+
+        let config =
+            ImageConfig { seed: 42, sampler: SamplerKind::Stratified, samples: 64, ..Default::default() };
+        let mut bytes = vec![];
+        encode(&accumulator, &config, &mut bytes).unwrap();
+        assert_eq!(bytes.len(), 8 + 8 + 8 + 1 + 4 + 6 * 28);
+        assert_eq!(decode(&mut &bytes[..]).unwrap(),
+            Checkpoint { accumulator, seed: 42, sampler: SamplerKind::Stratified, samples: 64 });
+    }
+
+    #[test]
+    fn corrupt_checkpoints() {
+        let mut bytes = vec![];
+        encode(&Accumulator::new(2, 2), &ImageConfig::default(), &mut bytes).unwrap();
+
+        assert!(decode(&mut &bytes[..bytes.len() - 1]).is_err());
+        bytes.push(0);
+        assert!(decode(&mut &bytes[..]).is_err());
+        bytes.pop();
+
+        // A header claiming a huge image, with only a few pixels after it
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&[0xFF, 0xFF, 0, 0, 0xFF, 0xFF, 0, 0]);
+        assert!(decode(&mut &huge[..]).unwrap_err().to_string().contains("wrong number of pixels"));
+        assert!(decode(&mut &b"P6\n2 2\n255\n"[..]).is_err());
+
+        bytes[24] = 9;
+        assert!(decode(&mut &bytes[..]).unwrap_err().to_string().contains("unknown sampler"));
+
+        bytes[7] = 2;
+        assert!(decode(&mut &bytes[..]).unwrap_err().to_string().contains("different version"));
+    }
+}
@@ -0,0 +1,367 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use raytracer::output::ImageFormat;
+use raytracer::sampler::SamplerKind;
+use raytracer::tile::TileOrder;
+use raytracer::tonemap::ToneOperator;
+use raytracer::PreviewInterval;
+
+pub const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
+
+Renders SCENE, which is either a scene file, a glTF file (.gltf or .glb), or
+the number (1-5) of one of the scenes bundled in scenes/. Defaults to scene 1.
+
+Options:
+  -o, --output <PATH>     Where to write the image [default: <scene name>.png]
+                          When animating, the directory to write frames to
+                          [default: frames]
+  -f, --format <FORMAT>   Output format: png, ppm (binary), p3 (ASCII PPM), or
+                          hdr (Radiance) and exr (OpenEXR) for unclamped
+                          linear radiance [default: from the output extension]
+  -W, --width <N>         Image width in pixels
+  -H, --height <N>        Image height in pixels
+  -s, --samples <N>       Samples per pixel
+  -d, --max-depth <N>     Maximum number of bounces per ray
+  -j, --threads <N>       Number of worker threads [default: all cores]
+      --tile-size <N>     Width and height of the tiles threads render [default: 32]
+      --tile-order <ORDER>
+                          Order tiles are rendered in: scanline, spiral or
+                          hilbert [default: spiral]
+      --tonemap <CURVE>   Tone curve for 8-bit output: clamp, reinhard or aces
+                          [default: from the scene, or clamp]
+      --exposure <STOPS>  Exposure adjustment for 8-bit output
+      --white <RADIANCE>  Radiance that maps to display white
+      --seed <N>          Seed for the random number generator
+      --sampler <NAME>    How each pixel's samples are spread: independent,
+                          stratified, halton or sobol [default: sobol]
+  -m, --mode <MODE>       single, threaded or progressive [default: threaded]
+                          progressive renders a sample per pixel at a time,
+                          rewriting the image as it goes
+      --preview-every <N> How often progressive mode rewrites the image: a
+                          number of samples, or seconds with an 's' suffix,
+                          e.g. 30s [default: 10s]
+      --checkpoint <PATH> In progressive mode, save the samples taken so far to
+                          PATH alongside each preview
+      --resume <PATH>     In progressive mode, carry on from the samples saved
+                          in PATH, e.g. to finish an interrupted render or to
+                          add samples to a finished one
+      --scene <PATH>      Scene file to render, instead of SCENE
+      --animate <FRAMES>  Render FRAMES frames of the camera pivoting about
+                          the point it's looking at
+  -h, --help              Print this message";
+
+const SCENES: [&str; 5] = [
+    "scenes/spheres.scene",
+    "scenes/cornell.scene",
+    "scenes/balls.scene",
+    "scenes/armadillo.scene",
+    "scenes/planck.scene"
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Single,
+    Threaded,
+    Progressive
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub scene: PathBuf,
+    pub output: Option<PathBuf>,
+    pub format: Option<ImageFormat>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub samples: Option<u32>,
+    pub max_depth: Option<u32>,
+    pub threads: Option<usize>,
+    pub tile_size: Option<u32>,
+    pub tile_order: Option<TileOrder>,
+    pub tonemap: Option<ToneOperator>,
+    pub exposure: Option<f64>,
+    pub white_point: Option<f64>,
+    pub seed: Option<u64>,
+    pub sampler: Option<SamplerKind>,
+    pub mode: Mode,
+    pub preview: PreviewInterval,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: Option<PathBuf>,
+    pub animate: Option<u32>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Render(Box<Options>),
+    Help
+}
+
+#[derive(Debug, PartialEq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Mode {
+    type Err = UsageError;
+
+    fn from_str(s: &str) -> Result<Mode, UsageError> {
+        match s {
+            "single" => Ok(Mode::Single),
+            "threaded" => Ok(Mode::Threaded),
+            "progressive" | "live" => Ok(Mode::Progressive),
+            _ => Err(UsageError(format!("'{}' is not a render mode (expected single, threaded or progressive)", s)))
+        }
+    }
+}
+
+fn format(value: &str) -> Result<ImageFormat, UsageError> {
+    ImageFormat::from_name(value).ok_or_else(|| UsageError(
+        format!("'{}' is not a supported output format (expected png, ppm, p3, hdr or exr)", value)
+    ))
+}
+
+fn tile_order(value: &str) -> Result<TileOrder, UsageError> {
+    TileOrder::from_name(value).ok_or_else(|| UsageError(
+        format!("'{}' is not a tile order (expected scanline, spiral or hilbert)", value)
+    ))
+}
+
+fn sampler(value: &str) -> Result<SamplerKind, UsageError> {
+    SamplerKind::from_name(value).ok_or_else(|| UsageError(
+        format!("'{}' is not a sampler (expected independent, stratified, halton or sobol)", value)
+    ))
+}
+
+fn tone_operator(value: &str) -> Result<ToneOperator, UsageError> {
+    ToneOperator::from_name(value).ok_or_else(|| UsageError(
+        format!("'{}' is not a tone curve (expected clamp, reinhard or aces)", value)
+    ))
+}
+
+/// Parses a flag's value as any finite number
+fn number(flag: &str, value: &str) -> Result<f64, UsageError> {
+    match value.parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        _ => Err(UsageError(format!("{} expects a number, got '{}'", flag, value)))
+    }
+}
+
+fn preview_interval(value: &str) -> Result<PreviewInterval, UsageError> {
+    let interval = match value.strip_suffix('s') {
+        // Durations can't be negative, infinite or too long to count in whole seconds
+        Some(secs) => secs.parse::<f64>().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+            .map(PreviewInterval::Time),
+        None => value.parse::<u32>().ok().filter(|&samples| samples > 0).map(PreviewInterval::Samples)
+    };
+
+    interval.ok_or_else(|| UsageError(format!(
+        "--preview-every expects a number of samples or seconds (like 30s), got '{}'", value
+    )))
+}
+
+/// Parses a flag's value as a number no smaller than one
+fn positive<T>(flag: &str, value: &str) -> Result<T, UsageError>
+where
+    T: FromStr + PartialOrd + From<u8>
+{
+    match value.parse::<T>() {
+        Ok(n) if n >= T::from(1) => Ok(n),
+        _ => Err(UsageError(format!("{} expects a positive integer, got '{}'", flag, value)))
+    }
+}
+
+fn scene_path(arg: &str) -> Result<PathBuf, UsageError> {
+    // Scenes can be picked by their number in the README or given as a path
+    match arg.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= SCENES.len() => Ok(PathBuf::from(SCENES[n - 1])),
+        Ok(n) => Err(UsageError(format!("{} is not a valid scene number (expected 1-{})", n, SCENES.len()))),
+        Err(_) => Ok(PathBuf::from(arg))
+    }
+}
+
+/// Parses command-line arguments, not including the program name
+pub fn parse_args<I>(args: I) -> Result<Command, UsageError>
+where
+    I: IntoIterator<Item = String>
+{
+    let mut options = Options {
+        scene: PathBuf::from(SCENES[0]),
+        output: None,
+        format: None,
+        width: None,
+        height: None,
+        samples: None,
+        max_depth: None,
+        threads: None,
+        tile_size: None,
+        tile_order: None,
+        tonemap: None,
+        exposure: None,
+        white_point: None,
+        seed: None,
+        sampler: None,
+        mode: Mode::Threaded,
+        preview: PreviewInterval::Time(Duration::from_secs(10)),
+        checkpoint: None,
+        resume: None,
+        animate: None
+    };
+    let mut scene = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+
+        if !arg.starts_with('-') || arg == "-" {
+            if scene.is_some() {
+                return Err(UsageError(format!("Unexpected argument '{}'", arg)));
+            }
+            scene = Some(scene_path(&arg)?);
+            continue;
+        }
+
+        // Flags take their value either as the next argument or after an '='
+        let (flag, inline_value) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (arg[..i].to_string(), Some(arg[i + 1..].to_string())),
+            _ => (arg.clone(), None)
+        };
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(UsageError(format!("{} expects a value", flag)))
+        };
+
+        match &flag[..] {
+            "-o" | "--output" => options.output = Some(PathBuf::from(value)),
+            "-f" | "--format" => options.format = Some(format(&value)?),
+            "-W" | "--width" => options.width = Some(positive(&flag, &value)?),
+            "-H" | "--height" => options.height = Some(positive(&flag, &value)?),
+            "-s" | "--samples" => options.samples = Some(positive(&flag, &value)?),
+            "-d" | "--max-depth" => options.max_depth = Some(positive(&flag, &value)?),
+            "-j" | "--threads" => options.threads = Some(positive(&flag, &value)?),
+            "--tile-size" => options.tile_size = Some(positive(&flag, &value)?),
+            "--tile-order" => options.tile_order = Some(tile_order(&value)?),
+            "--sampler" => options.sampler = Some(sampler(&value)?),
+            "--tonemap" => options.tonemap = Some(tone_operator(&value)?),
+            "--exposure" => options.exposure = Some(number(&flag, &value)?),
+            "--white" => match number(&flag, &value)? {
+                white if white > 0.0 => options.white_point = Some(white),
+                _ => return Err(UsageError(format!("--white expects a positive number, got '{}'", value)))
+            },
+            "--seed" => match value.parse() {
+                Ok(seed) => options.seed = Some(seed),
+                Err(_) => return Err(UsageError(format!("--seed expects an integer, got '{}'", value)))
+            },
+            "-m" | "--mode" => options.mode = value.parse()?,
+            "--preview-every" => options.preview = preview_interval(&value)?,
+            "--checkpoint" => options.checkpoint = Some(PathBuf::from(value)),
+            "--resume" => options.resume = Some(PathBuf::from(value)),
+            "--scene" => {
+                if scene.is_some() {
+                    return Err(UsageError("More than one scene given".to_string()));
+                }
+                scene = Some(PathBuf::from(value));
+            },
+            "--animate" => options.animate = Some(positive(&flag, &value)?),
+            _ => return Err(UsageError(format!("Unknown option '{}'", flag)))
+        }
+    }
+
+    if let Some(scene) = scene {
+        options.scene = scene;
+    }
+
+    Ok(Command::Render(Box::new(options)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, UsageError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse_options(args: &[&str]) -> Options {
+        match parse(args) {
+            Ok(Command::Render(options)) => *options,
+            other => panic!("Expected render options, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse_options(&[]);
+        assert_eq!(options.scene, PathBuf::from("scenes/spheres.scene"));
+        assert_eq!(options.mode, Mode::Threaded);
+        assert_eq!(options.samples, None);
+    }
+
+    #[test]
+    fn flags_and_scene() {
+        let options = parse_options(&[
+            "-W", "320", "--height=240", "-s", "16", "--seed", "42", "-m", "single",
+            "--tonemap", "reinhard", "--exposure=-1.5", "--tile-order", "hilbert", "--sampler", "halton", "my.scene"
+        ]);
+        assert_eq!(options.scene, PathBuf::from("my.scene"));
+        assert_eq!(options.width, Some(320));
+        assert_eq!(options.height, Some(240));
+        assert_eq!(options.samples, Some(16));
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.tile_order, Some(TileOrder::Hilbert));
+        assert_eq!(options.sampler, Some(SamplerKind::Halton));
+        assert_eq!(options.tonemap, Some(ToneOperator::Reinhard));
+        assert_eq!(options.exposure, Some(-1.5));
+        assert_eq!(options.mode, Mode::Single);
+    }
+
+    #[test]
+    fn scene_number() {
+        let options = parse_options(&["2"]);
+        assert_eq!(options.scene, PathBuf::from("scenes/cornell.scene"));
+        assert!(parse(&["6"]).is_err());
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(parse(&["--samples", "many"]).is_err());
+        assert!(parse(&["--width", "0"]).is_err());
+        assert!(parse(&["--mode", "fast"]).is_err());
+        assert!(parse(&["--format", "gif"]).is_err());
+        assert!(parse(&["--tonemap", "hable"]).is_err());
+        assert!(parse(&["--tile-order", "random"]).is_err());
+        assert!(parse(&["--preview-every", "0"]).is_err());
+        assert!(parse(&["--preview-every", "soon"]).is_err());
+        assert!(parse(&["--preview-every", "1e30s"]).is_err());
+        assert!(parse(&["--preview-every", "-1s"]).is_err());
+        assert!(parse(&["--white", "0"]).is_err());
+        assert!(parse(&["--threads"]).is_err());
+        assert!(parse(&["--frobnicate", "1"]).is_err());
+        assert!(parse(&["1", "2"]).is_err());
+    }
+
+    #[test]
+    fn preview_intervals() {
+        let options = parse_options(&["-m", "progressive", "--preview-every", "16"]);
+        assert_eq!(options.mode, Mode::Progressive);
+        assert_eq!(options.preview, PreviewInterval::Samples(16));
+
+        let options = parse_options(&["--preview-every=2.5s", "--checkpoint", "a.ckpt", "--resume", "a.ckpt"]);
+        assert_eq!(options.preview, PreviewInterval::Time(Duration::from_millis(2500)));
+        assert_eq!(options.checkpoint, Some(PathBuf::from("a.ckpt")));
+        assert_eq!(options.resume, options.checkpoint);
+    }
+
+    #[test]
+    fn help() {
+        assert_eq!(parse(&["-s", "4", "--help"]), Ok(Command::Help));
+    }
+}
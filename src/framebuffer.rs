@@ -0,0 +1,238 @@
+use std::ops;
+
+use crate::colors;
+use crate::tile::Tile;
+use crate::vec::ColorRGB;
+
+/// A rendered image of linear, unclamped radiance, stored row-major with the top row first
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<ColorRGB>
+}
+
+impl Framebuffer {
+    /// Creates a black image
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer { width, height, pixels: vec![colors::BLACK; width as usize * height as usize] }
+    }
+
+    /// Wraps existing pixels, or returns `None` if there aren't exactly `width * height` of them
+    pub fn from_pixels(width: u32, height: u32, pixels: Vec<ColorRGB>) -> Option<Framebuffer> {
+        if pixels.len() != width as usize * height as usize {
+            return None;
+        }
+
+        Some(Framebuffer { width, height, pixels })
+    }
+
+    /// Creates an image by averaging pixels that each hold the sum of `samples` samples, or returns
+    /// `None` if there aren't exactly `width * height` of them
+    pub fn from_sums(width: u32, height: u32, sums: &[ColorRGB], samples: u32) -> Option<Framebuffer> {
+        let scale = 1.0 / f64::from(samples.max(1));
+        Framebuffer::from_pixels(width, height, sums.iter().map(|sum| sum * scale).collect())
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[ColorRGB] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [ColorRGB] {
+        &mut self.pixels
+    }
+
+    pub fn into_pixels(self) -> Vec<ColorRGB> {
+        self.pixels
+    }
+
+    /// Iterates over the rows of the image, top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[ColorRGB]> {
+        self.pixels.chunks(self.width.max(1) as usize)
+    }
+
+    /// Copies a tile's pixels, given in row-major order, into the image
+    pub fn write_tile(&mut self, tile: &Tile, pixels: &[ColorRGB]) {
+        for (row, tile_row) in pixels.chunks(tile.width as usize).enumerate() {
+            let start = self.index(tile.x, tile.y + row as u32);
+            self.pixels[start..start + tile_row.len()].clone_from_slice(tile_row);
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height, "Pixel ({}, {}) is outside the image", x, y);
+        y as usize * self.width as usize + x as usize
+    }
+}
+
+/// Running sums of every pixel's samples, for images that are refined over several passes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Accumulator {
+    width: u32,
+    height: u32,
+    sums: Vec<ColorRGB>,
+    counts: Vec<u32>
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Accumulator {
+        let pixel_count = width as usize * height as usize;
+        Accumulator { width, height, sums: vec![colors::BLACK; pixel_count], counts: vec![0; pixel_count] }
+    }
+
+    /// Restores an accumulator from its sums and counts, or returns `None` if there aren't exactly
+    /// `width * height` of each
+    pub fn from_parts(width: u32, height: u32, sums: Vec<ColorRGB>, counts: Vec<u32>) -> Option<Accumulator> {
+        let pixel_count = width as usize * height as usize;
+        if sums.len() != pixel_count || counts.len() != pixel_count {
+            return None;
+        }
+
+        Some(Accumulator { width, height, sums, counts })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The sum of each pixel's samples, in row-major order
+    pub fn sums(&self) -> &[ColorRGB] {
+        &self.sums
+    }
+
+    /// How many samples each pixel has taken, in row-major order
+    pub fn counts(&self) -> &[u32] {
+        &self.counts
+    }
+
+    /// The fewest samples any pixel has taken
+    pub fn min_samples(&self) -> u32 {
+        self.counts.iter().copied().min().unwrap_or(0)
+    }
+
+    /// The fewest samples any pixel within a tile has taken
+    pub fn min_samples_in(&self, tile: &Tile) -> u32 {
+        (tile.y..tile.y + tile.height)
+            .flat_map(|y| {
+                let start = y as usize * self.width as usize + tile.x as usize;
+                self.counts[start..start + tile.width as usize].iter().copied()
+            })
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// Adds a tile's sums of `samples` samples each, given in row-major order
+    pub fn add_tile(&mut self, tile: &Tile, sums: &[ColorRGB], samples: u32) {
+        for (row, tile_row) in sums.chunks(tile.width as usize).enumerate() {
+            let start = (tile.y as usize + row) * self.width as usize + tile.x as usize;
+            for (i, sum) in tile_row.iter().enumerate() {
+                self.sums[start + i] += sum;
+                self.counts[start + i] += samples;
+            }
+        }
+    }
+
+    /// Adds a tile's samples for the `pass`th pass, given in row-major order, to the pixels that
+    /// don't have that pass's sample yet. A render resumed with other tiles than it was started with
+    /// can have tiles whose pixels are partly a pass ahead.
+    pub fn add_pass(&mut self, tile: &Tile, samples: &[ColorRGB], pass: u32) {
+        for (row, tile_row) in samples.chunks(tile.width as usize).enumerate() {
+            let start = (tile.y as usize + row) * self.width as usize + tile.x as usize;
+            for (i, sample) in tile_row.iter().enumerate() {
+                if self.counts[start + i] < pass {
+                    self.sums[start + i] += sample;
+                    self.counts[start + i] += 1;
+                }
+            }
+        }
+    }
+
+    /// Averages every pixel over the samples it has so far. Pixels without any are black.
+    pub fn image(&self) -> Framebuffer {
+        let pixels = self.sums.iter().zip(&self.counts)
+            .map(|(sum, &count)| if count == 0 { colors::BLACK } else { sum * (1.0 / f64::from(count)) })
+            .collect();
+
+        Framebuffer { width: self.width, height: self.height, pixels }
+    }
+}
+
+/// Indexes pixels by `(x, y)`, with `(0, 0)` at the top left
+impl ops::Index<(u32, u32)> for Framebuffer {
+    type Output = ColorRGB;
+
+    fn index(&self, (x, y): (u32, u32)) -> &ColorRGB {
+        &self.pixels[self.index(x, y)]
+    }
+}
+
+impl ops::IndexMut<(u32, u32)> for Framebuffer {
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut ColorRGB {
+        let index = self.index(x, y);
+        &mut self.pixels[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_indexing() {
+        let mut image = Framebuffer::new(3, 2);
+        image[(2, 1)] = colors::WHITE;
+
+        assert_eq!(image.pixels()[5], colors::WHITE);
+        assert_eq!(image.rows().count(), 2);
+        assert_eq!(image.rows().last().unwrap()[2], colors::WHITE);
+    }
+
+    #[test]
+    fn tiles_written_in_place() {
+        let mut image = Framebuffer::new(4, 4);
+        let tile = Tile { x: 1, y: 2, width: 2, height: 2 };
+        image.write_tile(&tile, &[colors::WHITE; 4]);
+
+        assert_eq!(image[(1, 2)], colors::WHITE);
+        assert_eq!(image[(2, 3)], colors::WHITE);
+        assert_eq!(image.pixels().iter().filter(|&pixel| *pixel == colors::WHITE).count(), 4);
+    }
+
+    #[test]
+    fn averaged_sums() {
+        let sums = vec![ColorRGB::new(4.0, 2.0, 0.0); 4];
+        let image = Framebuffer::from_sums(2, 2, &sums, 4).unwrap();
+        assert_eq!(image[(1, 1)], ColorRGB::new(1.0, 0.5, 0.0));
+
+        assert!(Framebuffer::from_sums(2, 2, &sums[..3], 4).is_none());
+        assert!(Framebuffer::from_pixels(2, 2, vec![colors::BLACK; 3]).is_none());
+    }
+
+    #[test]
+    fn accumulated_tiles() {
+        let mut accumulator = Accumulator::new(4, 2);
+        let left = Tile { x: 0, y: 0, width: 2, height: 2 };
+        accumulator.add_tile(&left, &vec![ColorRGB::new(2.0, 2.0, 2.0); 4], 2);
+        accumulator.add_tile(&left, &vec![ColorRGB::new(4.0, 4.0, 4.0); 4], 2);
+
+        assert_eq!(accumulator.min_samples(), 0);
+        assert_eq!(accumulator.min_samples_in(&left), 4);
+        assert_eq!(accumulator.counts()[5], 4);
+
+        let image = accumulator.image();
+        assert_eq!(image[(1, 1)], ColorRGB::new(1.5, 1.5, 1.5));
+        assert_eq!(image[(2, 0)], colors::BLACK);
+    }
+}
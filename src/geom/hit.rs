@@ -1,30 +1,85 @@
 use std::sync::Arc;
 
 use crate::accel::bvh::BVH;
-use crate::material::Material;
+use crate::material::{ Material, Medium };
 use crate::math;
-use crate::vec::{ Coord, Point3, Vec3, Ray };
+use crate::microfacet::Frame;
+use crate::vec::{ ColorRGB, Coord, Point3, Vec3, Ray };
 
 pub type HittableRefs = Vec<Arc<dyn BoundedHittable>>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Hit {
     pub point: Point3,
+    /// The normal materials shade with, facing the ray. Normal and bump maps tilt it away from
+    /// the geometric normal.
     pub normal: Vec3,
+    /// The surface's true normal, facing the same way as `normal`. Which side was hit and how
+    /// much area a light covers go by this one.
+    pub geometric_normal: Vec3,
     pub t: f64,
     pub outer: bool,
-    pub material: Arc<dyn Material>
+    pub material: Arc<dyn Material>,
+    /// Where the hit is on the surface, for looking up textures. Shapes without a natural
+    /// parameterization leave it at (0, 0).
+    pub uv: (f64, f64),
+    /// How the point moves as u and v change, which orients textures and anisotropic materials
+    /// on the surface. Shapes without a parameterization leave them at zero.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    /// The color blended from the corners of meshes that have colored vertices
+    pub vertex_color: Option<ColorRGB>,
+    /// The refractive index of whatever surrounds the material, which depends on what the ray
+    /// is inside of, so it's filled in while following a path
+    pub exterior_ior: f64
 }
 
 impl Hit {
     pub const FP_OFFSET: f64 = 0.001;
 
     pub fn new(point: Point3, normal: Vec3, t: f64, outer: bool, material: Arc<dyn Material>) -> Hit {
+        let normal = if outer { normal.unit() } else { -normal.unit() };
         Hit {
             point, t, outer, material,
-            normal: if outer { normal.unit() } else { -normal.unit() }
+            geometric_normal: normal.clone(),
+            normal,
+            uv: (0.0, 0.0),
+            dpdu: Vec3::O,
+            dpdv: Vec3::O,
+            vertex_color: None,
+            exterior_ior: Medium::AIR.ior
         }
     }
+
+    pub fn with_uv(mut self, uv: (f64, f64)) -> Hit {
+        self.uv = uv;
+        self
+    }
+
+    /// Sets the hit's uv coordinates along with the derivatives of the point with respect to them
+    pub fn with_surface(mut self, uv: (f64, f64), dpdu: Vec3, dpdv: Vec3) -> Hit {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
+    }
+
+    pub fn with_vertex_color(mut self, color: ColorRGB) -> Hit {
+        self.vertex_color = Some(color);
+        self
+    }
+
+    /// Shades with `normal` instead of the geometric normal, turned to face the same side
+    pub fn with_shading_normal(mut self, normal: Vec3) -> Hit {
+        let normal = normal.unit();
+        self.normal = if normal.dot(&self.geometric_normal) < 0.0 { -normal } else { normal };
+        self
+    }
+
+    /// The basis materials shade in: the normal, with the first tangent along `dpdu`
+    pub fn frame(&self) -> Frame {
+        Frame::from_normal_tangent(&self.normal, &self.dpdu)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -53,6 +108,11 @@ impl AxisAlignedBoundingBox {
         &self.center
     }
 
+    /// The vector from the box's back bottom left corner to its front top right one
+    pub fn diagonal(&self) -> Vec3 {
+        &self.ftr_corner - &self.bbl_corner
+    }
+
     pub fn surface_area(&self) -> f64 {
         let size = &self.ftr_corner - &self.bbl_corner;
         2.0 * size[Coord::X] * size[Coord::Y]
@@ -110,7 +170,7 @@ impl AxisAlignedBoundingBox {
     }
 
     pub fn union(boxes: Vec<&Self>) -> AxisAlignedBoundingBox {
-        if boxes.len() == 0 {
+        if boxes.is_empty() {
             return AxisAlignedBoundingBox::new(Point3::O, Point3::O);
         }
 
@@ -134,7 +194,7 @@ impl AxisAlignedBoundingBox {
         AxisAlignedBoundingBox::union(bounding_boxes.iter().collect())
     }
 
-    pub fn union_from_points(points: &Vec<Point3>) -> AxisAlignedBoundingBox {
+    pub fn union_from_points(points: &[Point3]) -> AxisAlignedBoundingBox {
         let points_boxes: Vec<_> = points.iter()
             .map(|point| AxisAlignedBoundingBox::new(point.clone(), point.clone())).collect();
         AxisAlignedBoundingBox::union(points_boxes.iter().collect())
@@ -163,24 +223,43 @@ pub trait Bounded {
 pub trait Hittable {
     fn is_hit(&self, ray: &Ray, t_min: f64, t_max: f64, rand: &mut math::Rand) -> Option<Hit>;
     fn surface_area(&self) -> f64 { 0.0 }
+    /// Whether any part of the surface gives off light
+    fn is_emissive(&self) -> bool { false }
+    /// Maps a point in the unit square to a point on the surface, uniformly by area, so that lights
+    /// can be sampled directly. Hittables without a surface to sample return `None`.
+    fn sample_surface(&self, _: (f64, f64)) -> Option<Point3> { None }
 }
 
 pub trait BoundedHittable: Bounded + Hittable + Send + Sync + std::fmt::Debug {}
 
 #[derive(Debug)]
 pub struct HittableGroup {
-    accel: BVH
+    accel: BVH,
+    /// Running totals of the hittables' surface areas, for picking one in proportion to its area
+    cumulative_areas: Vec<f64>
 }
 
 impl HittableGroup {
     pub fn new(hittables: HittableRefs) -> HittableGroup {
         let accel = BVH::new(hittables);
-        HittableGroup { accel }
+        let cumulative_areas = accel.objects().iter()
+            .scan(0.0, |total, obj| {
+                *total += obj.surface_area();
+                Some(*total)
+            })
+            .collect();
+
+        HittableGroup { accel, cumulative_areas }
     }
 
     pub fn hittables(&self) -> &HittableRefs {
         self.accel.objects()
     }
+
+    /// Gathers the hittables that give off light into a group of their own, to sample directly
+    pub fn emitters(&self) -> HittableGroup {
+        HittableGroup::new(self.hittables().iter().filter(|obj| obj.is_emissive()).cloned().collect())
+    }
 }
 
 impl Hittable for HittableGroup {
@@ -189,7 +268,26 @@ impl Hittable for HittableGroup {
     }
 
     fn surface_area(&self) -> f64 {
-        self.accel.objects().iter().fold(0.0, |acc, obj| acc + obj.surface_area())
+        self.cumulative_areas.last().copied().unwrap_or(0.0)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.hittables().iter().any(|obj| obj.is_emissive())
+    }
+
+    fn sample_surface(&self, (u, v): (f64, f64)) -> Option<Point3> {
+        let total_area = self.surface_area();
+        if total_area <= 0.0 {
+            return None;
+        }
+
+        let target = u * total_area;
+        let i = self.cumulative_areas.partition_point(|&area| area <= target).min(self.cumulative_areas.len() - 1);
+        let start = if i == 0 { 0.0 } else { self.cumulative_areas[i - 1] };
+
+        // Stretch the part of `u` that picked this hittable back over [0, 1) to sample within it
+        let u = math::f_clamp((target - start) / (self.cumulative_areas[i] - start), 0.0, 1.0);
+        self.hittables()[i].sample_surface((u, v))
     }
 }
 
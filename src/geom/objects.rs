@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::Path;
 use std::sync::Arc;
@@ -13,7 +14,8 @@ use super::hit::{
 };
 use super::primitives::{ Plane, Triangle };
 
-use crate::loader::{ Loader, Polygon };
+use crate::gltf;
+use crate::loader::{ Loader, LoaderError, Obj, Polygon };
 use crate::material::Material;
 use crate::math;
 use crate::vec::{ Point3, Ray, Vec3 };
@@ -78,6 +80,14 @@ impl Prism {
 
         Prism { center, spanning_vecs, primitives }
     }
+
+    pub fn center(&self) -> &Point3 {
+        &self.center
+    }
+
+    pub fn spanning_vecs(&self) -> &(Vec3, Vec3, Vec3) {
+        &self.spanning_vecs
+    }
 }
 
 impl BoundedHittable for Prism {}
@@ -88,7 +98,15 @@ impl Hittable for Prism {
     }
 
     fn surface_area(&self) -> f64 {
-        self.primitives.hittables().iter().fold(0.0, |acc, plane| acc + plane.surface_area())
+        self.primitives.surface_area()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.primitives.is_emissive()
+    }
+
+    fn sample_surface(&self, uv: (f64, f64)) -> Option<Point3> {
+        self.primitives.sample_surface(uv)
     }
 }
 
@@ -145,6 +163,14 @@ impl Icosahedron {
             primitives: icosahedron_tris
         }
     }
+
+    pub fn center(&self) -> &Point3 {
+        &self.center
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius
+    }
 }
 
 impl BoundedHittable for Icosahedron {}
@@ -155,7 +181,15 @@ impl Hittable for Icosahedron {
     }
 
     fn surface_area(&self) -> f64 {
-        self.primitives.hittables().iter().fold(0.0, |acc, tri| acc + tri.surface_area())
+        self.primitives.surface_area()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.primitives.is_emissive()
+    }
+
+    fn sample_surface(&self, uv: (f64, f64)) -> Option<Point3> {
+        self.primitives.sample_surface(uv)
     }
 }
 
@@ -166,64 +200,113 @@ impl Bounded for Icosahedron {
 }
 
 impl Object {
+    /// Loads a model from an .obj, .ply, .gltf or .glb file, centers it on `center`, scales it and
+    /// then rotates it about its center
     pub fn new(center: Point3, scale: f64, rotations: Vec<(f64, Vec3)>,
         filepath: &Path, material: Arc<dyn Material>)
-        -> Object
+        -> Result<Object, LoaderError>
     {
-        match filepath.extension().and_then(OsStr::to_str) {
-            Some("obj") => {
-                let obj = Loader::load_obj(filepath).unwrap();
-                let mut tris: Vec<Triangle> = vec![];
-                let mut primitives: HittableRefs = vec![];
-                for face in obj.indices {
-                    match face.0 {
-                        Polygon::Tri => {
-                            let a = &center + scale * (obj.vertices[face.1[0]].clone());
-                            let b = &center + scale * (obj.vertices[face.1[1]].clone());
-                            let c = &center + scale * (obj.vertices[face.1[2]].clone());
-
-                            tris.push(Triangle::new(
-                                (a.clone(), b.clone(), c.clone()), material.clone()
-                            ));
-                            primitives.push(Arc::new(Triangle::new(
-                                (a, b, c), material.clone()
-                            )));
-                        }
-                    }
+        let tris = match filepath.extension().and_then(OsStr::to_str) {
+            Some("obj") => Object::triangles(&Loader::load_obj(filepath)?, &material),
+            Some("ply") => Object::triangles(&Loader::load_ply(filepath)?, &material),
+            // A glTF scene's meshes stay where its nodes put them, and are moved together
+            Some("gltf") | Some("glb") => gltf::load(filepath, material)?.triangles,
+            extension => return Err(LoaderError::Format(extension.unwrap_or("").to_string()))
+        };
+
+        let mut adjusted_primitives: HittableRefs = vec![];
+        let corners: Vec<Point3> = tris.iter()
+            .flat_map(|tri| vec![tri.a().clone(), tri.b().clone(), tri.c().clone()])
+            .collect();
+        let bound_center = Vec3::O + AxisAlignedBoundingBox::union_from_points(&corners).center();
+        for tri in &tris {
+            let mut new_a = scale * (tri.a() - &bound_center);
+            let mut new_b = scale * (tri.b() - &bound_center);
+            let mut new_c = scale * (tri.c() - &bound_center);
+
+            for rotation in &rotations {
+                 new_a = new_a.rotate(rotation.0, &rotation.1);
+                 new_b = new_b.rotate(rotation.0, &rotation.1);
+                 new_c = new_c.rotate(rotation.0, &rotation.1);
+            }
+
+            new_a += &center;
+            new_b += &center;
+            new_c += &center;
+
+            let mut adjusted = Triangle::new((new_a, new_b, new_c), tri.material().clone()).with_uvs(*tri.uvs());
+            if let Some((normal_a, normal_b, normal_c)) = tri.normals() {
+                let (mut normal_a, mut normal_b, mut normal_c) =
+                    (normal_a.clone(), normal_b.clone(), normal_c.clone());
+                for rotation in &rotations {
+                    normal_a = normal_a.rotate(rotation.0, &rotation.1);
+                    normal_b = normal_b.rotate(rotation.0, &rotation.1);
+                    normal_c = normal_c.rotate(rotation.0, &rotation.1);
                 }
+                adjusted = adjusted.with_normals((normal_a, normal_b, normal_c));
+            }
+            if let Some(colors) = tri.colors() {
+                adjusted = adjusted.with_colors(colors.clone());
+            }
+            adjusted_primitives.push(Arc::new(adjusted));
+        }
 
-                let mut adjusted_primitives: HittableRefs = vec![];
-                let bound_center = Vec3::O + AxisAlignedBoundingBox::union_from_objs(&primitives).center();
-                for tri in &tris {
-                    let mut new_a = tri.a() - &bound_center;
-                    let mut new_b = tri.b() - &bound_center;
-                    let mut new_c = tri.c() - &bound_center;
-
-                    for rotation in &rotations {
-                         new_a = new_a.rotate(rotation.0, &rotation.1);
-                         new_b = new_b.rotate(rotation.0, &rotation.1);
-                         new_c = new_c.rotate(rotation.0, &rotation.1);
-                    }
-
-                    new_a += &center;
-                    new_b += &center;
-                    new_c += &center;
-
-                    adjusted_primitives.push(Arc::new(Triangle::new(
-                        (new_a, new_b, new_c), material.clone()
-                    )));
-                }
+        Ok(Object {
+            center,
+            primitives: HittableGroup::new(adjusted_primitives)
+        })
+    }
 
-                Object {
-                    center,
-                    primitives: HittableGroup::new(adjusted_primitives)
+    /// A model's faces as triangles, where the file put them
+    fn triangles(obj: &Obj, material: &Arc<dyn Material>) -> Vec<Triangle> {
+        // Faces without normals of their own are shaded smoothly with averaged ones
+        let vertex_normals =
+            if obj.faces.iter().all(|face| face.normals.is_some()) { vec![] } else { obj.vertex_normals() };
+        // Faces the model gives a material of its own use it instead of `material`
+        let materials: HashMap<&String, Arc<dyn Material>> =
+            obj.materials.iter().map(|(name, mtl)| (name, mtl.material())).collect();
+        let mut tris: Vec<Triangle> = vec![];
+        for face in &obj.faces {
+            match face.polygon {
+                Polygon::Tri => {
+                    let a = obj.vertices[face.vertices[0]].clone();
+                    let b = obj.vertices[face.vertices[1]].clone();
+                    let c = obj.vertices[face.vertices[2]].clone();
+
+                    let face_material = face.material.as_ref().and_then(|name| materials.get(name));
+                    let mut tri = Triangle::new((a, b, c), face_material.unwrap_or(material).clone());
+                    if let Some(texcoords) = &face.texcoords {
+                        tri = tri.with_uvs((
+                            obj.texcoords[texcoords[0]],
+                            obj.texcoords[texcoords[1]],
+                            obj.texcoords[texcoords[2]]
+                        ));
+                    }
+                    let (normals, indices) = match &face.normals {
+                        Some(indices) => (&obj.normals, indices),
+                        None => (&vertex_normals, &face.vertices)
+                    };
+                    tri = tri.with_normals((
+                        normals[indices[0]].clone(), normals[indices[1]].clone(), normals[indices[2]].clone()
+                    ));
+                    if !obj.colors.is_empty() {
+                        tri = tri.with_colors((
+                            obj.colors[face.vertices[0]].clone(),
+                            obj.colors[face.vertices[1]].clone(),
+                            obj.colors[face.vertices[2]].clone()
+                        ));
+                    }
+                    tris.push(tri);
                 }
-            },
-            Some(_) | None => todo!()
+            }
         }
-    }
 
+        tris
+    }
 
+    pub fn center(&self) -> &Point3 {
+        &self.center
+    }
 }
 
 impl BoundedHittable for Object {}
@@ -234,7 +317,15 @@ impl Hittable for Object {
     }
 
     fn surface_area(&self) -> f64 {
-        self.primitives.hittables().iter().fold(0.0, |acc, tri| acc + tri.surface_area())
+        self.primitives.surface_area()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.primitives.is_emissive()
+    }
+
+    fn sample_surface(&self, uv: (f64, f64)) -> Option<Point3> {
+        self.primitives.sample_surface(uv)
     }
 }
 
@@ -12,7 +12,7 @@ use super::hit::{
 
 use crate::math;
 use crate::material::Material;
-use crate::vec::{ Coord, Point3, Ray, Vec3 };
+use crate::vec::{ ColorRGB, Coord, Point3, Ray, Vec3 };
 
 #[derive(Debug)]
 pub struct Sphere {
@@ -25,6 +25,32 @@ impl Sphere {
     pub fn new(center: Point3, radius: f64, material: Arc<dyn Material>) -> Sphere {
         Sphere { center, radius, material }
     }
+
+    /// The texture coordinates of a point on the unit sphere, with u going around from the -x axis
+    /// and v going up from the bottom
+    fn uv(unit: &Vec3) -> (f64, f64) {
+        let theta = (-unit[Coord::Y]).clamp(-1.0, 1.0).acos();
+        let phi = (-unit[Coord::Z]).atan2(unit[Coord::X]) + consts::PI;
+        (phi / (2.0 * consts::PI), theta / consts::PI)
+    }
+
+    /// How a point on the sphere moves with its uv coordinates, given its direction from the center
+    fn derivatives(&self, unit: &Vec3) -> (Vec3, Vec3) {
+        let (x, y, z) = (unit[Coord::X], unit[Coord::Y], unit[Coord::Z]);
+        // Longitude lines all meet at the poles, so keep away from dividing by zero there
+        let sin_theta = (x * x + z * z).sqrt().max(1e-9);
+        let dpdu = 2.0 * consts::PI * self.radius * Vec3::new(z, 0.0, -x);
+        let dpdv = consts::PI * self.radius * Vec3::new(-x * y / sin_theta, sin_theta, -z * y / sin_theta);
+        (dpdu, dpdv)
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+        let normal = (ray.at(t) - &self.center) * (1.0/self.radius);
+        let outer = ray.dir.dot(&normal) < 0.0;
+        let uv = Sphere::uv(&normal);
+        let (dpdu, dpdv) = self.derivatives(&normal);
+        Hit::new(ray.at(t), normal, t, outer, self.material.clone()).with_surface(uv, dpdu, dpdv)
+    }
 }
 
 impl BoundedHittable for Sphere {}
@@ -44,13 +70,9 @@ impl Hittable for Sphere {
         let t2 = -center_dot_self + root;
 
         if t1 < t_max && t1 > t_min {
-            let normal = (ray.at(t1) - &self.center) * (1.0/self.radius);
-            let outer = ray.dir.dot(&normal) < 0.0;
-            return Some(Hit::new(ray.at(t1), normal, t1, outer, self.material.clone()));
+            return Some(self.hit_at(ray, t1));
         } else if t2 < t_max && t2 > t_min {
-            let normal = (ray.at(t2) - &self.center) * (1.0/self.radius);
-            let outer = ray.dir.dot(&normal) < 0.0;
-            return Some(Hit::new(ray.at(t2), normal, t2, outer, self.material.clone()));
+            return Some(self.hit_at(ray, t2));
         }
 
         None
@@ -59,6 +81,14 @@ impl Hittable for Sphere {
     fn surface_area(&self) -> f64 {
         4.0 * consts::PI * self.radius.powi(2)
     }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample_surface(&self, (u, v): (f64, f64)) -> Option<Point3> {
+        Some(&self.center + self.radius * Vec3::on_unit_sphere(u, v))
+    }
 }
 
 impl Bounded for Sphere {
@@ -107,21 +137,36 @@ impl Hittable for Plane {
 
         let t = ((&self.center - &ray.origin).dot(&normal)) / (ray.dir.dot(&normal));
         let center_to_point = ray.at(t) - &self.center;
-        let ctp_components = center_to_point.projections(&plane_i, &plane_j);
+        let ctp_components = center_to_point.projections(plane_i, plane_j);
 
         if t < t_max && t > t_min
             && math::f_leq(ctp_components.0.norm(), plane_i.norm())
             && math::f_leq(ctp_components.1.norm(), plane_j.norm())
         {
             let outer = ray.dir.dot(&normal) < 0.0;
-            Some(Hit::new(ray.at(t), normal, t, outer, self.material.clone()))
+            // The spanning vectors reach from the center to the edges, which are at 0 and 1
+            let uv = (
+                0.5 + 0.5 * center_to_point.dot(plane_i) / plane_i.dot(plane_i),
+                0.5 + 0.5 * center_to_point.dot(plane_j) / plane_j.dot(plane_j)
+            );
+            let (dpdu, dpdv) = (2.0 * plane_i, 2.0 * plane_j);
+            Some(Hit::new(ray.at(t), normal, t, outer, self.material.clone()).with_surface(uv, dpdu, dpdv))
         } else {
             None
         }
     }
 
     fn surface_area(&self) -> f64 {
-        2.0 * self.spanning_vecs.0.norm() + 2.0 * self.spanning_vecs.1.norm()
+        // The spanning vectors reach from the center to the edges, so the sides are twice as long
+        4.0 * self.spanning_vecs.0.cross(&self.spanning_vecs.1).norm()
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample_surface(&self, (u, v): (f64, f64)) -> Option<Point3> {
+        Some(&self.center + (2.0 * u - 1.0) * &self.spanning_vecs.0 + (2.0 * v - 1.0) * &self.spanning_vecs.1)
     }
 }
 
@@ -172,20 +217,91 @@ impl Bounded for Plane {
             }
         }
 
-        let bound = AxisAlignedBoundingBox::new(ftr_corner, bbl_corner);
-        bound
+        AxisAlignedBoundingBox::new(ftr_corner, bbl_corner)
     }
 }
 
+/// Texture coordinates for each of a triangle's corners
+pub type CornerUvs = ((f64, f64), (f64, f64), (f64, f64));
+
 #[derive(Debug)]
 pub struct Triangle {
     corners: (Point3, Point3, Point3),
+    /// The corners' texture coordinates, which are interpolated across the face
+    uvs: CornerUvs,
+    /// Normals at the corners, which are interpolated across the face to shade it smoothly
+    normals: Option<(Vec3, Vec3, Vec3)>,
+    /// Colors at the corners, which are blended across the face
+    colors: Option<(ColorRGB, ColorRGB, ColorRGB)>,
     material: Arc<dyn Material>
 }
 
 impl Triangle {
     pub fn new(corners: (Point3, Point3, Point3), material: Arc<dyn Material>) -> Triangle {
-        Triangle { corners, material }
+        Triangle { corners, material, uvs: ((0.0, 0.0), (1.0, 0.0), (0.0, 1.0)), normals: None, colors: None }
+    }
+
+    pub fn with_uvs(mut self, uvs: CornerUvs) -> Triangle {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn uvs(&self) -> &CornerUvs {
+        &self.uvs
+    }
+
+    pub fn with_normals(mut self, normals: (Vec3, Vec3, Vec3)) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn normals(&self) -> Option<&(Vec3, Vec3, Vec3)> {
+        self.normals.as_ref()
+    }
+
+    pub fn with_colors(mut self, colors: (ColorRGB, ColorRGB, ColorRGB)) -> Triangle {
+        self.colors = Some(colors);
+        self
+    }
+
+    pub fn colors(&self) -> Option<&(ColorRGB, ColorRGB, ColorRGB)> {
+        self.colors.as_ref()
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+
+    /// How much of each corner goes into a point on the face
+    fn barycentric(&self, point: &Point3) -> (f64, f64, f64) {
+        let a = &self.corners.0;
+        let ab = &self.corners.1 - a;
+        let ac = &self.corners.2 - a;
+        let ap = point - a;
+        let normal = ab.cross(&ac);
+        let area = normal.dot(&normal);
+
+        let weight_b = ap.cross(&ac).dot(&normal) / area;
+        let weight_c = ab.cross(&ap).dot(&normal) / area;
+        (1.0 - weight_b - weight_c, weight_b, weight_c)
+    }
+
+    /// How a point on the face moves with its uv coordinates, or nothing if the corners' uvs don't
+    /// span any area
+    fn derivatives(&self) -> (Vec3, Vec3) {
+        let (uv_a, uv_b, uv_c) = &self.uvs;
+        let (du_ac, dv_ac) = (uv_a.0 - uv_c.0, uv_a.1 - uv_c.1);
+        let (du_bc, dv_bc) = (uv_b.0 - uv_c.0, uv_b.1 - uv_c.1);
+        let det = du_ac * dv_bc - dv_ac * du_bc;
+        if det.abs() < 1e-12 {
+            return (Vec3::O, Vec3::O);
+        }
+
+        let dp_ac = &self.corners.0 - &self.corners.2;
+        let dp_bc = &self.corners.1 - &self.corners.2;
+        let dpdu = (dv_bc * &dp_ac - dv_ac * &dp_bc) * (1.0 / det);
+        let dpdv = (du_ac * &dp_bc - du_bc * &dp_ac) * (1.0 / det);
+        (dpdu, dpdv)
     }
 
     pub fn a(&self) -> &Point3 {
@@ -229,7 +345,22 @@ impl Hittable for Triangle {
 
         if t < t_max && t > t_min {
             let outer = ray.dir.dot(&normal) < 0.0;
-            Some(Hit::new(ray.at(t), normal, t, outer, self.material.clone()))
+            let (weight_a, weight_b, weight_c) = self.barycentric(&ray.at(t));
+            let (uv_a, uv_b, uv_c) = &self.uvs;
+            let uv = (
+                weight_a * uv_a.0 + weight_b * uv_b.0 + weight_c * uv_c.0,
+                weight_a * uv_a.1 + weight_b * uv_b.1 + weight_c * uv_c.1
+            );
+            let (dpdu, dpdv) = self.derivatives();
+            let mut hit = Hit::new(ray.at(t), normal, t, outer, self.material.clone()).with_surface(uv, dpdu, dpdv);
+            if let Some((color_a, color_b, color_c)) = &self.colors {
+                hit = hit.with_vertex_color(weight_a * color_a + weight_b * color_b + weight_c * color_c);
+            }
+            Some(match &self.normals {
+                Some((normal_a, normal_b, normal_c)) =>
+                    hit.with_shading_normal(weight_a * normal_a + weight_b * normal_b + weight_c * normal_c),
+                None => hit
+            })
         } else {
             None
         }
@@ -245,6 +376,18 @@ impl Hittable for Triangle {
 
         0.5 * ab.cross(&ac).norm()
     }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    fn sample_surface(&self, (u, v): (f64, f64)) -> Option<Point3> {
+        // Folding the square's barycentric coordinates with a square root keeps them uniform by area
+        let root = u.sqrt();
+        let (weight_a, weight_b) = (1.0 - root, v * root);
+        Some(weight_a * &self.corners.0 + weight_b * &self.corners.1
+            + (1.0 - weight_a - weight_b) * &self.corners.2)
+    }
 }
 
 impl Bounded for Triangle {
@@ -253,17 +396,25 @@ impl Bounded for Triangle {
         let b = &self.corners.1;
         let c = &self.corners.2;
 
-        let ftr_corner = Point3::new(
+        let mut ftr_corner = Point3::new(
             math::f_max_all(vec![a[Coord::X], b[Coord::X], c[Coord::X]]),
             math::f_max_all(vec![a[Coord::Y], b[Coord::Y], c[Coord::Y]]),
             math::f_max_all(vec![a[Coord::Z], b[Coord::Z], c[Coord::Z]])
         );
-        let bbl_corner = Point3::new(
+        let mut bbl_corner = Point3::new(
             math::f_min_all(vec![a[Coord::X], b[Coord::X], c[Coord::X]]),
             math::f_min_all(vec![a[Coord::Y], b[Coord::Y], c[Coord::Y]]),
             math::f_min_all(vec![a[Coord::Z], b[Coord::Z], c[Coord::Z]])
         );
 
+        // Add a little padding so ray intersection doesn't devolve for axis-aligned faces
+        for &coord in [ Coord::X, Coord::Y, Coord::Z ].iter() {
+            if math::f_eq(ftr_corner[coord], bbl_corner[coord]) {
+                ftr_corner[coord] += 0.01;
+                bbl_corner[coord] -= 0.01;
+            }
+        }
+
         AxisAlignedBoundingBox::new(
             ftr_corner, bbl_corner
         )
@@ -272,8 +423,9 @@ impl Bounded for Triangle {
 
 #[cfg(test)]
 mod tests {
+    use crate::geom::hit::HittableGroup;
     use crate::math::Rand;
-    use crate::material::DiffuseLambert;
+    use crate::material::{ DiffuseLambert, Emissive };
     use crate::vec::colors;
 
     use super::*;
@@ -283,10 +435,7 @@ mod tests {
         let mat_dif_white = DiffuseLambert::new(colors::WHITE);
         let sphere = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, Arc::new(mat_dif_white));
         let ray = Ray::new(&Vec3::O, &-Vec3::K);
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(sphere.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_some(),
             "Ray should have hit sphere but didn't.")
     }
@@ -296,10 +445,7 @@ mod tests {
         let mat_dif_white = DiffuseLambert::new(colors::WHITE);
         let sphere = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, Arc::new(mat_dif_white));
         let ray = Ray::new(&Vec3::O, &Vec3::J);
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(sphere.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_none(),
             "Ray shouldn't have hit sphere but did.")
     }
@@ -309,10 +455,7 @@ mod tests {
         let mat_dif_white = DiffuseLambert::new(colors::WHITE);
         let sphere = Sphere::new(Point3::new(0.0, 0.0, -0.3), 0.5, Arc::new(mat_dif_white));
         let ray = Ray::new(&Vec3::O, &-Vec3::K);
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(sphere.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_some(),
             "Ray should have hit sphere but didn't.")
     }
@@ -326,10 +469,7 @@ mod tests {
             Arc::new(mat_dif_white)
         );
         let ray = Ray::new(&Vec3::O, &Vec3::J);
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(plane.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_some(),
             "Ray should have hit plane but didn't.")
     }
@@ -343,10 +483,7 @@ mod tests {
             Arc::new(mat_dif_white)
         );
         let ray = Ray::new(&Vec3::O, &(Vec3::J + Vec3::I));
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(plane.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_none(),
             "Ray shouldn't have hit plane but did.")
     }
@@ -361,10 +498,7 @@ mod tests {
             Arc::new(mat_dif_white)
         );
         let ray = Ray::new(&Vec3::O, &-Vec3::K);
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(tri.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_some(),
             "Ray should have hit triangle but didn't.")
     }
@@ -379,11 +513,113 @@ mod tests {
             Arc::new(mat_dif_white)
         );
         let ray = Ray::new(&Vec3::O, &(Vec3::J - Vec3::K));
-        let mut rand = Rand {
-            dist: rand::distributions::Uniform::from(0.0..1.0),
-            rng: rand::thread_rng()
-        };
+        let mut rand = Rand::seeded(0);
         assert!(tri.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_none(),
             "Ray shouldn't have hit triangle but did.")
     }
+
+    #[test]
+    fn plane_surface_sampling() {
+        let plane = Plane::new(
+            Point3::new(1.0, 0.0, 0.0), (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 0.5)),
+            Arc::new(Emissive::new(colors::WHITE, 1.0))
+        );
+        assert!(math::f_eq(plane.surface_area(), 4.0));
+        assert!(plane.is_emissive());
+        assert_eq!(plane.sample_surface((0.0, 0.0)), Some(Point3::new(-1.0, 0.0, -0.5)));
+        assert_eq!(plane.sample_surface((0.75, 0.5)), Some(Point3::new(2.0, 0.0, 0.0)));
+
+        let group = HittableGroup::new(vec![
+            Arc::new(plane),
+            Arc::new(Sphere::new(Point3::new(0.0, 5.0, 0.0), 1.0, Arc::new(DiffuseLambert::new(colors::WHITE))))
+        ]);
+        assert!(math::f_eq(group.surface_area(), 4.0 + 4.0 * consts::PI));
+        assert_eq!(group.emitters().hittables().len(), 1);
+
+        // Points are only ever on one of the group's surfaces
+        for i in 0..100 {
+            let point = group.sample_surface((f64::from(i) / 100.0, 0.3)).unwrap();
+            let on_sphere = math::f_eq((&point - Point3::new(0.0, 5.0, 0.0)).norm(), 1.0);
+            assert!(on_sphere || math::f_eq(point[Coord::Y], 0.0), "{} isn't on a surface", point);
+        }
+    }
+
+    #[test]
+    fn surface_coordinates() {
+        let mut rand = Rand::seeded(0);
+        let sphere = Sphere::new(Point3::O, 2.0, Arc::new(DiffuseLambert::new(colors::WHITE)));
+        let hit = sphere.is_hit(&Ray::new(&Point3::new(0.0, 0.0, 5.0), &-Vec3::K), 0.0, f64::INFINITY, &mut rand)
+            .unwrap();
+        assert!(math::f_eq(hit.uv.0, 0.25) && math::f_eq(hit.uv.1, 0.5));
+
+        let plane = Plane::new(
+            Point3::O, (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+            Arc::new(DiffuseLambert::new(colors::WHITE))
+        );
+        let hit = plane.is_hit(&Ray::new(&Point3::new(1.0, 1.0, -0.5), &-Vec3::J), 0.0, f64::INFINITY, &mut rand)
+            .unwrap();
+        assert!(math::f_eq(hit.uv.0, 0.75) && math::f_eq(hit.uv.1, 0.25));
+        assert_eq!(hit.dpdu, Vec3::new(4.0, 0.0, 0.0));
+        assert_eq!(hit.frame().to_local(&Vec3::I), Vec3::I);
+
+        let tri = Triangle::new(
+            (Point3::O, Point3::new(2.0, 0.0, 0.0), Point3::new(0.0, 2.0, 0.0)),
+            Arc::new(DiffuseLambert::new(colors::WHITE))
+        ).with_uvs(((0.0, 0.0), (0.0, 1.0), (1.0, 0.0)));
+        let hit = tri.is_hit(&Ray::new(&Point3::new(0.5, 1.0, 1.0), &-Vec3::K), 0.0, f64::INFINITY, &mut rand)
+            .unwrap();
+        assert!(math::f_eq(hit.uv.0, 0.5) && math::f_eq(hit.uv.1, 0.25));
+        assert_eq!(hit.dpdu, Vec3::new(0.0, 2.0, 0.0));
+        assert_eq!(hit.dpdv, Vec3::new(2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn axis_aligned_triangles_are_found() {
+        // Flat bounding boxes would have no room for a ray to pass through
+        let tri = Triangle::new(
+            (Point3::O, Point3::new(0.0, 0.0, 1.0), Point3::new(1.0, 0.0, 0.0)),
+            Arc::new(DiffuseLambert::new(colors::WHITE))
+        );
+        let group = HittableGroup::new(vec![Arc::new(tri)]);
+        let ray = Ray::new(&Point3::new(0.2, 1.0, 0.2), &-Vec3::J);
+        let mut rand = Rand::seeded(0);
+        assert!(group.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).is_some());
+    }
+
+    #[test]
+    fn smooth_triangles() {
+        let mut rand = Rand::seeded(0);
+        let corners = (Point3::O, Point3::new(2.0, 0.0, 0.0), Point3::new(0.0, 2.0, 0.0));
+        let tri = Triangle::new(corners, Arc::new(DiffuseLambert::new(colors::WHITE)))
+            .with_normals((Vec3::K, Vec3::new(1.0, 0.0, 1.0).unit(), Vec3::new(0.0, 1.0, 1.0).unit()));
+        let shade = |origin: Point3, dir: Vec3, rand: &mut Rand| {
+            tri.is_hit(&Ray::new(&origin, &dir), 0.0, f64::INFINITY, rand).unwrap()
+        };
+
+        let hit = shade(Point3::new(0.0, 0.0, 1.0), -Vec3::K, &mut rand);
+        assert_eq!(hit.normal, Vec3::K);
+        let hit = shade(Point3::new(1.0, 0.0, 1.0), -Vec3::K, &mut rand);
+        assert_eq!(hit.normal, (Vec3::K + Vec3::new(1.0, 0.0, 1.0).unit()).unit());
+        assert_eq!(hit.geometric_normal, Vec3::K);
+
+        // From behind, both normals turn to face the ray
+        let hit = shade(Point3::new(1.0, 0.0, -1.0), Vec3::K, &mut rand);
+        assert_eq!(hit.normal, -(Vec3::K + Vec3::new(1.0, 0.0, 1.0).unit()).unit());
+        assert_eq!(hit.geometric_normal, -Vec3::K);
+    }
+
+    #[test]
+    fn sphere_derivatives_follow_uv() {
+        // Stepping along dpdu and dpdv should move the uv coordinates by the same small amount
+        let sphere = Sphere::new(Point3::new(1.0, -1.0, 0.5), 1.5, Arc::new(DiffuseLambert::new(colors::WHITE)));
+        let unit = Vec3::new(0.3, 0.4, -0.5).unit();
+        let (dpdu, dpdv) = sphere.derivatives(&unit);
+        let (u, v) = Sphere::uv(&unit);
+        let step = 1e-5;
+
+        let moved_u = Sphere::uv(&(&unit + step * (1.0 / 1.5) * &dpdu).unit());
+        assert!((moved_u.0 - u - step).abs() < 1e-8 && (moved_u.1 - v).abs() < 1e-8);
+        let moved_v = Sphere::uv(&(&unit + step * (1.0 / 1.5) * &dpdv).unit());
+        assert!((moved_v.1 - v - step).abs() < 1e-8 && (moved_v.0 - u).abs() < 1e-8);
+    }
 }
@@ -0,0 +1,325 @@
+use std::error::Error;
+use std::fmt;
+
+/// A JSON value. Objects keep their members in the order they were written.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>)
+}
+
+/// Something wrong with a JSON document, at a line and column
+#[derive(Debug)]
+pub struct JsonError {
+    pub line: usize,
+    pub column: usize,
+    pub msg: String
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.msg)
+    }
+}
+
+impl Error for JsonError {}
+
+impl Json {
+    /// The member of an object called `key`, if this is an object and has one
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            _ => None
+        }
+    }
+
+    /// The value as an index or count, if it's a whole number that isn't negative
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Json::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as usize),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None
+        }
+    }
+}
+
+/// How deeply arrays and objects can be nested, which keeps a malicious file from overflowing the
+/// stack
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    source: &'a [u8],
+    index: usize,
+    /// The number of arrays and objects the parser is inside
+    depth: usize
+}
+
+impl<'a> Parser<'a> {
+    fn error<T>(&self, msg: &str) -> Result<T, JsonError> {
+        let before = &self.source[..self.index.min(self.source.len())];
+        let line = before.iter().filter(|&&c| c == b'\n').count() + 1;
+        let column = before.iter().rev().take_while(|&&c| c != b'\n').count() + 1;
+        Err(JsonError { line, column, msg: msg.to_string() })
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.source.get(self.index).is_some_and(|c| b" \t\r\n".contains(c)) {
+            self.index += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), JsonError> {
+        self.skip_whitespace();
+        if self.source.get(self.index) == Some(&c) {
+            self.index += 1;
+            Ok(())
+        } else {
+            self.error(&format!("expected '{}'", c as char))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.source.get(self.index) {
+            Some(b'{') | Some(b'[') => {
+                if self.depth == MAX_DEPTH {
+                    return self.error("arrays and objects are nested too deeply");
+                }
+                self.depth += 1;
+                let value = if self.source[self.index] == b'{' { self.object() } else { self.array() };
+                self.depth -= 1;
+                value
+            },
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(b't') => self.keyword("true", Json::Bool(true)),
+            Some(b'f') => self.keyword("false", Json::Bool(false)),
+            Some(b'n') => self.keyword("null", Json::Null),
+            Some(_) => self.error("expected a value"),
+            None => self.error("unexpected end of file")
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json, JsonError> {
+        if self.source[self.index..].starts_with(word.as_bytes()) {
+            self.index += word.len();
+            Ok(value)
+        } else {
+            self.error("expected a value")
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.index += 1;
+        let mut members = vec![];
+        self.skip_whitespace();
+        if self.source.get(self.index) == Some(&b'}') {
+            self.index += 1;
+            return Ok(Json::Object(members));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.source.get(self.index) != Some(&b'"') {
+                return self.error("expected a member name");
+            }
+            let name = self.string()?;
+            self.expect(b':')?;
+            members.push((name, self.value()?));
+
+            self.skip_whitespace();
+            match self.source.get(self.index) {
+                Some(b',') => self.index += 1,
+                Some(b'}') => {
+                    self.index += 1;
+                    return Ok(Json::Object(members));
+                },
+                _ => return self.error("expected ',' or '}'")
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.index += 1;
+        let mut values = vec![];
+        self.skip_whitespace();
+        if self.source.get(self.index) == Some(&b']') {
+            self.index += 1;
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+
+            self.skip_whitespace();
+            match self.source.get(self.index) {
+                Some(b',') => self.index += 1,
+                Some(b']') => {
+                    self.index += 1;
+                    return Ok(Json::Array(values));
+                },
+                _ => return self.error("expected ',' or ']'")
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.index;
+        while self.source.get(self.index).is_some_and(|c| b"+-.eE0123456789".contains(c)) {
+            self.index += 1;
+        }
+
+        let text = std::str::from_utf8(&self.source[start..self.index]).unwrap_or("");
+        match text.parse() {
+            Ok(n) => Ok(Json::Number(n)),
+            Err(_) => {
+                self.index = start;
+                self.error(&format!("invalid number '{}'", text))
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.index += 1;
+        let mut bytes = vec![];
+        loop {
+            match self.source.get(self.index) {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    self.index += 1;
+                    let c = match self.source.get(self.index) {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return self.error("invalid escape")
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                },
+                Some(&c) if c < 0x20 => return self.error("control character in string"),
+                Some(&c) => bytes.push(c),
+                None => return self.error("unterminated string")
+            }
+            self.index += 1;
+        }
+        self.index += 1;
+
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(s),
+            Err(_) => self.error("string is not UTF-8")
+        }
+    }
+
+    /// Reads the digits of a `\u` escape, and the low half of a surrogate pair after it, leaving
+    /// the index on the last digit
+    fn unicode_escape(&mut self) -> Result<char, JsonError> {
+        let code = match self.hex() {
+            Some(high @ 0xD800..=0xDBFF) => {
+                self.index += 2;
+                if self.source.get(self.index - 1..=self.index) != Some(b"\\u") {
+                    return self.error("unpaired surrogate");
+                }
+                match self.hex() {
+                    Some(low @ 0xDC00..=0xDFFF) => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                    _ => return self.error("unpaired surrogate")
+                }
+            },
+            Some(code) => code,
+            None => return self.error("invalid unicode escape")
+        };
+
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.error("unpaired surrogate")
+        }
+    }
+
+    /// The four hex digits after the index
+    fn hex(&mut self) -> Option<u32> {
+        let digits = self.source.get(self.index + 1..self.index + 5)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        self.index += 4;
+        digits
+    }
+}
+
+/// Parses a whole JSON document
+pub fn parse(source: &[u8]) -> Result<Json, JsonError> {
+    let mut parser = Parser { source, index: 0, depth: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.index < source.len() {
+        return parser.error("unexpected data after the document");
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        let json = parse(r#"
+            { "a": [1, -2.5e2, true, false, null], "b": { "c": "tab\tquote\" é \u00e9 \ud83d\ude00" }, "d": [] }
+        "#.as_bytes()).unwrap();
+
+        let a = json.get("a").and_then(Json::as_array).unwrap();
+        assert_eq!(a[0].as_usize(), Some(1));
+        assert_eq!(a[1].as_f64(), Some(-250.0));
+        assert_eq!(a[1].as_usize(), None);
+        assert_eq!(&a[2..], &[Json::Bool(true), Json::Bool(false), Json::Null]);
+        assert_eq!(json.get("b").and_then(|b| b.get("c")).and_then(Json::as_str), Some("tab\tquote\" é é 😀"));
+        assert_eq!(json.get("d"), Some(&Json::Array(vec![])));
+        assert_eq!(json.get("e"), None);
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let at = |source: &[u8]| {
+            let err = parse(source).unwrap_err();
+            (err.line, err.column)
+        };
+        assert_eq!(at(b"{\n  \"a\": [1, 2,]\n}"), (2, 14));
+        assert_eq!(at(b"{ \"a\" 1 }"), (1, 7));
+        assert_eq!(at(b"[\"unterminated"), (1, 15));
+        assert_eq!(at(b"[1] 2"), (1, 5));
+        assert_eq!(at(b"[1.2.3]"), (1, 2));
+
+        let deep = |depth: usize| parse(("[".repeat(depth) + &"]".repeat(depth)).as_bytes());
+        assert!(deep(MAX_DEPTH).is_ok());
+        assert_eq!(deep(100_000).unwrap_err().column, MAX_DEPTH + 1);
+    }
+}
@@ -0,0 +1,955 @@
+use std::collections::{ HashMap, HashSet };
+use std::convert;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+
+use crate::geom::primitives::Triangle;
+use crate::material::{ Bumped, Emissive, Material, NormalDetail, Principled, PrincipledParams };
+use crate::texture::{ Channel, Image, TextureError, TextureRef, Tint };
+use crate::vec::{ colors, Color, ColorRGB, Coord, Point3, Vec3 };
+
+pub mod json;
+use self::json::{ Json, JsonError };
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(io::Error),
+    Json(JsonError),
+    /// Something the file gets wrong, or leaves out
+    Format(String),
+    /// Something the file may do, but that we can't load
+    Unsupported(String),
+    Texture(TextureError)
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            GltfError::Io(err) =>
+                write!(f, "Could not load glTF file: {}", err),
+            GltfError::Json(err) =>
+                write!(f, "Could not parse glTF file at {}", err),
+            GltfError::Format(msg) =>
+                write!(f, "Could not read glTF file: {}", msg),
+            GltfError::Unsupported(msg) =>
+                write!(f, "Unsupported glTF file: {}", msg),
+            GltfError::Texture(err) =>
+                write!(f, "Could not load texture: {}", err)
+        }
+    }
+}
+
+impl Error for GltfError {}
+
+impl convert::From<io::Error> for GltfError {
+    fn from(err: io::Error) -> Self {
+        GltfError::Io(err)
+    }
+}
+
+impl convert::From<JsonError> for GltfError {
+    fn from(err: JsonError) -> Self {
+        GltfError::Json(err)
+    }
+}
+
+impl convert::From<TextureError> for GltfError {
+    fn from(err: TextureError) -> Self {
+        GltfError::Texture(err)
+    }
+}
+
+fn format_error<T>(msg: String) -> Result<T, GltfError> {
+    Err(GltfError::Format(msg))
+}
+
+/// A perspective camera placed in a glTF scene
+#[derive(Clone, Debug)]
+pub struct GltfCamera {
+    pub location: Point3,
+    /// A point a unit in front of the camera
+    pub look_at: Point3,
+    pub up: Vec3,
+    /// The vertical field of view, in degrees
+    pub yfov_deg: f64,
+    /// The width of the image over its height, if the file gives one
+    pub aspect_ratio: Option<f64>
+}
+
+/// A glTF scene: its meshes, split into triangles and placed in the world by the nodes they hang
+/// from, and its cameras
+pub struct Gltf {
+    pub triangles: Vec<Triangle>,
+    pub cameras: Vec<GltfCamera>
+}
+
+/// The extensions whose parameters are read; files that require any others can't be loaded
+const EXTENSIONS: [&str; 3] = ["KHR_materials_emissive_strength", "KHR_materials_ior", "KHR_materials_transmission"];
+
+/// The magic number a binary .glb file starts with, and the types of its chunks
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON: u32 = 0x4E4F_534A;
+const GLB_BIN: u32 = 0x004E_4942;
+
+/// The most elements an accessor without a buffer view can have, so that a file can't ask for
+/// more zeros than fit in memory
+const MAX_ZEROED_ELEMENTS: usize = 1 << 24;
+
+/// An affine transform, by rows
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0]
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.0; 4]; 4];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+fn transform_point(m: &Matrix, p: &Point3) -> Point3 {
+    transform_dir(m, p) + Vec3::new(m[0][3], m[1][3], m[2][3])
+}
+
+fn transform_dir(m: &Matrix, v: &Vec3) -> Vec3 {
+    let (x, y, z) = (v[Coord::X], v[Coord::Y], v[Coord::Z]);
+    Vec3::new(
+        m[0][0] * x + m[0][1] * y + m[0][2] * z,
+        m[1][0] * x + m[1][1] * y + m[1][2] * z,
+        m[2][0] * x + m[2][1] * y + m[2][2] * z
+    )
+}
+
+/// Normals go through the inverse transpose of a transform, which is its cofactor matrix over its
+/// determinant, so that they stay perpendicular to surfaces that are scaled unevenly
+fn transform_normal(m: &Matrix, n: &Vec3) -> Vec3 {
+    let cofactor = |i: usize, j: usize| {
+        let (i0, i1) = ((i + 1) % 3, (i + 2) % 3);
+        let (j0, j1) = ((j + 1) % 3, (j + 2) % 3);
+        m[i0][j0] * m[i1][j1] - m[i0][j1] * m[i1][j0]
+    };
+    let (x, y, z) = (n[Coord::X], n[Coord::Y], n[Coord::Z]);
+    let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+    let normal = Vec3::new(
+        cofactor(0, 0) * x + cofactor(0, 1) * y + cofactor(0, 2) * z,
+        cofactor(1, 0) * x + cofactor(1, 1) * y + cofactor(1, 2) * z,
+        cofactor(2, 0) * x + cofactor(2, 1) * y + cofactor(2, 2) * z
+    );
+    normalize(det.signum() * normal)
+}
+
+/// Scales a vector to unit length, however short it is
+fn normalize(v: Vec3) -> Vec3 {
+    let norm = v.norm();
+    if norm > 0.0 { (1.0 / norm) * v } else { v }
+}
+
+/// Decodes standard base64, as data URIs use
+fn base64(data: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in data.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None
+        };
+        bits = (bits << 6) | u32::from(value);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// Undoes the percent-encoding of a relative URI
+fn uri_path(dir: &Path, uri: &str) -> PathBuf {
+    let mut bytes = vec![];
+    let mut rest = uri.as_bytes();
+    while let Some((&c, tail)) = rest.split_first() {
+        let escaped = tail.get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (c, escaped) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            },
+            _ => {
+                bytes.push(c);
+                rest = tail;
+            }
+        }
+    }
+    dir.join(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+}
+
+/// Splits a .glb file into its JSON and, if it has one, its binary buffer
+fn glb_chunks(bytes: &[u8]) -> Result<(Json, Option<Vec<u8>>), GltfError> {
+    match read_u32(bytes, 4) {
+        Some(2) => {},
+        Some(version) => return Err(GltfError::Unsupported(format!("binary glTF version {}", version))),
+        None => return format_error("the file ends early".to_string())
+    }
+
+    let length = (read_u32(bytes, 8).unwrap_or(0) as usize).min(bytes.len());
+    let (mut json, mut bin) = (None, None);
+    let mut offset = 12;
+    while offset + 8 <= length {
+        let chunk_length = read_u32(bytes, offset).unwrap_or(0) as usize;
+        let chunk_type = read_u32(bytes, offset + 4).unwrap_or(0);
+        let data = match bytes.get(offset + 8..offset + 8 + chunk_length) {
+            Some(data) => data,
+            None => return format_error(format!("the chunk at byte {} ends early", offset))
+        };
+        match chunk_type {
+            GLB_JSON if json.is_none() => json = Some(json::parse(data)?),
+            GLB_BIN if bin.is_none() => bin = Some(data.to_vec()),
+            // Other chunks are for extensions, and may be skipped
+            _ => {}
+        }
+        offset += 8 + chunk_length;
+    }
+
+    match json {
+        Some(json) => Ok((json, bin)),
+        None => format_error("no JSON chunk".to_string())
+    }
+}
+
+/// The items of one of the document's top-level arrays, e.g. "meshes"
+fn items<'j>(json: &'j Json, key: &str) -> &'j [Json] {
+    json.get(key).and_then(Json::as_array).unwrap_or(&[])
+}
+
+/// The `index`th of the document's `key`, e.g. its third accessor
+fn item<'j>(json: &'j Json, key: &str, index: usize) -> Result<&'j Json, GltfError> {
+    match items(json, key).get(index) {
+        Some(item) => Ok(item),
+        None => format_error(format!("there is no {} {}", key, index))
+    }
+}
+
+/// An index into one of the document's arrays, if `json` has one
+fn index(json: &Json, key: &str) -> Result<Option<usize>, GltfError> {
+    match json.get(key) {
+        None => Ok(None),
+        Some(value) => match value.as_usize() {
+            Some(index) => Ok(Some(index)),
+            None => format_error(format!("'{}' must be an index", key))
+        }
+    }
+}
+
+fn number_or(json: &Json, key: &str, default: f64) -> Result<f64, GltfError> {
+    match json.get(key) {
+        None => Ok(default),
+        Some(value) => match value.as_f64() {
+            Some(n) => Ok(n),
+            None => format_error(format!("'{}' must be a number", key))
+        }
+    }
+}
+
+/// A fixed number of numbers, e.g. a color or a translation
+fn numbers_or<const N: usize>(json: &Json, key: &str, default: [f64; N]) -> Result<[f64; N], GltfError> {
+    let values = match json.get(key) {
+        None => return Ok(default),
+        Some(value) => value.as_array().unwrap_or(&[])
+    };
+    let mut numbers = default;
+    if values.len() != N {
+        return format_error(format!("'{}' must have {} numbers", key, N));
+    }
+    for (number, value) in numbers.iter_mut().zip(values) {
+        *number = match value.as_f64() {
+            Some(n) => n,
+            None => return format_error(format!("'{}' must have {} numbers", key, N))
+        };
+    }
+    Ok(numbers)
+}
+
+/// The parameters of one of the extensions a material can have
+fn extension<'j>(json: &'j Json, name: &str) -> Option<&'j Json> {
+    json.get("extensions").and_then(|extensions| extensions.get(name))
+}
+
+/// A glTF document, with its buffers loaded, being turned into triangles
+struct Document<'a> {
+    json: Json,
+    dir: &'a Path,
+    buffers: Vec<Vec<u8>>,
+    default_material: Arc<dyn Material>,
+    materials: Vec<Arc<dyn Material>>,
+    /// Images decoded so far, by index and whether they're sRGB-encoded. Ones that can't be
+    /// decoded are skipped, and are only warned about once.
+    images: HashMap<(usize, bool), Option<TextureRef<ColorRGB>>>,
+    triangles: Vec<Triangle>,
+    cameras: Vec<GltfCamera>
+}
+
+impl<'a> Document<'a> {
+    fn new(json: Json, dir: &'a Path, bin: Option<Vec<u8>>, default_material: Arc<dyn Material>)
+        -> Result<Document<'a>, GltfError>
+    {
+        let version = json.get("asset").and_then(|asset| asset.get("version")).and_then(Json::as_str);
+        match version {
+            Some(version) if version.starts_with("2.") => {},
+            Some(version) => return Err(GltfError::Unsupported(format!("version {}", version))),
+            None => return format_error("no asset version".to_string())
+        }
+        for required in items(&json, "extensionsRequired") {
+            let name = required.as_str().unwrap_or("");
+            if !EXTENSIONS.contains(&name) {
+                return Err(GltfError::Unsupported(format!("requires the extension {}", name)));
+            }
+        }
+
+        let mut bin = bin;
+        let mut buffers = vec![];
+        for (i, buffer) in items(&json, "buffers").iter().enumerate() {
+            let data = match buffer.get("uri").and_then(Json::as_str) {
+                Some(uri) => Document::uri(dir, uri)?,
+                None if i == 0 => match bin.take() {
+                    Some(data) => data,
+                    None => return format_error("buffer 0 has no uri, and there's no binary chunk".to_string())
+                },
+                None => return format_error(format!("buffer {} has no uri", i))
+            };
+            let length = index(buffer, "byteLength")?.unwrap_or(0);
+            if data.len() < length {
+                return format_error(format!("buffer {} has {} bytes, not {}", i, data.len(), length));
+            }
+            buffers.push(data);
+        }
+
+        Ok(Document {
+            json, dir, buffers, default_material,
+            materials: vec![],
+            images: HashMap::new(),
+            triangles: vec![],
+            cameras: vec![]
+        })
+    }
+
+    /// The bytes a URI points to: a base64 data URI, or a file relative to the document
+    fn uri(dir: &Path, uri: &str) -> Result<Vec<u8>, GltfError> {
+        if let Some(data) = uri.strip_prefix("data:") {
+            return match data.split_once(";base64,") {
+                Some((_, encoded)) => match base64(encoded) {
+                    Some(bytes) => Ok(bytes),
+                    None => format_error("invalid base64 in a data URI".to_string())
+                },
+                None => Err(GltfError::Unsupported("data URIs that aren't base64".to_string()))
+            };
+        }
+
+        let path = uri_path(dir, uri);
+        fs::read(&path).map_err(|err| GltfError::Io(io::Error::new(err.kind(), format!("{}: {}", path.display(), err))))
+    }
+
+    /// The bytes of a buffer view
+    fn view(&self, index: usize) -> Result<(&[u8], Option<usize>), GltfError> {
+        let view = item(&self.json, "bufferViews", index)?;
+        let buffer = match index_of(view, "buffer")? {
+            buffer if buffer < self.buffers.len() => &self.buffers[buffer],
+            buffer => return format_error(format!("there is no buffers {}", buffer))
+        };
+        let offset = self::index(view, "byteOffset")?.unwrap_or(0);
+        let length = index_of(view, "byteLength")?;
+        match offset.checked_add(length).and_then(|end| buffer.get(offset..end)) {
+            Some(bytes) => Ok((bytes, self::index(view, "byteStride")?)),
+            None => format_error(format!("buffer view {} runs past the end of its buffer", index))
+        }
+    }
+
+    /// Reads an accessor's elements, `width` numbers at a time, with normalized integers brought
+    /// into [0, 1] or [-1, 1]
+    fn accessor(&self, index: usize, width: usize) -> Result<Vec<f64>, GltfError> {
+        let accessor = item(&self.json, "accessors", index)?;
+        let count = index_of(accessor, "count")?;
+        let ty = accessor.get("type").and_then(Json::as_str).unwrap_or("");
+        let components = match ty {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT2" => 4,
+            "MAT3" => 9,
+            "MAT4" => 16,
+            _ => return format_error(format!("accessor {} has an unknown type '{}'", index, ty))
+        };
+        if components != width {
+            return format_error(format!("accessor {} should have {} components, not {}", index, width, components));
+        }
+        if accessor.get("sparse").is_some() {
+            return Err(GltfError::Unsupported(format!("accessor {} is sparse", index)));
+        }
+
+        let component_type = index_of(accessor, "componentType")?;
+        let normalized = accessor.get("normalized") == Some(&Json::Bool(true));
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return format_error(format!("accessor {} has an unknown component type {}", index, component_type))
+        };
+        let read = |bytes: &[u8]| -> f64 {
+            let value = match component_type {
+                5120 => f64::from(bytes[0] as i8),
+                5121 => f64::from(bytes[0]),
+                5122 => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+                5123 => f64::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+                5125 => f64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+                _ => f64::from(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            };
+            match (normalized, component_type) {
+                (true, 5120) => (value / 127.0).max(-1.0),
+                (true, 5121) => value / 255.0,
+                (true, 5122) => (value / 32767.0).max(-1.0),
+                (true, 5123) => value / 65535.0,
+                _ => value
+            }
+        };
+
+        // Accessors without a buffer view are all zeros, and have no bytes to check their count
+        // against
+        let view = match self::index(accessor, "bufferView")? {
+            Some(view) => view,
+            None if count <= MAX_ZEROED_ELEMENTS => return Ok(vec![0.0; count * width]),
+            None => return format_error(format!("accessor {} has too many elements", index))
+        };
+        let (bytes, stride) = self.view(view)?;
+        let offset = self::index(accessor, "byteOffset")?.unwrap_or(0);
+        let element = size * width;
+        let stride = stride.unwrap_or(element);
+        if stride < element {
+            return format_error(format!("accessor {}'s elements overlap in their buffer view", index));
+        }
+        let end = match count {
+            0 => Some(offset),
+            _ => stride.checked_mul(count - 1).and_then(|n| n.checked_add(offset)).and_then(|n| n.checked_add(element))
+        };
+        if !matches!(end, Some(end) if end <= bytes.len()) {
+            return format_error(format!("accessor {} runs past the end of its buffer view", index));
+        }
+
+        let mut values = Vec::with_capacity(count * width);
+        for i in 0..count {
+            let start = offset + i * stride;
+            values.extend((0..width).map(|j| read(&bytes[start + j * size..])));
+        }
+        Ok(values)
+    }
+
+    fn build_materials(&mut self) -> Result<(), GltfError> {
+        let json = self.json.clone();
+        for material in items(&json, "materials") {
+            let material = self.material(material)?;
+            self.materials.push(material);
+        }
+        Ok(())
+    }
+
+    /// A principled material following the metallic-roughness model, or a light if it's emissive
+    fn material(&mut self, json: &Json) -> Result<Arc<dyn Material>, GltfError> {
+        let pbr = json.get("pbrMetallicRoughness").unwrap_or(&Json::Null);
+        let [r, g, b, _] = numbers_or(pbr, "baseColorFactor", [1.0; 4])?;
+        let base_factor = ColorRGB::new(r, g, b);
+        let base_color: TextureRef<ColorRGB> = match self.texture(pbr.get("baseColorTexture"), true)? {
+            Some(texture) if base_factor == colors::WHITE => texture,
+            Some(texture) => Arc::new(Tint::new(texture, base_factor)),
+            None => Arc::new(base_factor)
+        };
+
+        // Roughness is kept in the green channel of the same image as metalness, in the blue
+        let metallic_factor = number_or(pbr, "metallicFactor", 1.0)?;
+        let roughness_factor = number_or(pbr, "roughnessFactor", 1.0)?;
+        let (metallic, roughness): (TextureRef<f64>, TextureRef<f64>) =
+            match self.texture(pbr.get("metallicRoughnessTexture"), false)? {
+                Some(texture) => (
+                    Arc::new(Channel::new(texture.clone(), Color::B, metallic_factor)),
+                    Arc::new(Channel::new(texture, Color::G, roughness_factor))
+                ),
+                None => (Arc::new(metallic_factor), Arc::new(roughness_factor))
+            };
+
+        let [r, g, b] = numbers_or(json, "emissiveFactor", [0.0; 3])?;
+        let emission = ColorRGB::new(r, g, b);
+        let material: Arc<dyn Material> = if emission != colors::BLACK {
+            let strength = match extension(json, "KHR_materials_emissive_strength") {
+                Some(ext) => number_or(ext, "emissiveStrength", 1.0)?,
+                None => 1.0
+            };
+            match self.texture(json.get("emissiveTexture"), true)? {
+                Some(texture) => Arc::new(Emissive::new(Tint::new(texture, emission), strength)),
+                None => Arc::new(Emissive::new(emission, strength))
+            }
+        } else {
+            let defaults = PrincipledParams::default();
+            let transmission = match extension(json, "KHR_materials_transmission") {
+                Some(ext) => number_or(ext, "transmissionFactor", 0.0)?,
+                None => 0.0
+            };
+            let ior = match extension(json, "KHR_materials_ior") {
+                Some(ext) => number_or(ext, "ior", defaults.ior)?,
+                None => defaults.ior
+            };
+            Arc::new(Principled::new(PrincipledParams {
+                base_color, metallic, roughness, transmission, ior,
+                ..defaults
+            }))
+        };
+
+        match self.texture(json.get("normalTexture"), false)? {
+            Some(normal_map) => Ok(Arc::new(Bumped::new(material, NormalDetail::Map(normal_map)))),
+            None => Ok(material)
+        }
+    }
+
+    /// The image a material's texture reference points to, if it can be decoded
+    fn texture(&mut self, info: Option<&Json>, srgb: bool) -> Result<Option<TextureRef<ColorRGB>>, GltfError> {
+        let info = match info {
+            Some(info) => info,
+            None => return Ok(None)
+        };
+        if index(info, "texCoord")?.unwrap_or(0) != 0 {
+            eprintln!("Warning: Only the first set of texture coordinates is read, so some textures may be misplaced.");
+        }
+
+        let texture = item(&self.json, "textures", index_of(info, "index")?)?;
+        let image_index = match index(texture, "source")? {
+            Some(image) => image,
+            None => {
+                eprintln!("Warning: Skipping a texture with no image we can read.");
+                return Ok(None);
+            }
+        };
+        if let Some(image) = self.images.get(&(image_index, srgb)) {
+            return Ok(image.clone());
+        }
+
+        let image = item(&self.json, "images", image_index)?;
+        let bytes = match (image.get("uri").and_then(Json::as_str), index(image, "bufferView")?) {
+            (Some(uri), _) => Document::uri(self.dir, uri)?,
+            (None, Some(view)) => self.view(view)?.0.to_vec(),
+            (None, None) => return format_error(format!("image {} has no uri or buffer view", image_index))
+        };
+        let decoded: Option<TextureRef<ColorRGB>> = match Image::decode(&bytes, srgb) {
+            Ok(decoded) => Some(Arc::new(decoded)),
+            Err(TextureError::Unsupported(msg)) => {
+                eprintln!("Warning: Skipping image {}: {}.", image_index, msg);
+                None
+            },
+            Err(err) => return Err(GltfError::Texture(err))
+        };
+
+        self.images.insert((image_index, srgb), decoded.clone());
+        Ok(decoded)
+    }
+
+    /// Places a node, then its children, transformed by `parent`
+    fn node(&mut self, index: usize, parent: &Matrix, visited: &mut HashSet<usize>) -> Result<(), GltfError> {
+        if !visited.insert(index) {
+            return format_error(format!("node {} is in the scene more than once", index));
+        }
+        let node = item(&self.json, "nodes", index)?.clone();
+
+        let local = match node.get("matrix") {
+            Some(_) => {
+                // Matrices are written by columns
+                let values = numbers_or(&node, "matrix", [0.0; 16])?;
+                let mut matrix = IDENTITY;
+                for (i, row) in matrix.iter_mut().enumerate() {
+                    for (j, value) in row.iter_mut().enumerate() {
+                        *value = values[4 * j + i];
+                    }
+                }
+                matrix
+            },
+            None => {
+                let [tx, ty, tz] = numbers_or(&node, "translation", [0.0; 3])?;
+                let [x, y, z, w] = numbers_or(&node, "rotation", [0.0, 0.0, 0.0, 1.0])?;
+                let [sx, sy, sz] = numbers_or(&node, "scale", [1.0; 3])?;
+                [
+                    [sx * (1.0 - 2.0 * (y * y + z * z)), sy * 2.0 * (x * y - z * w), sz * 2.0 * (x * z + y * w), tx],
+                    [sx * 2.0 * (x * y + z * w), sy * (1.0 - 2.0 * (x * x + z * z)), sz * 2.0 * (y * z - x * w), ty],
+                    [sx * 2.0 * (x * z - y * w), sy * 2.0 * (y * z + x * w), sz * (1.0 - 2.0 * (x * x + y * y)), tz],
+                    [0.0, 0.0, 0.0, 1.0]
+                ]
+            }
+        };
+        let world = multiply(parent, &local);
+
+        if let Some(mesh) = self::index(&node, "mesh")? {
+            self.mesh(mesh, &world)?;
+        }
+        if let Some(camera) = self::index(&node, "camera")? {
+            self.camera(camera, &world)?;
+        }
+        for child in items(&node, "children") {
+            match child.as_usize() {
+                Some(child) => self.node(child, &world, visited)?,
+                None => return format_error(format!("node {} has a child that isn't an index", index))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mesh(&mut self, index: usize, transform: &Matrix) -> Result<(), GltfError> {
+        let mesh = item(&self.json, "meshes", index)?.clone();
+        for primitive in items(&mesh, "primitives") {
+            let attributes = primitive.get("attributes").unwrap_or(&Json::Null);
+            let positions = match self::index(attributes, "POSITION")? {
+                Some(accessor) => self.accessor(accessor, 3)?,
+                None => continue
+            };
+            let points: Vec<Point3> = positions.chunks(3)
+                .map(|p| transform_point(transform, &Point3::new(p[0], p[1], p[2])))
+                .collect();
+            let normals: Option<Vec<Vec3>> = match self::index(attributes, "NORMAL")? {
+                Some(accessor) => Some(self.accessor(accessor, 3)?.chunks(3)
+                    .map(|n| transform_normal(transform, &Vec3::new(n[0], n[1], n[2])))
+                    .collect()),
+                None => None
+            };
+            // glTF's v goes down images, and ours goes up
+            let uvs: Option<Vec<(f64, f64)>> = match self::index(attributes, "TEXCOORD_0")? {
+                Some(accessor) => Some(self.accessor(accessor, 2)?.chunks(2).map(|uv| (uv[0], 1.0 - uv[1])).collect()),
+                None => None
+            };
+
+            let indices: Vec<usize> = match self::index(primitive, "indices")? {
+                Some(accessor) => self.accessor(accessor, 1)?.into_iter().map(|i| i as usize).collect(),
+                None => (0..points.len()).collect()
+            };
+            let corners: Vec<[usize; 3]> = match self::index(primitive, "mode")?.unwrap_or(4) {
+                4 => indices.chunks_exact(3).map(|tri| [tri[0], tri[1], tri[2]]).collect(),
+                // Every other triangle of a strip is wound the other way
+                5 => (2..indices.len()).map(|i| if i % 2 == 0 {
+                    [indices[i - 2], indices[i - 1], indices[i]]
+                } else {
+                    [indices[i - 1], indices[i - 2], indices[i]]
+                }).collect(),
+                6 => (2..indices.len()).map(|i| [indices[0], indices[i - 1], indices[i]]).collect(),
+                _ => {
+                    eprintln!("Warning: Skipping points and lines in mesh {}.", index);
+                    continue;
+                }
+            };
+            if let Some(&bad) = corners.iter().flatten().find(|&&i| i >= points.len()) {
+                return format_error(format!("mesh {} has an index {} past its {} vertices", index, bad, points.len()));
+            }
+            let counts = [normals.as_ref().map(Vec::len), uvs.as_ref().map(Vec::len)];
+            if counts.iter().flatten().any(|&count| count != points.len()) {
+                return format_error(format!("mesh {} has attributes for different numbers of vertices", index));
+            }
+
+            let material = match self::index(primitive, "material")? {
+                Some(material) => match self.materials.get(material) {
+                    Some(material) => material.clone(),
+                    None => return format_error(format!("there is no materials {}", material))
+                },
+                None => self.default_material.clone()
+            };
+            for [a, b, c] in corners {
+                let mut tri = Triangle::new((points[a].clone(), points[b].clone(), points[c].clone()), material.clone());
+                if let Some(uvs) = &uvs {
+                    tri = tri.with_uvs((uvs[a], uvs[b], uvs[c]));
+                }
+                if let Some(normals) = &normals {
+                    tri = tri.with_normals((normals[a].clone(), normals[b].clone(), normals[c].clone()));
+                }
+                self.triangles.push(tri);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Perspective cameras look down their node's -z, with y up
+    fn camera(&mut self, index: usize, transform: &Matrix) -> Result<(), GltfError> {
+        let camera = item(&self.json, "cameras", index)?;
+        let perspective = match camera.get("perspective") {
+            Some(perspective) if camera.get("type").and_then(Json::as_str) == Some("perspective") => perspective,
+            _ => {
+                eprintln!("Warning: Skipping camera {}, which isn't a perspective camera.", index);
+                return Ok(());
+            }
+        };
+        let yfov = match perspective.get("yfov").and_then(Json::as_f64) {
+            Some(yfov) => yfov,
+            None => return format_error(format!("camera {} has no yfov", index))
+        };
+        let aspect_ratio = match perspective.get("aspectRatio") {
+            Some(_) => Some(number_or(perspective, "aspectRatio", 1.0)?),
+            None => None
+        };
+
+        let location = transform_point(transform, &Point3::O);
+        let forward = normalize(transform_dir(transform, &-Vec3::K));
+        self.cameras.push(GltfCamera {
+            look_at: &location + forward,
+            location,
+            up: normalize(transform_dir(transform, &Vec3::J)),
+            yfov_deg: yfov.to_degrees(),
+            aspect_ratio
+        });
+        Ok(())
+    }
+}
+
+/// An index a glTF object must have
+fn index_of(json: &Json, key: &str) -> Result<usize, GltfError> {
+    match index(json, key)? {
+        Some(index) => Ok(index),
+        None => format_error(format!("missing '{}'", key))
+    }
+}
+
+/// Loads the default scene of a .gltf or .glb file. Primitives without a material of their own
+/// are given `default_material`.
+pub fn load(filepath: &Path, default_material: Arc<dyn Material>) -> Result<Gltf, GltfError> {
+    let bytes = fs::read(filepath)?;
+    let (json, bin) = if bytes.starts_with(GLB_MAGIC) {
+        glb_chunks(&bytes)?
+    } else {
+        (json::parse(&bytes)?, None)
+    };
+
+    let dir = filepath.parent().unwrap_or_else(|| Path::new(""));
+    let mut document = Document::new(json, dir, bin, default_material)?;
+    document.build_materials()?;
+
+    // Without a default scene, every node that isn't another's child is placed
+    let scene = match index(&document.json, "scene")? {
+        Some(scene) => Some(item(&document.json, "scenes", scene)?),
+        None => items(&document.json, "scenes").first()
+    };
+    let roots: Vec<usize> = match scene {
+        Some(scene) => items(scene, "nodes").iter().filter_map(Json::as_usize).collect(),
+        None => {
+            let children: HashSet<usize> = items(&document.json, "nodes").iter()
+                .flat_map(|node| items(node, "children").iter().filter_map(Json::as_usize))
+                .collect();
+            (0..items(&document.json, "nodes").len()).filter(|node| !children.contains(node)).collect()
+        }
+    };
+
+    let mut visited = HashSet::new();
+    for root in roots {
+        document.node(root, &IDENTITY, &mut visited)?;
+    }
+
+    Ok(Gltf { triangles: document.triangles, cameras: document.cameras })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::hit::Hittable;
+    use crate::material::DiffuseLambert;
+    use crate::math::f_eq;
+
+    /// A right triangle with normals, uvs and indices, packed one after the other
+    fn mesh_buffer() -> Vec<u8> {
+        let mut bytes = vec![];
+        let floats = [
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0,
+            0.0, 0.0, 1.0, 0.0, 0.0, 1.0_f32
+        ];
+        for value in &floats {
+            bytes.extend(value.to_le_bytes().iter());
+        }
+        for index in &[0_u16, 1, 2, 0] {
+            bytes.extend(index.to_le_bytes().iter());
+        }
+        bytes
+    }
+
+    /// A document that hangs the triangle from a scaled, moved node, turned by a child node, next
+    /// to a camera. `buffer` is the buffer's members.
+    fn document(buffer: &str, extra: &str) -> String {
+        format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0, 2] }}],
+            "nodes": [
+                {{ "children": [1], "translation": [0, 0, 5], "scale": [2, 2, 2] }},
+                {{ "mesh": 0, "rotation": [0, 0, 0.70710678, 0.70710678] }},
+                {{ "camera": 0, "translation": [0, 1, 10] }}
+            ],
+            "cameras": [{{ "type": "perspective", "perspective": {{ "yfov": 0.5, "aspectRatio": 1.5, "znear": 0.1 }} }}],
+            "meshes": [{{ "primitives": [
+                {{ "attributes": {{ "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 }}, "indices": 3, "material": 0 }},
+                {{ "attributes": {{ "POSITION": 0 }} }}
+            ] }}],
+            "materials": [{{ "emissiveFactor": [1, 0.5, 0] }}],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                {{ "bufferView": 0, "byteOffset": 36, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                {{ "bufferView": 0, "byteOffset": 72, "componentType": 5126, "count": 3, "type": "VEC2" }},
+                {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ],
+            "bufferViews": [
+                {{ "buffer": 0, "byteLength": 96 }},
+                {{ "buffer": 0, "byteOffset": 96, "byteLength": 6 }}
+            ],
+            "buffers": [{{ {} "byteLength": 104 }}]
+            {}
+        }}"#, buffer, extra)
+    }
+
+    fn write_files(test: &str, files: &[(&str, &[u8])]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-gltf-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn load_default(path: &Path) -> Result<Gltf, GltfError> {
+        load(path, Arc::new(DiffuseLambert::new(colors::WHITE)))
+    }
+
+    fn check_scene(gltf: &Gltf) {
+        // Both primitives' triangles are turned a quarter about z, doubled and moved back
+        assert_eq!(gltf.triangles.len(), 2);
+        for tri in &gltf.triangles {
+            assert_eq!(tri.a(), &Point3::new(0.0, 0.0, 5.0));
+            assert_eq!(tri.b(), &Point3::new(0.0, 2.0, 5.0));
+            assert_eq!(tri.c(), &Point3::new(-2.0, 0.0, 5.0));
+        }
+        let tri = &gltf.triangles[0];
+        assert_eq!(tri.uvs(), &((0.0, 1.0), (1.0, 1.0), (0.0, 0.0)));
+        assert_eq!(tri.normals().unwrap().0, Vec3::K);
+        assert!(tri.is_emissive());
+        assert!(!gltf.triangles[1].is_emissive() && gltf.triangles[1].normals().is_none());
+
+        assert_eq!(gltf.cameras.len(), 1);
+        let camera = &gltf.cameras[0];
+        assert_eq!(camera.location, Point3::new(0.0, 1.0, 10.0));
+        assert_eq!(camera.look_at, Point3::new(0.0, 1.0, 9.0));
+        assert_eq!(camera.up, Vec3::J);
+        assert!((camera.yfov_deg - 0.5_f64.to_degrees()).abs() < 1e-9);
+        assert_eq!(camera.aspect_ratio, Some(1.5));
+    }
+
+    #[test]
+    fn reads_gltf_files() {
+        let dir = write_files("text", &[
+            ("scene.gltf", document(r#""uri": "mesh%20data.bin","#, "").as_bytes()),
+            ("mesh data.bin", &mesh_buffer())
+        ]);
+        let gltf = load_default(&dir.join("scene.gltf"));
+        fs::remove_dir_all(&dir).unwrap();
+        check_scene(&gltf.unwrap());
+    }
+
+    #[test]
+    fn reads_glb_files() {
+        // Chunks are padded to four bytes, JSON with spaces
+        let mut json = document("", "").into_bytes();
+        json.resize(json.len().div_ceil(4) * 4, b' ');
+        let bin = mesh_buffer();
+
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2_u32.to_le_bytes().iter());
+        glb.extend(((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes().iter());
+        for (chunk, chunk_type) in &[(&json, GLB_JSON), (&bin, GLB_BIN)] {
+            glb.extend((chunk.len() as u32).to_le_bytes().iter());
+            glb.extend(chunk_type.to_le_bytes().iter());
+            glb.extend(chunk.iter());
+        }
+
+        let dir = write_files("binary", &[("scene.glb", &glb)]);
+        let gltf = load_default(&dir.join("scene.glb"));
+        fs::remove_dir_all(&dir).unwrap();
+        check_scene(&gltf.unwrap());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular() {
+        // Turned about y, then stretched along x and mirrored in z
+        let (sin, cos) = 0.6_f64.sin_cos();
+        let m = [
+            [3.0 * cos, 0.0, 3.0 * sin, 1.0],
+            [0.0, 1.0, 0.0, 2.0],
+            [sin, 0.0, -cos, 3.0],
+            [0.0, 0.0, 0.0, 1.0]
+        ];
+        let (tangent, normal) = (Vec3::new(1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0));
+        let (tangent, normal) = (transform_dir(&m, &tangent), transform_normal(&m, &normal));
+        assert!(f_eq(tangent.dot(&normal), 0.0) && f_eq(normal.norm(), 1.0));
+        assert!(f_eq(transform_normal(&m, &Vec3::J).dot(&Vec3::J), 1.0));
+
+        let point = transform_point(&multiply(&m, &IDENTITY), &Point3::new(0.0, 1.0, 0.0));
+        assert_eq!(point, Point3::new(1.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn decodes_data_uris() {
+        assert_eq!(base64("aGVsbG8sIHdvcmxk"), Some(b"hello, world".to_vec()));
+        assert_eq!(base64("aGk="), Some(b"hi".to_vec()));
+        assert_eq!(base64("a*b"), None);
+        assert_eq!(Document::uri(Path::new(""), "data:application/octet-stream;base64,aGk=").unwrap(), b"hi");
+    }
+
+    #[test]
+    fn errors_say_what() {
+        let error = |name: &str, contents: String| {
+            let dir = write_files("errors", &[(name, contents.as_bytes()), ("mesh.bin", &mesh_buffer())]);
+            let result = load_default(&dir.join(name));
+            fs::remove_dir_all(&dir).unwrap();
+            result.err().unwrap().to_string()
+        };
+
+        let json = error("json.gltf", document(r#""uri": "mesh.bin""#, ""));
+        assert!(json.contains("line 26"), "{}", json);
+        let draco = r#", "extensionsRequired": ["KHR_draco_mesh_compression"]"#;
+        let required = error("required.gltf", document(r#""uri": "mesh.bin","#, draco));
+        assert!(required.contains("Unsupported") && required.contains("KHR_draco_mesh_compression"), "{}", required);
+        let missing = error("missing.gltf", document(r#""uri": "missing.bin","#, ""));
+        assert!(missing.contains("missing.bin"), "{}", missing);
+        let short_view = document(r#""uri": "mesh.bin","#, "").replace(r#""byteLength": 6"#, r#""byteLength": 4"#);
+        let short = error("short.gltf", short_view);
+        assert!(short.contains("accessor 3"), "{}", short);
+
+        // Counts too big for the buffer view, or for memory when there isn't one
+        let huge_view = document(r#""uri": "mesh.bin","#, "").replace(r#""count": 3, "type": "SCALAR""#, r#""count": 1000000000000, "type": "SCALAR""#);
+        let huge = error("huge.gltf", huge_view);
+        assert!(huge.contains("accessor 3"), "{}", huge);
+        let huge_zeros = document(r#""uri": "mesh.bin","#, "").replace(r#""bufferView": 0, "componentType": 5126, "count": 3,"#, r#""componentType": 5126, "count": 1000000000000,"#);
+        let huge = error("huge-zeros.gltf", huge_zeros);
+        assert!(huge.contains("accessor 0") && huge.contains("too many"), "{}", huge);
+        let far_view = document(r#""uri": "mesh.bin","#, "").replace(r#""byteOffset": 96,"#, r#""byteOffset": 18446744073709551615,"#);
+        let far = error("far.gltf", far_view);
+        assert!(far.contains("buffer view 1"), "{}", far);
+    }
+}
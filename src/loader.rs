@@ -1,12 +1,28 @@
+use std::collections::{ HashMap, HashSet };
 use std::convert;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{ self, File };
 use std::fmt;
 use std::io::{ self, BufRead };
 use std::num;
-use std::path::Path;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
 
-use crate::vec::{ Point3 };
+use crate::gltf::GltfError;
+use crate::material::{
+    Bumped,
+    Coated,
+    DiffuseLambert,
+    Emissive,
+    Material,
+    NormalDetail,
+    Principled,
+    PrincipledParams,
+    Reflective,
+    Translucent
+};
+use crate::texture::{ Image, Luminance, TextureError, TextureRef };
+use crate::vec::{ colors, Color, ColorRGB, Point3, Vec3 };
 
 fn read_lines(filepath: &Path) -> io::Result<io::Lines<io::BufReader<File>>> {
     let file = File::open(filepath)?;
@@ -17,9 +33,17 @@ fn read_lines(filepath: &Path) -> io::Result<io::Lines<io::BufReader<File>>> {
 pub enum LoaderError {
     Vert(String),
     Face(String),
+    Material(String),
+    Ply(String),
+    /// A model file of a kind that can't be loaded
+    Format(String),
     Io(io::Error),
     ParseFloat(num::ParseFloatError),
-    ParseInt(num::ParseIntError)
+    ParseInt(num::ParseIntError),
+    Texture(TextureError),
+    Gltf(GltfError),
+    /// Something wrong on a line of a file
+    Line(PathBuf, usize, Box<LoaderError>)
 }
 
 impl fmt::Display for LoaderError {
@@ -29,12 +53,24 @@ impl fmt::Display for LoaderError {
                 write!(f, "Could not read vertex from file: {}", msg),
             LoaderError::Face(msg) =>
                 write!(f, "Could not read face from file: {}", msg),
+            LoaderError::Material(msg) =>
+                write!(f, "Could not read material from file: {}", msg),
+            LoaderError::Ply(msg) =>
+                write!(f, "Could not read PLY file: {}", msg),
+            LoaderError::Format(extension) =>
+                write!(f, "Unsupported model format '{}'", extension),
             LoaderError::Io(err) =>
                 write!(f, "Could not load file: {}", err),
             LoaderError::ParseFloat(err) =>
                 write!(f, "Could not parse float in file: {}", err),
             LoaderError::ParseInt(err) =>
-                write!(f, "Could not parse int in file: {}", err)
+                write!(f, "Could not parse int in file: {}", err),
+            LoaderError::Texture(err) =>
+                write!(f, "Could not load texture: {}", err),
+            LoaderError::Gltf(err) =>
+                write!(f, "{}", err),
+            LoaderError::Line(path, line, err) =>
+                write!(f, "{}:{}: {}", path.display(), line, err)
         }
     }
 }
@@ -59,89 +95,1008 @@ impl convert::From<num::ParseIntError> for LoaderError {
     }
 }
 
+impl convert::From<TextureError> for LoaderError {
+    fn from(err: TextureError) -> Self {
+        LoaderError::Texture(err)
+    }
+}
+
+impl convert::From<GltfError> for LoaderError {
+    fn from(err: GltfError) -> Self {
+        LoaderError::Gltf(err)
+    }
+}
+
 pub enum Polygon {
     Tri
 }
 
 pub trait Model {}
 
+/// One of a model's faces, as indices into its lists of vertices and texture coordinates.
+/// Polygons with more corners are split into triangles as they're read.
+pub struct Face {
+    pub polygon: Polygon,
+    pub vertices: Vec<usize>,
+    /// Faces without texture coordinates or normals leave them to whatever uses the model
+    pub texcoords: Option<Vec<usize>>,
+    pub normals: Option<Vec<usize>>,
+    /// The group (`o` or `g`) the face is in, as an index into the model's groups
+    pub group: Option<usize>,
+    /// The name of the material `usemtl` gave the face
+    pub material: Option<String>
+}
+
+/// A model's triangles and what's attached to them. Models in other formats than .obj are read
+/// into the same form.
+#[derive(Default)]
 pub struct Obj {
     pub vertices: Vec<Point3>,
-    pub indices: Vec<(Polygon, Vec<usize>)>
+    pub texcoords: Vec<(f64, f64)>,
+    pub normals: Vec<Vec3>,
+    /// A color for each vertex, if the model has them
+    pub colors: Vec<ColorRGB>,
+    pub faces: Vec<Face>,
+    pub groups: Vec<String>,
+    /// The materials defined in the model's `mtllib` files, by name
+    pub materials: HashMap<String, Mtl>
 }
 
 impl Model for Obj {}
 
+impl Obj {
+    /// A normal for each vertex, averaging those of the faces around it weighted by the angle
+    /// each face makes there, so that the mesh can be shaded smoothly when it has no normals
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        // Faces of scanned models can be too small for `Vec3::unit`, which treats them as empty
+        let normalized = |v: Vec3| {
+            let norm = v.norm();
+            if norm > 0.0 { (1.0 / norm) * v } else { v }
+        };
+
+        let mut normals = vec![Vec3::O; self.vertices.len()];
+        for face in &self.faces {
+            let corners: Vec<&Point3> = face.vertices.iter().map(|&i| &self.vertices[i]).collect();
+            let face_normal = normalized((corners[1] - corners[0]).cross(&(corners[2] - corners[0])));
+            for (i, &vertex) in face.vertices.iter().enumerate() {
+                let to_next = corners[(i + 1) % 3] - corners[i];
+                let to_prev = corners[(i + 2) % 3] - corners[i];
+                let angle = to_next.cross(&to_prev).norm().atan2(to_next.dot(&to_prev));
+                normals[vertex] += angle * &face_normal;
+            }
+        }
+
+        normals.into_iter().map(normalized).collect()
+    }
+}
+
+/// A material from a Wavefront .mtl file, before it's turned into one of ours
+#[derive(Debug)]
+pub struct Mtl {
+    /// `Kd`, and `map_Kd`, which replaces it
+    pub diffuse: ColorRGB,
+    pub diffuse_map: Option<TextureRef<ColorRGB>>,
+    /// `Ks`
+    pub specular: ColorRGB,
+    /// `Ke`
+    pub emission: ColorRGB,
+    /// `Ns`, the Phong exponent
+    pub shininess: f64,
+    /// `Ni`
+    pub ior: Option<f64>,
+    /// `d`, or one minus `Tr`
+    pub dissolve: f64,
+    /// `Tf`
+    pub transmission: ColorRGB,
+    pub illum: u32,
+    /// `Pr` and `Pm`, or their maps, from the PBR extension to the format
+    pub roughness: Option<TextureRef<f64>>,
+    pub metallic: Option<TextureRef<f64>>,
+    /// `bump` or `map_Bump`, with the height given by its `-bm` option
+    pub bump: Option<(TextureRef<f64>, f64)>,
+    /// `norm`
+    pub normal_map: Option<TextureRef<ColorRGB>>
+}
+
+impl Default for Mtl {
+    fn default() -> Mtl {
+        Mtl {
+            diffuse: ColorRGB::new(0.8, 0.8, 0.8),
+            diffuse_map: None,
+            specular: colors::BLACK,
+            emission: colors::BLACK,
+            shininess: 0.0,
+            ior: None,
+            dissolve: 1.0,
+            transmission: colors::WHITE,
+            illum: 2,
+            roughness: None,
+            metallic: None,
+            bump: None,
+            normal_map: None
+        }
+    }
+}
+
+impl Mtl {
+    /// How bumpy a bump map makes a surface when its `-bm` option doesn't say
+    const BUMP_HEIGHT: f64 = 0.01;
+
+    /// The closest of our materials to what the file describes. Lights are emissive; the PBR
+    /// extension's parameters make a principled material; see-through materials are
+    /// translucent; and the classic diffuse and specular colors make a diffuse surface, a
+    /// glossy metal if there's only specular, or a glossy coat over diffuse if there's both.
+    pub fn material(&self) -> Arc<dyn Material> {
+        let diffuse: TextureRef<ColorRGB> = match &self.diffuse_map {
+            Some(map) => map.clone(),
+            None => Arc::new(self.diffuse.clone())
+        };
+        // Phong exponents and GGX widths are related by alpha = sqrt(2 / (n + 2))
+        let phong_roughness = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt().sqrt();
+        let is_black = |color: &ColorRGB| [Color::R, Color::G, Color::B].iter().all(|&channel| color[channel] <= 0.0);
+
+        let material: Arc<dyn Material> = if !is_black(&self.emission) {
+            Arc::new(Emissive::new(self.emission.clone(), 1.0))
+        } else if self.roughness.is_some() || self.metallic.is_some() {
+            let defaults = PrincipledParams::default();
+            Arc::new(Principled::new(PrincipledParams {
+                base_color: diffuse,
+                metallic: self.metallic.clone().unwrap_or(defaults.metallic),
+                roughness: self.roughness.clone().unwrap_or_else(|| Arc::new(phong_roughness)),
+                transmission: 1.0 - self.dissolve,
+                ior: self.ior.unwrap_or(defaults.ior),
+                ..defaults
+            }))
+        } else if self.dissolve < 1.0 || [4, 6, 7, 9].contains(&self.illum) {
+            Arc::new(Translucent::new(self.transmission.clone(), self.ior.unwrap_or(1.5), phong_roughness))
+        } else if self.illum < 2 || is_black(&self.specular) {
+            Arc::new(DiffuseLambert::new(diffuse))
+        } else if self.diffuse_map.is_none() && is_black(&self.diffuse) {
+            Arc::new(Reflective::new(self.specular.clone(), phong_roughness))
+        } else {
+            let base = Arc::new(DiffuseLambert::new(diffuse));
+            Arc::new(Coated::new(base, self.ior.unwrap_or(1.5), phong_roughness))
+        };
+
+        let detail = match (&self.normal_map, &self.bump) {
+            (Some(map), _) => NormalDetail::Map(map.clone()),
+            (None, Some((bump, height))) => NormalDetail::Bump(bump.clone(), *height),
+            (None, None) => return material
+        };
+        Arc::new(Bumped::new(material, detail))
+    }
+}
+
 pub struct Loader;
 
+/// What's been read of an .obj file so far, and the state its statements change
+struct ObjReader<'a> {
+    obj: Obj,
+    dir: &'a Path,
+    group: Option<usize>,
+    material: Option<String>,
+    /// Materials faces used that none of the libraries define, so each is only warned about once
+    missing: HashSet<String>
+}
+
+impl<'a> ObjReader<'a> {
+    fn read(&mut self, data: &[&str]) -> Result<(), LoaderError> {
+        match data[0] {
+            "v" => {
+                if data.len() < 4 {
+                    return Err(
+                        LoaderError::Vert("Received fewer than three coordinates".to_string())
+                    );
+                }
+                let v_x: f64 = data[1].parse()?;
+                let v_y: f64 = data[2].parse()?;
+                let v_z: f64 = data[3].parse()?;
+                self.obj.vertices.push(Point3::new(v_x, v_y, v_z));
+            },
+            "vt" => {
+                // The v coordinate is optional, and so is a third w that only 3D textures use
+                let u: f64 = data[1].parse()?;
+                let v: f64 = match data.get(2) {
+                    Some(v) => v.parse()?,
+                    None => 0.0
+                };
+                self.obj.texcoords.push((u, v));
+            },
+            "vn" => {
+                if data.len() < 4 {
+                    return Err(
+                        LoaderError::Vert("Received fewer than three normal coordinates".to_string())
+                    );
+                }
+                let n_x: f64 = data[1].parse()?;
+                let n_y: f64 = data[2].parse()?;
+                let n_z: f64 = data[3].parse()?;
+                self.obj.normals.push(Vec3::new(n_x, n_y, n_z));
+            },
+            "f" => {
+                if data.len() < 4 {
+                    return Err(LoaderError::Face("Received fewer than three indices".to_string()));
+                }
+                self.face(&data[1..])?;
+            },
+            "o" | "g" => {
+                let name = data[1..].join(" ");
+                self.group = Some(match self.obj.groups.iter().position(|group| *group == name) {
+                    Some(index) => index,
+                    None => {
+                        self.obj.groups.push(name);
+                        self.obj.groups.len() - 1
+                    }
+                });
+            },
+            "usemtl" => {
+                let name = data[1..].join(" ");
+                if !self.obj.materials.contains_key(&name) && self.missing.insert(name.clone()) {
+                    eprintln!("Warning: Material {} isn't in any of the model's libraries.", name);
+                }
+                self.material = Some(name);
+            },
+            "mtllib" => {
+                for library in &data[1..] {
+                    let path = self.dir.join(library);
+                    if !path.exists() {
+                        eprintln!("Warning: Material library {} doesn't exist.", path.display());
+                        continue;
+                    }
+                    self.obj.materials.extend(Loader::load_mtl(&path)?);
+                }
+            },
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    /// Reads a polygon's corners, each `v`, `v/vt`, `v/vt/vn` or `v//vn`, and splits it into a
+    /// fan of triangles, which is right as long as it's convex
+    fn face(&mut self, corners: &[&str]) -> Result<(), LoaderError> {
+        let mut vertices = vec![];
+        let mut texcoords = vec![];
+        let mut normals = vec![];
+        for corner in corners {
+            let mut refs = corner.split('/');
+            let vertex = refs.next().unwrap_or("");
+            vertices.push(Loader::obj_index(vertex, self.obj.vertices.len())?);
+            match refs.next() {
+                Some(texcoord) if !texcoord.is_empty() =>
+                    texcoords.push(Loader::obj_index(texcoord, self.obj.texcoords.len())?),
+                _ => ()
+            }
+            match refs.next() {
+                Some(normal) if !normal.is_empty() =>
+                    normals.push(Loader::obj_index(normal, self.obj.normals.len())?),
+                _ => ()
+            }
+        }
+
+        let texcoords = Loader::corner_indices(texcoords, corners.len(), "texture coordinates")?;
+        let normals = Loader::corner_indices(normals, corners.len(), "normals")?;
+        for i in 1..corners.len() - 1 {
+            self.obj.faces.push(Face {
+                polygon: Polygon::Tri,
+                vertices: Loader::fan(&vertices, i),
+                texcoords: texcoords.as_ref().map(|indices| Loader::fan(indices, i)),
+                normals: normals.as_ref().map(|indices| Loader::fan(indices, i)),
+                group: self.group,
+                material: self.material.clone()
+            });
+        }
+
+        Ok(())
+    }
+}
+
 impl Loader {
     pub fn load_obj(filepath: &Path) -> Result<Obj, LoaderError> {
         let lines = read_lines(filepath)?;
+        let mut reader = ObjReader {
+            obj: Obj::default(),
+            dir: filepath.parent().unwrap_or_else(|| Path::new("")),
+            group: None,
+            material: None,
+            missing: HashSet::new()
+        };
 
-        let mut vertices: Vec<Point3> = vec![];
-        let mut indices: Vec<(Polygon, Vec<usize>)> = vec![];
-
-        for line in lines {
+        for (number, line) in lines.enumerate() {
             let line = line?;
-            let mut data: Vec<_> = line.split(" ").collect();
-            data.retain(|s| !s.is_empty());
+            let data = Loader::statement(&line);
             if data.len() <= 1 { continue; }
 
-            match data[0] {
-                "v" => {
-                    if data.len() < 4 {
-                        return Err(
-                            LoaderError::Vert(format!("Received fewer than three coordinates"))
-                        );
+            reader.read(&data)
+                .map_err(|err| LoaderError::Line(filepath.to_path_buf(), number + 1, Box::new(err)))?;
+        }
+
+        Ok(reader.obj)
+    }
+
+    /// Reads a PLY file's vertices, with their normals, colors and texture coordinates if it has
+    /// them, and its faces, which are split into triangles
+    pub fn load_ply(filepath: &Path) -> Result<Obj, LoaderError> {
+        let bytes = fs::read(filepath)?;
+        let (format, elements, body_start) = Loader::ply_header(filepath, &bytes)?;
+        let mut body = PlyBody { bytes: &bytes, position: body_start, format };
+
+        let mut obj = Obj::default();
+        let (mut has_normals, mut has_texcoords) = (false, false);
+        for element in &elements {
+            // Every entry of an element with properties takes up at least a byte
+            if !element.properties.is_empty() && element.count > bytes.len() - body.position {
+                return Err(LoaderError::Ply(format!("File is too short for {} {}s", element.count, element.name)));
+            }
+
+            let find = |names: &[&str]| element.properties.iter().position(|property| names.contains(&property.name()));
+            let find_all = |names: &[&str]| -> Option<Vec<usize>> { names.iter().map(|name| find(&[name])).collect() };
+            match &element.name[..] {
+                "vertex" => {
+                    let position = find_all(&["x", "y", "z"])
+                        .ok_or_else(|| LoaderError::Ply("Vertices have no position".to_string()))?;
+                    let normal = find_all(&["nx", "ny", "nz"]);
+                    let color = find_all(&["red", "green", "blue"]);
+                    let texcoord = [["u", "v"], ["s", "t"], ["texture_u", "texture_v"], ["texture_s", "texture_t"]]
+                        .iter().find_map(|names| find_all(names));
+                    let color_scale = match color.as_ref().map(|color| &element.properties[color[0]]) {
+                        Some(PlyProperty::Scalar(_, ty)) => ty.color_scale(),
+                        _ => 1.0
+                    };
+
+                    for _ in 0..element.count {
+                        let values = body.element(element)?;
+                        let value = |index: usize| values[index].first().copied().unwrap_or(0.0);
+                        obj.vertices.push(Point3::new(value(position[0]), value(position[1]), value(position[2])));
+                        if let Some(normal) = &normal {
+                            obj.normals.push(Vec3::new(value(normal[0]), value(normal[1]), value(normal[2])));
+                        }
+                        if let Some(color) = &color {
+                            obj.colors.push(color_scale * ColorRGB::new(value(color[0]), value(color[1]), value(color[2])));
+                        }
+                        if let Some(texcoord) = &texcoord {
+                            obj.texcoords.push((value(texcoord[0]), value(texcoord[1])));
+                        }
                     }
-                    let v_x: f64 = data[1].parse()?;
-                    let v_y: f64 = data[2].parse()?;
-                    let v_z: f64 = data[3].parse()?;
-                    vertices.push(Point3::new(v_x, v_y, v_z));
+                    has_normals = normal.is_some();
+                    has_texcoords = texcoord.is_some();
                 },
-                "f" => {
-                    if data.len() < 4 {
-                        return Err(LoaderError::Face(format!("Received fewer than three indices")));
-                    }
-                    let indices_strs: Vec<_> = data[1..].iter().collect();
-                    match indices_strs.len() {
-                        3 => {
-                            let i_1: i64 = indices_strs[0].parse()?;
-                            let i_2: i64 = indices_strs[1].parse()?;
-                            let i_3: i64 = indices_strs[2].parse()?;
-
-                            let u_1: usize =
-                                if i_1 < 0 { vertices.len() + 1 - ((-i_1) as usize) }
-                                else { i_1 as usize - 1 };
-                            let u_2: usize =
-                                if i_2 < 0 { vertices.len() + 1 - ((-i_2) as usize) }
-                                else { i_2 as usize - 1 };
-                            let u_3: usize =
-                                if i_3 < 0 { vertices.len() + 1 - ((-i_3) as usize) }
-                                else { i_3 as usize - 1 };
-
-                            if u_1 >= vertices.len() || u_2 >= vertices.len() || u_3 >= vertices.len() {
-                                return Err(
-                                    LoaderError::Face(format!(
-                                        "One of {}, {}, {} refers to vertex outside of current range",
-                                        u_1, u_2, u_3
-                                    )
-                                ));
-                            }
-
-                            let face_indices = vec![u_1, u_3, u_2];
-                            indices.push((Polygon::Tri, face_indices));
-                        },
-                        4 => {
-                            todo!()
-                        },
-                        _ => return Err(LoaderError::Face(format!("Too many indices")))
+                "face" => {
+                    let corners = find(&["vertex_indices", "vertex_index"])
+                        .ok_or_else(|| LoaderError::Ply("Faces have no vertex indices".to_string()))?;
+
+                    for _ in 0..element.count {
+                        let values = body.element(element)?;
+                        // Indices are read as floats, which would be cast to a vertex that was never given
+                        let vertices = values[corners].iter().map(|&index| match index {
+                            index if index >= 0.0 && index.fract() == 0.0 => Ok(index as usize),
+                            index => Err(LoaderError::Face(format!("{} is not a vertex index", index)))
+                        }).collect::<Result<Vec<usize>, LoaderError>>()?;
+                        if vertices.len() < 3 {
+                            return Err(LoaderError::Face("Received fewer than three indices".to_string()));
+                        }
+                        if let Some(index) = vertices.iter().find(|&&index| index >= obj.vertices.len()) {
+                            return Err(LoaderError::Face(format!("{} refers to vertex outside of current range", index)));
+                        }
+
+                        // Vertices' other attributes go with them
+                        for i in 1..vertices.len() - 1 {
+                            let corners = Loader::fan(&vertices, i);
+                            obj.faces.push(Face {
+                                polygon: Polygon::Tri,
+                                texcoords: if has_texcoords { Some(corners.clone()) } else { None },
+                                normals: if has_normals { Some(corners.clone()) } else { None },
+                                vertices: corners,
+                                group: None,
+                                material: None
+                            });
+                        }
                     }
                 },
-                _ => continue
+                // Elements without properties take up no room, so there's nothing to skip
+                _ if element.properties.is_empty() => {},
+                _ => {
+                    for _ in 0..element.count {
+                        body.element(element)?;
+                    }
+                }
+            }
+        }
+
+        Ok(obj)
+    }
+
+    /// Reads the header of a PLY file, which says how its body is stored and what's in it, and
+    /// finds where the body starts
+    fn ply_header(filepath: &Path, bytes: &[u8]) -> Result<(PlyFormat, Vec<PlyElement>, usize), LoaderError> {
+        let mut format = None;
+        let mut elements = vec![];
+        let (mut position, mut number) = (0, 0);
+        loop {
+            number += 1;
+            let end = bytes[position..].iter().position(|&byte| byte == b'\n')
+                .ok_or_else(|| LoaderError::Ply("Header never ends".to_string()))?;
+            let line = String::from_utf8_lossy(&bytes[position..position + end]);
+            position += end + 1;
+
+            let data: Vec<&str> = line.split_whitespace().collect();
+            let at_line = |err| LoaderError::Line(filepath.to_path_buf(), number, Box::new(err));
+            if number == 1 {
+                if data != ["ply"] {
+                    return Err(at_line(LoaderError::Ply("Not a PLY file".to_string())));
+                }
+            } else if data.first() == Some(&"end_header") {
+                let format = format.ok_or_else(|| at_line(LoaderError::Ply("No format given".to_string())))?;
+                return Ok((format, elements, position));
+            } else {
+                Loader::ply_header_line(&data, &mut format, &mut elements).map_err(at_line)?;
             }
         }
+    }
+
+    fn ply_header_line(data: &[&str], format: &mut Option<PlyFormat>, elements: &mut Vec<PlyElement>)
+        -> Result<(), LoaderError>
+    {
+        let ply_type = |name: &str| PlyType::from_name(name)
+            .ok_or_else(|| LoaderError::Ply(format!("Unknown type {}", name)));
+
+        match data {
+            ["format", kind, _] => *format = Some(match *kind {
+                "ascii" => PlyFormat::Ascii,
+                "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                _ => return Err(LoaderError::Ply(format!("Unknown format {}", kind)))
+            }),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: vec![]
+            }),
+            ["property", rest @ ..] => {
+                let property = match rest {
+                    ["list", count_type, ty, name] => PlyProperty::List(name.to_string(), ply_type(count_type)?, ply_type(ty)?),
+                    [ty, name] => PlyProperty::Scalar(name.to_string(), ply_type(ty)?),
+                    _ => return Err(LoaderError::Ply("Malformed property".to_string()))
+                };
+                match elements.last_mut() {
+                    Some(element) => element.properties.push(property),
+                    None => return Err(LoaderError::Ply("Property comes before any element".to_string()))
+                }
+            },
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            _ => return Err(LoaderError::Ply(format!("Unexpected {}", data[0])))
+        }
+
+        Ok(())
+    }
+
+    /// Reads the materials in a Wavefront .mtl file, by name. Texture maps are found relative to
+    /// the file.
+    pub fn load_mtl(filepath: &Path) -> Result<HashMap<String, Mtl>, LoaderError> {
+        let lines = read_lines(filepath)?;
+        let dir = filepath.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut materials = HashMap::new();
+        let mut current: Option<(String, Mtl)> = None;
+        for (number, line) in lines.enumerate() {
+            let line = line?;
+            let data = Loader::statement(&line);
+            if data.len() <= 1 { continue; }
+
+            let at_line = |err| LoaderError::Line(filepath.to_path_buf(), number + 1, Box::new(err));
+            if data[0] == "newmtl" {
+                // Starting a new material finishes the last one
+                if let Some((name, mtl)) = current.take() {
+                    materials.insert(name, mtl);
+                }
+                current = Some((data[1..].join(" "), Mtl::default()));
+                continue;
+            }
+
+            match &mut current {
+                Some((_, mtl)) => Loader::read_mtl(mtl, &data, dir).map_err(at_line)?,
+                None => return Err(at_line(LoaderError::Material(format!("{} comes before any newmtl", data[0]))))
+            }
+        }
+
+        if let Some((name, mtl)) = current {
+            materials.insert(name, mtl);
+        }
+        Ok(materials)
+    }
+
+    fn read_mtl(mtl: &mut Mtl, data: &[&str], dir: &Path) -> Result<(), LoaderError> {
+        // Maps of numbers are read by their brightness
+        let scalar_map = |data: &[&str]| -> Result<TextureRef<f64>, LoaderError> {
+            Ok(Arc::new(Luminance(Arc::new(Loader::mtl_map(data, dir, false)?.0))))
+        };
+
+        match data[0] {
+            "Kd" => mtl.diffuse = Loader::mtl_color(data)?,
+            "Ks" => mtl.specular = Loader::mtl_color(data)?,
+            "Ke" => mtl.emission = Loader::mtl_color(data)?,
+            "Tf" => mtl.transmission = Loader::mtl_color(data)?,
+            "Ns" => mtl.shininess = data[1].parse()?,
+            "Ni" => mtl.ior = Some(data[1].parse()?),
+            "d" => mtl.dissolve = data[1].parse()?,
+            "Tr" => mtl.dissolve = 1.0 - data[1].parse::<f64>()?,
+            "illum" => mtl.illum = data[1].parse()?,
+            "Pr" => mtl.roughness = Some(Arc::new(data[1].parse::<f64>()?)),
+            "Pm" => mtl.metallic = Some(Arc::new(data[1].parse::<f64>()?)),
+            "map_Kd" => mtl.diffuse_map = Some(Arc::new(Loader::mtl_map(data, dir, true)?.0)),
+            "map_Pr" => mtl.roughness = Some(scalar_map(data)?),
+            "map_Pm" => mtl.metallic = Some(scalar_map(data)?),
+            "bump" | "map_Bump" | "map_bump" => {
+                let (map, height) = Loader::mtl_map(data, dir, false)?;
+                mtl.bump = Some((Arc::new(Luminance(Arc::new(map))), height.unwrap_or(Mtl::BUMP_HEIGHT)));
+            },
+            "norm" | "map_Kn" => mtl.normal_map = Some(Arc::new(Loader::mtl_map(data, dir, false)?.0)),
+            _ => ()
+        }
+
+        Ok(())
+    }
+
+    /// The `i`th triangle of a fan splitting up a polygon, from its first corner. The corners keep
+    /// the polygon's counter-clockwise order, which `Triangle` takes to face outward.
+    fn fan(corners: &[usize], i: usize) -> Vec<usize> {
+        vec![corners[0], corners[i], corners[i + 1]]
+    }
+
+    /// Splits a line into its words, leaving out comments
+    fn statement(line: &str) -> Vec<&str> {
+        let line = match line.find('#') {
+            Some(start) => &line[..start],
+            None => line
+        };
+        line.split_whitespace().collect()
+    }
+
+    /// An .mtl color, whose green and blue default to its red
+    fn mtl_color(data: &[&str]) -> Result<ColorRGB, LoaderError> {
+        if data[1] == "spectral" || data[1] == "xyz" {
+            return Err(LoaderError::Material(format!("{} colors aren't supported", data[1])));
+        }
+
+        let r: f64 = data[1].parse()?;
+        let g: f64 = match data.get(2) { Some(g) => g.parse()?, None => r };
+        let b: f64 = match data.get(3) { Some(b) => b.parse()?, None => r };
+        Ok(ColorRGB::new(r, g, b))
+    }
+
+    /// Loads a texture map named at the end of an .mtl statement, along with the height its
+    /// `-bm` option gives, if any. Other options are skipped over.
+    fn mtl_map(data: &[&str], dir: &Path, srgb: bool) -> Result<(Image, Option<f64>), LoaderError> {
+        let file = data[data.len() - 1];
+        let height = match data.iter().position(|&option| option == "-bm") {
+            Some(index) if index + 2 < data.len() => Some(data[index + 1].parse()?),
+            _ => None
+        };
+
+        Ok((Image::load(&dir.join(file), srgb)?, height))
+    }
+
+    /// Reorders a polygon's indices into some attribute like its vertices', if every corner has
+    /// one
+    fn corner_indices(indices: Vec<usize>, corners: usize, what: &str) -> Result<Option<Vec<usize>>, LoaderError> {
+        match indices.len() {
+            0 => Ok(None),
+            count if count == corners => Ok(Some(indices)),
+            _ => Err(LoaderError::Face(format!("Only some corners have {}", what)))
+        }
+    }
+
+    /// Turns one of an .obj file's indices, which count from 1 or back from the end when negative,
+    /// into an index into a list of `count` things
+    fn obj_index(index: &str, count: usize) -> Result<usize, LoaderError> {
+        let index: i64 = index.parse()?;
+        let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+        if resolved < 0 || resolved as usize >= count {
+            return Err(LoaderError::Face(format!("{} refers to something outside of current range", index)));
+        }
+
+        Ok(resolved as usize)
+    }
+}
+
+/// How a PLY file's body is stored
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian
+}
+
+/// The kinds of number a PLY property can hold
+#[derive(Clone, Copy, Debug)]
+enum PlyType {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64
+}
+
+#[derive(Debug)]
+enum PlyProperty {
+    Scalar(String, PlyType),
+    /// A list of values, preceded by how many there are
+    List(String, PlyType, PlyType)
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>
+}
+
+/// Reads the numbers in the body of a PLY file one after another
+struct PlyBody<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    format: PlyFormat
+}
+
+impl PlyType {
+    fn from_name(name: &str) -> Option<PlyType> {
+        match name {
+            "char" | "int8" => Some(PlyType::Int8),
+            "uchar" | "uint8" => Some(PlyType::UInt8),
+            "short" | "int16" => Some(PlyType::Int16),
+            "ushort" | "uint16" => Some(PlyType::UInt16),
+            "int" | "int32" => Some(PlyType::Int32),
+            "uint" | "uint32" => Some(PlyType::UInt32),
+            "float" | "float32" => Some(PlyType::Float32),
+            "double" | "float64" => Some(PlyType::Float64),
+            _ => None
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::Int8 | PlyType::UInt8 => 1,
+            PlyType::Int16 | PlyType::UInt16 => 2,
+            PlyType::Int32 | PlyType::UInt32 | PlyType::Float32 => 4,
+            PlyType::Float64 => 8
+        }
+    }
+
+    /// Colors stored as integers run up to the largest one that fits, and as floats up to 1
+    fn color_scale(self) -> f64 {
+        match self {
+            PlyType::UInt8 | PlyType::Int8 => 1.0 / 255.0,
+            PlyType::UInt16 | PlyType::Int16 => 1.0 / 65535.0,
+            _ => 1.0
+        }
+    }
+}
+
+impl PlyProperty {
+    fn name(&self) -> &str {
+        match self {
+            PlyProperty::Scalar(name, _) | PlyProperty::List(name, _, _) => name
+        }
+    }
+}
+
+impl<'a> PlyBody<'a> {
+    fn read(&mut self, ty: PlyType) -> Result<f64, LoaderError> {
+        if self.format == PlyFormat::Ascii {
+            let rest = &self.bytes[self.position..];
+            let start = rest.iter().position(|byte| !byte.is_ascii_whitespace())
+                .ok_or_else(|| LoaderError::Ply("File ends early".to_string()))?;
+            let length = rest[start..].iter().position(u8::is_ascii_whitespace).unwrap_or(rest.len() - start);
+            self.position += start + length;
+            let word = String::from_utf8_lossy(&rest[start..start + length]);
+            return Ok(word.parse()?);
+        }
+
+        let size = ty.size();
+        if self.position + size > self.bytes.len() {
+            return Err(LoaderError::Ply("File ends early".to_string()));
+        }
+        // Put the bytes in little-endian order, whatever the file's
+        let mut bytes = [0; 8];
+        bytes[..size].copy_from_slice(&self.bytes[self.position..self.position + size]);
+        if self.format == PlyFormat::BinaryBigEndian {
+            bytes[..size].reverse();
+        }
+        self.position += size;
+
+        Ok(match ty {
+            PlyType::Int8 => f64::from(bytes[0] as i8),
+            PlyType::UInt8 => f64::from(bytes[0]),
+            PlyType::Int16 => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])),
+            PlyType::UInt16 => f64::from(u16::from_le_bytes([bytes[0], bytes[1]])),
+            PlyType::Int32 => f64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            PlyType::UInt32 => f64::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            PlyType::Float32 => f64::from(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            PlyType::Float64 => f64::from_le_bytes(bytes)
+        })
+    }
+
+    /// Reads a whole element, scalars and lists alike
+    fn element(&mut self, element: &PlyElement) -> Result<Vec<Vec<f64>>, LoaderError> {
+        element.properties.iter().map(|property| match property {
+            PlyProperty::Scalar(_, ty) => Ok(vec![self.read(*ty)?]),
+            PlyProperty::List(_, count_type, ty) => {
+                let count = match self.read(*count_type)? {
+                    count if count >= 0.0 && count.fract() == 0.0 => count as usize,
+                    count => return Err(LoaderError::Ply(format!("{} is not a list length", count)))
+                };
+                (0..count).map(|_| self.read(*ty)).collect()
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::geom::hit::Hittable;
+    use crate::geom::objects::Object;
+    use crate::material::LobeFlags;
+    use crate::math::Rand;
+    use crate::vec::Ray;
+
+    #[test]
+    fn reads_texture_coordinates() {
+        let path = std::env::temp_dir().join(format!("raytracer-loader-{}.obj", std::process::id()));
+        fs::write(&path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0.5 1\nvn 0 0 1\n\
+            f 1/1 2/2 3/3\nf -3//1 -2//1 -1//1\nf 1/1/1 2/-2/1 3/-1/1\n").unwrap();
+        let obj = Loader::load_obj(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(obj.texcoords, vec![(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)]);
+        assert_eq!(obj.faces.len(), 3);
+        assert_eq!(obj.faces[0].vertices, vec![0, 1, 2]);
+        assert_eq!(obj.faces[0].texcoords, Some(vec![0, 1, 2]));
+        assert_eq!(obj.faces[1].vertices, vec![0, 1, 2]);
+        assert_eq!(obj.faces[1].texcoords, None);
+        assert_eq!(obj.faces[2].texcoords, Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn reads_and_averages_normals() {
+        let path = std::env::temp_dir().join(format!("raytracer-loader-normals-{}.obj", std::process::id()));
+        // Two faces folded at a right angle along the x axis, small like a scanned model's
+        fs::write(&path, "v 0 0 0\nv 0.001 0 0\nv 0 0.001 0\nv 0 0 0.001\nvn 0 0 1\nvn 0 1 0\n\
+            f 1//1 2//1 3//1\nf 1 4 2\n").unwrap();
+        let obj = Loader::load_obj(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(obj.normals, vec![Vec3::K, Vec3::J]);
+        assert_eq!(obj.faces[0].normals, Some(vec![0, 0, 0]));
+        assert_eq!(obj.faces[1].normals, None);
+
+        // Vertices on the fold take a bit of both faces, the rest just their own face's normal
+        let normals = obj.vertex_normals();
+        let face_normals = (Vec3::K, Vec3::J);
+        assert_eq!(normals[0], (&face_normals.0 + &face_normals.1).unit());
+        assert_eq!(normals[1], (&face_normals.0 + &face_normals.1).unit());
+        assert_eq!(normals[2], face_normals.0);
+        assert_eq!(normals[3], face_normals.1);
+    }
+
+    #[test]
+    fn counter_clockwise_faces_face_outward() {
+        let path = std::env::temp_dir().join(format!("raytracer-loader-cube-{}.obj", std::process::id()));
+        fs::write(&path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+            f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 4 8 7 3\nf 1 5 8 4\nf 2 3 7 6\n").unwrap();
+        let cube = Object::new(Point3::O, 1.0, vec![], &path, Arc::new(DiffuseLambert::new(colors::WHITE)));
+        fs::remove_file(&path).unwrap();
+        let cube = cube.unwrap();
+
+        let mut rand = Rand::seeded(0);
+        for side in &[Vec3::I, -Vec3::I, Vec3::J, -Vec3::J, Vec3::K, -Vec3::K] {
+            let origin = 3.0 * side;
+            let ray = Ray::new(&origin, &(Point3::new(0.1, -0.2, 0.15) - &origin));
+            let hit = cube.is_hit(&ray, 0.0, f64::INFINITY, &mut rand).unwrap();
+            assert!(hit.outer, "hit the {:?} side from inside", side);
+            assert_eq!(hit.geometric_normal, side.clone());
+        }
+    }
+
+    /// Writes `files` into a fresh directory for a test to load from
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-loader-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn splits_polygons_into_triangles() {
+        let dir = write_files("polygons", &[("shapes.obj", "\
+            v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 0.5 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+            f 1/1 2/2 3/3 4/4   # a quad\n\
+            f 1 2 3 4 5\n")]);
+        let obj = Loader::load_obj(&dir.join("shapes.obj")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let vertices: Vec<_> = obj.faces.iter().map(|face| face.vertices.clone()).collect();
+        assert_eq!(vertices, vec![
+            vec![0, 1, 2], vec![0, 2, 3],
+            vec![0, 1, 2], vec![0, 2, 3], vec![0, 3, 4]
+        ]);
+        assert_eq!(obj.faces[1].texcoords, Some(vec![0, 2, 3]));
+        assert_eq!(obj.faces[4].texcoords, None);
+    }
+
+    #[test]
+    fn reads_groups_and_materials() {
+        let dir = write_files("materials", &[
+            ("scene.obj", "mtllib scene.mtl\n\
+                v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                f 1 2 3\n\
+                o lamp\nusemtl light\nf 1 2 3\n\
+                g body\nusemtl glass\nf 1 2 3\n\
+                usemtl painted\nf 1 2 3\n"),
+            ("scene.mtl", "# Materials for scene.obj\n\
+                newmtl light\nKe 4 4 3\n\
+                newmtl glass\nKd 0 0 0\nNi 1.45\nd 0.1\n\
+                newmtl painted\nKd 0.6 0.1 0.1\nKs 0.5\nNs 250\nillum 2\n\
+                newmtl chrome\nKd 0 0 0\nKs 0.9 0.9 0.9\nNs 800\n\
+                newmtl chalk\nKd 0.9 0.9 0.9\n")
+        ]);
+        let obj = Loader::load_obj(&dir.join("scene.obj")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(obj.groups, vec!["lamp".to_string(), "body".to_string()]);
+        let groups: Vec<_> = obj.faces.iter().map(|face| face.group).collect();
+        assert_eq!(groups, vec![None, Some(0), Some(1), Some(1)]);
+        let materials: Vec<_> = obj.faces.iter().map(|face| face.material.as_deref()).collect();
+        assert_eq!(materials, vec![None, Some("light"), Some("glass"), Some("painted")]);
+
+        assert_eq!(obj.materials.len(), 5);
+        assert_eq!(obj.materials["painted"].specular, ColorRGB::new(0.5, 0.5, 0.5));
+        assert!(obj.materials["light"].material().is_emissive());
+        assert!(obj.materials["glass"].material().flags().contains(LobeFlags::TRANSMISSION));
+        let painted = obj.materials["painted"].material().flags();
+        assert!(painted.contains(LobeFlags::DIFFUSE) && painted.contains(LobeFlags::GLOSSY));
+        assert!(!obj.materials["chrome"].material().flags().contains(LobeFlags::DIFFUSE));
+        assert!(!obj.materials["chalk"].material().flags().contains(LobeFlags::GLOSSY));
+    }
+
+    #[test]
+    fn errors_say_where() {
+        let dir = write_files("errors", &[
+            ("bad.obj", "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n"),
+            ("bad-mtl.obj", "mtllib bad.mtl\n"),
+            ("bad.mtl", "newmtl red\nKd 1 zero 0\n")
+        ]);
+        let face = Loader::load_obj(&dir.join("bad.obj"));
+        let mtl = Loader::load_obj(&dir.join("bad-mtl.obj"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(face, Err(LoaderError::Line(path, 4, err))
+            if path.ends_with("bad.obj") && matches!(*err, LoaderError::Face(_))));
+        // A mistake in a material library is reported against the library, inside the model
+        match mtl {
+            Err(LoaderError::Line(_, 1, err)) => assert!(matches!(*err, LoaderError::Line(path, 2, ref inner)
+                if path.ends_with("bad.mtl") && matches!(**inner, LoaderError::ParseFloat(_)))),
+            _ => panic!("Expected an error in the material library")
+        }
+    }
+
+    /// A square with colored corners and texture coordinates, and an element that isn't needed
+    const PLY_HEADER: &str = "\
+        element vertex 4\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property float nx\nproperty float ny\nproperty float nz\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        property float s\nproperty float t\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        element edge 1\n\
+        property int vertex1\nproperty int vertex2\n\
+        end_header\n";
+
+    fn check_square(obj: &Obj) {
+        assert_eq!(obj.vertices, vec![
+            Point3::O, Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 1.0, 0.0), Point3::new(0.0, 1.0, 0.0)
+        ]);
+        assert_eq!(obj.normals, vec![Vec3::K; 4]);
+        assert_eq!(obj.colors[1], ColorRGB::new(1.0, 0.0, 0.0));
+        assert_eq!(obj.colors[3], ColorRGB::new(0.0, 0.0, 1.0));
+        assert_eq!(obj.texcoords[2], (1.0, 1.0));
+
+        let vertices: Vec<_> = obj.faces.iter().map(|face| face.vertices.clone()).collect();
+        assert_eq!(vertices, vec![vec![0, 1, 2], vec![0, 2, 3]]);
+        assert_eq!(obj.faces[1].normals, Some(vec![0, 2, 3]));
+        assert_eq!(obj.faces[1].texcoords, Some(vec![0, 2, 3]));
+    }
+
+    #[test]
+    fn reads_ply_files() {
+        let vertices: [[f64; 11]; 4] = [
+            [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 255.0, 255.0, 255.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 255.0, 0.0, 0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 255.0, 0.0, 1.0, 1.0],
+            [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 255.0, 0.0, 1.0]
+        ];
+
+        let mut ascii = format!("ply\nformat ascii 1.0\ncomment made by hand\n{}", PLY_HEADER);
+        for vertex in &vertices {
+            let words: Vec<String> = vertex.iter().map(|value| value.to_string()).collect();
+            ascii += &format!("{}\n", words.join(" "));
+        }
+        ascii += "4 0 1 2 3\n0 1\n";
+
+        // The same square, stored in binary both ways round
+        let binary = |format: &str, big_endian: bool| {
+            let mut bytes = format!("ply\nformat {} 1.0\n{}", format, PLY_HEADER).into_bytes();
+            let order = |mut word: Vec<u8>| { if big_endian { word.reverse(); } word };
+            for vertex in &vertices {
+                for (i, &value) in vertex.iter().enumerate() {
+                    if (6..9).contains(&i) {
+                        bytes.push(value as u8);
+                    } else {
+                        bytes.extend(order((value as f32).to_le_bytes().to_vec()));
+                    }
+                }
+            }
+            bytes.push(4);
+            for index in 0..4_i32 {
+                bytes.extend(order(index.to_le_bytes().to_vec()));
+            }
+            bytes.extend(order(0_i32.to_le_bytes().to_vec()));
+            bytes.extend(order(1_i32.to_le_bytes().to_vec()));
+            bytes
+        };
+
+        let dir = write_files("ply", &[("ascii.ply", &ascii)]);
+        fs::write(dir.join("little.ply"), binary("binary_little_endian", false)).unwrap();
+        fs::write(dir.join("big.ply"), binary("binary_big_endian", true)).unwrap();
+        let models: Vec<_> = ["ascii.ply", "little.ply", "big.ply"].iter()
+            .map(|name| Loader::load_ply(&dir.join(name)))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        for model in models {
+            check_square(&model.unwrap());
+        }
+    }
+
+    #[test]
+    fn ply_errors_say_where() {
+        let dir = write_files("ply-errors", &[
+            ("type.ply", "ply\nformat ascii 1.0\nelement vertex 1\nproperty float128 x\nend_header\n"),
+            ("short.ply", "ply\nformat binary_little_endian 1.0\nelement vertex 1\n\
+                property float x\nproperty float y\nproperty float z\nend_header\n\0\0\0\0\0\0"),
+            ("index.ply", "ply\nformat ascii 1.0\nelement vertex 3\n\
+                property float x\nproperty float y\nproperty float z\n\
+                element face 2\nproperty list uchar float vertex_indices\nend_header\n\
+                0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n3 0 -1 2.5\n"),
+            ("length.ply", "ply\nformat ascii 1.0\nelement vertex 3\n\
+                property float x\nproperty float y\nproperty float z\n\
+                element face 2\nproperty list float uint vertex_indices\nend_header\n\
+                0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n2.5 0 1 2\n"),
+            ("count.ply", "ply\nformat binary_little_endian 1.0\nelement junk 18446744073709551615\n\
+                property uchar x\nend_header\n\0\0\0"),
+            // Elements without properties are skipped, however many there are
+            ("empty.ply", "ply\nformat ascii 1.0\nelement junk 18446744073709551615\nend_header\n")
+        ]);
+        let unknown_type = Loader::load_ply(&dir.join("type.ply"));
+        let too_short = Loader::load_ply(&dir.join("short.ply"));
+        let bad_index = Loader::load_ply(&dir.join("index.ply"));
+        let bad_length = Loader::load_ply(&dir.join("length.ply"));
+        let bad_count = Loader::load_ply(&dir.join("count.ply"));
+        let empty = Loader::load_ply(&dir.join("empty.ply"));
+        fs::remove_dir_all(&dir).unwrap();
 
-        Ok(Obj { vertices, indices })
+        assert!(matches!(unknown_type, Err(LoaderError::Line(_, 4, err)) if matches!(*err, LoaderError::Ply(_))));
+        assert!(matches!(too_short, Err(LoaderError::Ply(msg)) if msg.contains("ends early")));
+        assert!(matches!(bad_index, Err(LoaderError::Face(msg)) if msg.contains("-1")));
+        assert!(matches!(bad_length, Err(LoaderError::Ply(msg)) if msg.contains("2.5")));
+        assert!(matches!(bad_count, Err(LoaderError::Ply(msg)) if msg.contains("too short")));
+        assert!(empty.unwrap().vertices.is_empty());
     }
 }
@@ -1,502 +1,196 @@
-#![allow(unused)]
-use rand::prelude::Rng;
 use std::{
     f64::consts,
-    fs::{ File, create_dir_all },
-    io::prelude::Write,
-    path::Path,
+    fs::create_dir_all,
+    io,
+    path::{ Path, PathBuf },
+    process,
     sync::Arc
 };
 
 use raytracer::{
     camera::Camera,
-    geom::{
-        World,
-        hit::{ Bounded, HittableRefs },
-        primitives::{ Plane, Sphere, Triangle },
-        objects::{ Icosahedron, Prism, Object, Volume }
-    },
-    vec::{ colors, ColorRGB, Point3, Vec3 },
-    math,
-    material
+    checkpoint,
+    geom::World,
+    output::{ self, ImageFormat },
+    sampler::SamplerKind,
+    scene::{ self, Scene },
+    vec::{ Coord, Point3 },
+    Accumulator,
+    Framebuffer,
+    ImageConfig,
+    RenderError
 };
 
-enum RenderType {
-    Static,
-    Animated
-}
+mod cli;
+use cli::{ Command, Mode, Options };
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let render_opt =
-        if args.len() < 2 { 0 } else { args[1].parse().unwrap_or(1) };
-
-    match render_opt {
-        1 => {
-            eprintln!("Rendering scene 1:");
-            let render_type =
-                if args.len() < 3 { RenderType::Static }
-                else {
-                    match &args[2][..] {
-                        "animate" => RenderType::Animated,
-                        "static" | _ => RenderType::Static
-                    }
-                };
-            render_scene_1(render_type);
-        },
-        2 => {
-            eprintln!("Rendering scene 2.");
-            render_scene_2();
-        },
-        3 => {
-            eprintln!("Rendering scene 3.");
-            render_scene_3();
-        },
-        4 => {
-            eprintln!("Rendering scene 4.");
-            render_scene_4();
+    let options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Render(options)) => *options,
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            return;
         },
-        5 => {
-            eprintln!("Rendering scene 5.");
-            render_scene_5();
-        },
-        _ => {
-            eprintln!("{} is not a valid scene number.", render_opt);
-            std::process::exit(1);
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
         }
     };
 
+    if let Err(err) = run(options) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
 }
 
-fn render_scene_1(render_type: RenderType) {
-    let out_width = 640;
-    let out_height = 480;
-    let fov_deg = 30.0;
-    let aperture = 0.0;
-    let samples = 100;
-    let max_depth = 50;
-
-    let background = |t| {
-        math::lerp(colors::SKYBLUE, colors::WHITE, t)
+fn run(options: Options) -> Result<(), String> {
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None if options.animate.is_some() => PathBuf::from("frames"),
+        None => {
+            let stem = options.scene.file_stem().unwrap_or_else(|| "out".as_ref());
+            let extension = options.format.unwrap_or(ImageFormat::Png).extension();
+            PathBuf::from(format!("{}.{}", stem.to_string_lossy(), extension))
+        }
     };
-    let config = raytracer::ImageConfig {
-        width: out_width, height: out_height,
-        samples: samples, max_depth: max_depth,
-        background: Arc::new(background)
+    let format = match options.format {
+        Some(format) => format,
+        None if options.animate.is_some() => ImageFormat::Png,
+        None => ImageFormat::from_path(&output).ok_or_else(|| format!(
+            "Can't tell the output format of {} from its extension; use --format", output.display()
+        ))?
     };
 
-    let mat_dif_soft_blue = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.3, 0.5, 0.8)));
-    let mat_dif_soft_red = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.8, 0.3, 0.4)));
-    let mat_dif_soft_gray = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.8, 0.8, 0.8)));
-    let mat_glass_white = Arc::new(material::Translucent::new(ColorRGB::new(1.0, 1.0, 1.0), 1.52, 0.0));
-    let mat_air = Arc::new(material::Translucent::new(ColorRGB::new(1.0, 1.0, 1.0), 0.0, 0.0));
-    let mat_metal_rough_soft_green = Arc::new(material::Reflective::new(ColorRGB::new(0.6, 0.8, 0.3), 0.3));
+    let checkpointing = options.checkpoint.is_some() || options.resume.is_some();
+    if checkpointing && options.mode != Mode::Progressive {
+        return Err("Checkpoints are only written in progressive mode; use --mode progressive".to_string());
+    }
+    if checkpointing && options.animate.is_some() {
+        return Err("Animations can't be checkpointed".to_string());
+    }
+
+    eprintln!("Rendering {}:", options.scene.display());
+    let Scene { world, mut camera, mut config } = scene::load(&options.scene)
+        .map_err(|err| format!("{}: {}", options.scene.display(), err))?;
 
-    let ground = Plane::new(
-        Point3::new(0.0, -0.5, -1.0), 
-        (100.0 * Vec3::I, 100.0 * Vec3::K),
-        mat_dif_soft_blue.clone()
-    );
-    let glass_ball = Sphere::new(Point3::new(-0.27, -0.1, -0.8), 0.4, mat_glass_white.clone());
-    let green_metal_ball = Sphere::new(Point3::new(0.0, 0.0, -1.5), 0.5, mat_metal_rough_soft_green.clone());
-    let gray_plane = Plane::new(
-        Point3::new(-0.5, 0.8, -2.5),
-        (0.25 * (Vec3::I - Vec3::K), 0.25 * (Vec3::I + Vec3::J)),
-        mat_dif_soft_gray.clone()
-    );
-    let red_ball = Sphere::new(Point3::new(0.6, -0.2, -1.0), 0.3, mat_dif_soft_red.clone());
-    let air_ball = Sphere::new(Point3::new(0.0, 0.0, -1.5), 0.5, mat_dif_soft_gray.clone());
-    let blue_volume_ball = Volume::new(Arc::new(air_ball), 1.0, mat_dif_soft_red.clone());
+    if options.width.is_some() || options.height.is_some() {
+        config.width = options.width.unwrap_or(config.width);
+        config.height = options.height.unwrap_or(config.height);
+        camera = camera.with_resolution(config.width, config.height);
+    }
+    config.samples = options.samples.unwrap_or(config.samples);
+    config.max_depth = options.max_depth.unwrap_or(config.max_depth);
+    config.threads = options.threads.unwrap_or(config.threads);
+    config.seed = options.seed.unwrap_or(config.seed);
+    config.sampler = options.sampler.unwrap_or(config.sampler);
+    config.tile_size = options.tile_size.unwrap_or(config.tile_size);
+    config.tile_order = options.tile_order.unwrap_or(config.tile_order);
+    config.tone_map.operator = options.tonemap.unwrap_or(config.tone_map.operator);
+    config.tone_map.exposure = options.exposure.unwrap_or(config.tone_map.exposure);
+    config.tone_map.white_point = options.white_point.or(config.tone_map.white_point);
+
+    let tone_flags = options.tonemap.is_some() || options.exposure.is_some() || options.white_point.is_some();
+    if format.is_hdr() && tone_flags {
+        eprintln!("warning: {} images store linear radiance, so tone mapping options have no effect",
+            format.extension());
+    }
 
-    let world = World::new(vec![
-        Arc::new(ground),
-        Arc::new(red_ball),
-        Arc::new(glass_ball),
-        Arc::new(green_metal_ball),
-        Arc::new(gray_plane)
-    ]);
+    let resumed = match &options.resume {
+        Some(path) => {
+            let checkpoint = checkpoint::load(path)
+                .map_err(|err| format!("Could not resume from {}: {}", path.display(), err))?;
 
+            // Carrying on with the checkpoint's seed gives the same image as an uninterrupted render
+            if options.seed.is_some_and(|seed| seed != checkpoint.seed) {
+                eprintln!("warning: continuing with the seed {} was started with, so --seed has no effect",
+                    path.display());
+            }
+            config.seed = checkpoint.seed;
 
-    match render_type {
-        RenderType::Static => {
-            let camera =
-                Camera::new(Point3::new(0.7, -0.3, 3.0), &Point3::new(0.0, 0.0, -1.0),
-                    fov_deg, aperture, out_width, out_height);
+            if options.sampler.is_some_and(|sampler| sampler != checkpoint.sampler) {
+                eprintln!("warning: continuing with the sampler {} was started with, so --sampler has no \
+                    effect", path.display());
+            }
+            config.sampler = checkpoint.sampler;
+
+            // The stratified sampler's strata are laid out for the original number of samples, so
+            // adding more would leave them unevenly covered
+            if config.sampler == SamplerKind::Stratified && config.samples != checkpoint.samples {
+                eprintln!("warning: stratified renders can't change their number of samples, so {} keeps {}",
+                    path.display(), checkpoint.samples);
+                config.samples = checkpoint.samples;
+            }
+            Some(checkpoint.accumulator)
+        },
+        None => None
+    };
 
-            // Single-threaded
-            // let mut file = File::create(&Path::new("temp.ppm")).unwrap();
-            // file.write_all(raytracer::create_ppm(&world, &camera, &config).as_bytes()).unwrap();
+    let world = Arc::new(world);
+    let config = Arc::new(config);
 
-            // Multi-threaded
-            let world_arc = Arc::new(world);
-            let camera_arc = Arc::new(camera);
-            let config_arc = Arc::new(config);
-            raytracer::write_ppm_threaded(world_arc, camera_arc, "temp.ppm", config_arc);
+    match options.animate {
+        None => render(&world, camera, &config, &output, format, &options, resumed),
+        Some(frames) => {
+            // Pivot the camera about the point it's looking at, keeping its height
+            let look_at = camera.look_at().clone();
+            let offset = camera.location() - &look_at;
+            let dist = (offset[Coord::X].powi(2) + offset[Coord::Z].powi(2)).sqrt();
 
-            // Uncomment to watch render live
-            // raytracer::write_ppm(&world, &camera, "temp.ppm", &config);
-        },
-        RenderType::Animated => {
-            let frames = 120;
-            let look_at = Point3::new(0.0, 0.0, -1.0);
-            create_dir_all("frames/").unwrap();
-            let world_arc = Arc::new(world);
-            let config_arc = Arc::new(config);
+            create_dir_all(&output).map_err(|err| format!("{}: {}", output.display(), err))?;
             for i in 0..frames {
                 eprintln!("Frame {}:", i+1);
-                let frame_name = format!("frames/frame{}.ppm", i+1);
+                let frame_path = output.join(format!("frame{}.{}", i+1, format.extension()));
 
-                let dist = 3.0 * consts::SQRT_2;
                 let angle = f64::from(i) * 2.0 * consts::PI / f64::from(frames);
-                let camera =
-                    Camera::new(&look_at + Point3::new(dist * angle.cos(), 0.4, dist * angle.sin()),
-                        &look_at, fov_deg, aperture, out_width, out_height);
-
-
-                // Single-threaded
-                // let path = Path::new(&frame_name);
-                // let mut file = File::create(&path).unwrap();
-                // file.write_all(raytracer::create_ppm(&world, &camera, &config).as_bytes()).unwrap();
-
-                // Multi-threaded
-                let camera_arc = Arc::new(camera);
-                raytracer::write_ppm_threaded(
-                    world_arc.clone(), camera_arc.clone(), &frame_name, config_arc.clone()
+                let frame_camera = camera.with_location(
+                    &look_at + Point3::new(dist * angle.cos(), offset[Coord::Y], dist * angle.sin())
                 );
 
-                // Uncomment to watch render live
-                // raytracer::write_ppm(&world, &camera, &frame_name, &config);
+                render(&world, frame_camera, &config, &frame_path, format, &options, None)?;
             }
-        }
-    };
-}
-
-// Cornell box
-fn render_scene_2() {
-    let out_width = 512;
-    let out_height = 512;
-    let fov_deg = 37.0;
-    let aperture = 0.0;
-    let samples = 10000;
-    let max_depth = 50;
-
-    let background = |_| {
-        colors::BLACK
-    };
-    let config = raytracer::ImageConfig {
-        width: out_width, height: out_height,
-        samples: samples, max_depth: max_depth,
-        background: Arc::new(background)
-    };
-    let camera =
-        Camera::new(Point3::new(278.0, 273.0, -800.0), &Point3::new(278.0, 273.0, 0.0),
-            fov_deg, aperture, out_width, out_height);
-
-    let mat_dif_white = Arc::new(material::DiffuseLambert::new(ColorRGB::new(1.0, 1.0, 1.0)));
-    let mat_mirror = Arc::new(material::Reflective::new(ColorRGB::new(1.0, 1.0, 1.0), 0.0));
-    let mat_metal_blue = Arc::new(material::Reflective::new(ColorRGB::new(0.3, 0.7, 0.8), 0.4));
-    let mat_glass_white = Arc::new(material::Translucent::new(ColorRGB::new(1.0, 1.0, 1.0), 1.52, 0.0));
-    let mat_dif_red = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.57, 0.025, 0.025)));
-    let mat_dif_green = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.025, 0.236, 0.025)));
-    let mat_dif_lavender = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.776, 0.564, 0.976)));
-    let mat_light = Arc::new(material::Emissive::new(ColorRGB::new(1.0, 0.67, 0.21), 16.3));
-
-    let floor = Plane::new(
-        Point3::new(278.0, 0.0, 279.6),
-        (Point3::new(-278.0, 0.0, 0.0), Point3::new(0.0, 0.0, 279.6)),
-        mat_dif_white.clone()
-    );
-    let ceiling = Plane::new(
-        Point3::new(278.0, 548.8, 279.6),
-        (Point3::new(278.0, 0.0, 0.0), Point3::new(0.0, 0.0, 279.6)),
-        mat_dif_white.clone()
-    );
-    let back_wall = Plane::new(
-        Point3::new(278.0, 274.4, 559.2),
-        (Point3::new(-278.0, 0.0, 0.0), Point3::new(0.0, 274.4, 0.0)),
-        mat_dif_white.clone()
-    );
-    let right_wall = Plane::new(
-        Point3::new(0.0, 274.4, 279.6),
-        (Point3::new(0.0, 0.0, 279.6), Point3::new(0.0, -274.4, 0.0)),
-        mat_dif_green.clone()
-    );
-    let left_wall = Plane::new(
-        Point3::new(556.0, 274.4, 279.6),
-        (Point3::new(0.0, 0.0, 279.6), Point3::new(0.0, 274.4, 0.0)),
-        mat_dif_red.clone()
-    );
-
-    let light = Plane::new(
-        Point3::new(278.0, 548.7, 279.5),
-        (Point3::new(65.0, 0.0, 0.0), Point3::new(0.0, 0.0, 52.5)),
-        mat_light.clone()
-    );
-
-    let short_block = Prism::new(
-        Point3::new(185.0, 82.5, 168.5),
-        (Point3::new(80.0, 0.0, 24.0), Point3::new(0.0, 82.5, 0.0), Point3::new(24.0, 0.0, -80.0)),
-        mat_dif_white.clone()
-    );
-    let tall_block = Prism::new(
-        Point3::new(368.0, 165.0, 351.0),
-        (Point3::new(79.0, 0.0, -24.5), Point3::new(0.0, 165.0, 0.0), Point3::new(24.5, 0.0, 79.0)),
-        mat_dif_white.clone()
-    );
-    let small_glass_sphere = Sphere::new(
-        Point3::new(185.0, 82.5, 168.5),
-        82.5,
-        mat_glass_white.clone()
-    );
-    let tall_mirror_block = Prism::new(
-        Point3::new(368.0, 165.0, 351.0),
-        (Point3::new(79.0, 0.0, -24.5), Point3::new(0.0, 165.0, 0.0), Point3::new(24.5, 0.0, 79.0)),
-        mat_mirror.clone()
-    );
-
-    let lucy = Object::new(
-        Point3::new(368.0, 210.0, 351.0),
-        0.27,
-        vec![(90_f64.to_radians(), -Vec3::I), (15_f64.to_radians(), Vec3::J)],
-        &Path::new("models/lucy.obj"), mat_metal_blue.clone()
-    );
-    let bunny = Object::new(
-        Point3::new(185.0, 84.5, 168.5),
-        1100.0,
-        vec![(180_f64.to_radians(), Vec3::J)],
-        &Path::new("models/bunny.obj"), mat_dif_lavender.clone()
-    );
-
-    let cube = Prism::new(
-        Point3::new(278.0, 274.4, 279.6),
-        (Point3::new(-278.0, 0.0, 0.0), Point3::new(0.0, 274.2, 0.0), Point3::new(0.0, 0.0, 279.6)),
-        mat_dif_white.clone()
-    );
-    let volume = Volume::new(Arc::new(cube), 0.0005, mat_dif_white.clone());
-
-    let world = World::new(vec![
-        Arc::new(floor),
-        Arc::new(back_wall),
-        Arc::new(right_wall),
-        Arc::new(left_wall),
-        Arc::new(ceiling),
-        Arc::new(short_block),
-        Arc::new(tall_block),
-        Arc::new(light)
-    ]);
-
-    // Single-threaded
-    // let mut file = File::create(&Path::new("cornell.ppm")).unwrap();
-    // file.write_all(raytracer::create_ppm(&world, &camera, &config).as_bytes()).unwrap();
-
-    // Multi-threaded
-    let world_arc = Arc::new(world);
-    let camera_arc = Arc::new(camera);
-    let config_arc = Arc::new(config);
-    raytracer::write_ppm_threaded(world_arc, camera_arc, "cornell.ppm", config_arc);
 
-    // Uncomment to watch render live
-    // raytracer::write_ppm(&world, &camera, "cornell.ppm", &config);
-}
-
-fn render_scene_3() {
-    let out_width = 640;
-    let out_height = 480;
-    let fov_deg = 30.0;
-    let aperture = 0.1;
-    let samples = 100;
-    let max_depth = 50;
-
-    let background = |t| {
-        math::lerp(colors::SKYBLUE, colors::WHITE, t)
-    };
-    let config = raytracer::ImageConfig {
-        width: out_width, height: out_height,
-        samples: samples, max_depth: max_depth,
-        background: Arc::new(background)
-    };
-
-    let mat_dif_soft_blue = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.3, 0.5, 0.8)));
-    let materials = [
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.8, 0.3, 0.4))), // reddish
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.8, 0.6, 0.3))), // orangish
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.8, 0.8, 0.4))), // yellowish
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.5, 0.8, 0.3))), // greenish
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.3, 0.7, 0.8))), // bluish
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.4, 0.3, 0.8))), // indigoish
-        Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.6, 0.3, 0.8)))  // violetish
-    ];
-
-    let ground = Plane::new(
-        Point3::new(0.0, -0.5, -1.0), 
-        (100.0 * Vec3::I, 100.0 * Vec3::K),
-        mat_dif_soft_blue.clone()
-    );
-
-    let mut red_balls: HittableRefs = vec![Arc::new(ground)];
-    let mut rng = rand::thread_rng();
-    for i in 0..1000 {
-        let rand_x: f64 = -(rng.gen::<f64>() * 50.0) + 25.0;
-        let rand_z: f64 = -(rng.gen::<f64>() * 50.0);
-        let material = materials[i % materials.len()].clone();
-        let radius = 0.5 * rng.gen::<f64>() + 0.3;
-        red_balls.push(Arc::new(
-            Sphere::new(Point3::new(rand_x, -0.5 + radius, rand_z), radius, material)
-        ));
+            Ok(())
+        }
     }
-
-    let world = World::new(red_balls);
-
-    let camera =
-        Camera::new(Point3::new(0.7, 2.0, 3.0), &Point3::new(0.0, 0.0, -10.0),
-            fov_deg, aperture, out_width, out_height);
-
-    // Single-threaded
-    // let mut file = File::create(&Path::new("temp.ppm")).unwrap();
-    // file.write_all(raytracer::create_ppm(&world, &camera, &config).as_bytes()).unwrap();
-
-    // Multi-threaded
-    let world_arc = Arc::new(world);
-    let camera_arc = Arc::new(camera);
-    let config_arc = Arc::new(config);
-    raytracer::write_ppm_threaded(world_arc, camera_arc, "balls.ppm", config_arc);
-
-    // Uncomment to watch render live
-    // raytracer::write_ppm(&world, &camera, "temp.ppm", &config);
 }
 
-fn render_scene_4() {
-    let out_width = 640;
-    let out_height = 480;
-    let fov_deg = 60.0;
-    let aperture = 0.0;
-    let samples = 100;
-    let max_depth = 50;
-
-    let background = |t| {
-        math::lerp(colors::SKYBLUE, colors::WHITE, t)
-    };
-    let config = raytracer::ImageConfig {
-        width: out_width, height: out_height,
-        samples: samples, max_depth: max_depth,
-        background: Arc::new(background)
-    };
-
-    let mat_floor = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.3, 0.5, 0.8)));
-    let mat_dif = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.8, 0.3, 0.4)));
-    let mat_reflect = Arc::new(material::Reflective::new(ColorRGB::new(0.831, 0.686, 0.215), 0.35));
-    let mat_glass = Arc::new(material::Translucent::new(ColorRGB::new(1.0, 1.0, 1.0), 1.52, 0.0));
-    let mat_air = Arc::new(material::Translucent::new(ColorRGB::new(1.0, 1.0, 1.0), 0.0, 0.0));
-
-    let mat_light = Arc::new(material::Emissive::new(ColorRGB::new(1.0, 1.0, 1.0), 7.0));
-
-    let ground = Plane::new(
-        Point3::new(0.0, -0.5, 0.0),
-        (1000.0 * Vec3::I, 1000.0 * Vec3::K),
-        mat_floor.clone()
-    );
-    let light = Plane::new(
-        Point3::new(0.0, 14.0, 7.0),
-        (2.3 * Vec3::I, 2.3 * (Vec3::J - Vec3::K)),
-        mat_light.clone()
-    );
-
-    let size = 0.1;
-    let object = Object::new(
-        Point3::new(0.0, 7.0, 0.0),
-        size, vec![],
-        &Path::new("models/armadillo.obj"), mat_reflect.clone()
-    );
-
-    let camera_focus = object.bounding_box().center().clone();
-    let camera =
-        Camera::new(&camera_focus + Point3::new(10.0, 3.0, -20.0), &camera_focus,
-            fov_deg, aperture, out_width, out_height);
-
-    let world = World::new(vec![
-        Arc::new(ground),
-        Arc::new(object)
-    ]);
-
-    // Single-threaded
-    // let mut file = File::create(&Path::new("temp.ppm")).unwrap();
-    // file.write_all(raytracer::create_ppm(&world, &camera, &config).as_bytes()).unwrap();
-
-    // Multi-threaded
-    let world_arc = Arc::new(world);
-    let camera_arc = Arc::new(camera);
-    let config_arc = Arc::new(config);
-    raytracer::write_ppm_threaded(world_arc, camera_arc, "armadillo.ppm", config_arc);
-
-    // Uncomment to watch render live
-    // raytracer::write_ppm(&world, &camera, "temp.ppm", &config);
+/// Adds the path an I/O error happened at to its message
+fn at_path(path: &Path, err: io::Error) -> RenderError {
+    RenderError::Io(io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
 }
 
-fn render_scene_5() {
-    let out_width = 640;
-    let out_height = 480;
-    let fov_deg = 60.0;
-    let aperture = 0.0;
-    let samples = 1000;
-    let max_depth = 50;
-
-    let background = |t| {
-        0.01 * colors::SKYBLUE
-    };
-    let config = raytracer::ImageConfig {
-        width: out_width, height: out_height,
-        samples: samples, max_depth: max_depth,
-        background: Arc::new(background)
+fn render(world: &Arc<World>, camera: Camera, config: &Arc<ImageConfig>, output: &Path,
+    format: ImageFormat, options: &Options, resumed: Option<Accumulator>) -> Result<(), String>
+{
+    let write = |image: &Framebuffer| output::write_image(output, format, image, &config.tone_map)
+        .map_err(|err| at_path(output, err));
+
+    let result = match options.mode {
+        Mode::Single => raytracer::render(world, &camera, config).and_then(|image| write(&image)),
+        Mode::Threaded => raytracer::render_threaded(world.clone(), Arc::new(camera), config.clone())
+            .and_then(|image| write(&image)),
+        Mode::Progressive => {
+            let accumulator = match resumed {
+                Some(accumulator) => Ok(accumulator),
+                None => raytracer::render::validate(config)
+                    .map(|()| Accumulator::new(config.width, config.height))
+            };
+
+            let save = |accumulator: &Accumulator| {
+                write(&accumulator.image())?;
+                match &options.checkpoint {
+                    Some(path) => checkpoint::save(path, accumulator, config)
+                        .map_err(|err| at_path(path, err)),
+                    None => Ok(())
+                }
+            };
+
+            accumulator
+                .and_then(|accumulator| raytracer::render_progressive(world.clone(), Arc::new(camera),
+                    config.clone(), accumulator, options.preview, save))
+                .and_then(|accumulator| save(&accumulator))
+        }
     };
 
-    let mat_floor = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.3, 0.5, 0.8)));
-    let mat_light = Arc::new(material::Emissive::new(ColorRGB::new(1.0, 1.0, 1.0), 7.0));
-
-    let mat_skin = Arc::new(material::Translucent::new(ColorRGB::new(0.945, 0.760, 0.490), 0.0, 0.5));
-    let mat_volume = Arc::new(material::DiffuseLambert::new(ColorRGB::new(0.772, 0.301, 0.301)));
-
-    let ground = Plane::new(
-        Point3::new(0.0, -0.5, 0.0),
-        (1000.0 * Vec3::I, 1000.0 * Vec3::K),
-        mat_floor.clone()
-    );
-    let light = Plane::new(
-        Point3::new(0.0, 14.0, 7.0),
-        (2.3 * Vec3::I, 2.3 * (Vec3::J - Vec3::K)),
-        mat_light.clone()
-    );
-
-    let size = 0.04;
-    let object = Arc::new(Object::new(
-        Point3::new(0.0, 7.0, 0.0),
-        size, vec![],
-        &Path::new("models/planck.obj"), mat_skin.clone()
-    ));
-
-    let camera_focus = object.bounding_box().center().clone();
-    let camera =
-        Camera::new(&camera_focus + Point3::new(10.0, 3.0, -20.0), &camera_focus,
-            fov_deg, aperture, out_width, out_height);
-
-    let volume = Arc::new(Volume::new(object.clone(), 0.4, mat_volume.clone()));
-
-    let world = World::new(vec![
-        Arc::new(ground),
-        Arc::new(light),
-        object.clone(),
-        volume.clone()
-    ]);
-
-    // Single-threaded
-    // let mut file = File::create(&Path::new("temp.ppm")).unwrap();
-    // file.write_all(raytracer::create_ppm(&world, &camera, &config).as_bytes()).unwrap();
-
-    // Multi-threaded
-    let world_arc = Arc::new(world);
-    let camera_arc = Arc::new(camera);
-    let config_arc = Arc::new(config);
-    raytracer::write_ppm_threaded(world_arc, camera_arc, "planck.ppm", config_arc);
-
-    // Uncomment to watch render live
-    // raytracer::write_ppm(&world, &camera, "temp.ppm", &config);
+    result.map_err(|err| err.to_string())
 }
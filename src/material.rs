@@ -1,127 +1,1325 @@
+use std::f64::consts;
+use std::ops;
+use std::sync::Arc;
+
 use super::geom::hit::Hit;
-use super::math::{ Rand, lerp, f_clamp };
-use super::vec::{ colors, Ray, ColorRGB, Vec3 };
+use super::math::{ self, Rand, lerp, f_clamp };
+use super::microfacet::{ self, Ggx };
+use super::texture::{ Texture, TextureRef };
+use super::tonemap::luminance;
+use super::vec::{ colors, Color, ColorRGB, Coord, Vec3 };
+
+/// The kinds of scattering a BSDF, or one sample of it, does
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LobeFlags(u8);
 
-pub trait MaterialBase {
-    fn attenuation(&self) -> &ColorRGB;
-    fn scatter(&self, _: &Ray, _: &Hit, _: &mut Rand) -> Option<Ray> { None }
-    fn emit(&self) -> ColorRGB { colors::BLACK }
+impl LobeFlags {
+    pub const NONE: LobeFlags = LobeFlags(0);
+    pub const REFLECTION: LobeFlags = LobeFlags(1);
+    pub const TRANSMISSION: LobeFlags = LobeFlags(1 << 1);
+    /// Scatters over the whole hemisphere
+    pub const DIFFUSE: LobeFlags = LobeFlags(1 << 2);
+    /// Scatters around a preferred direction
+    pub const GLOSSY: LobeFlags = LobeFlags(1 << 3);
+    /// Scatters in a single direction, so it can be sampled but never evaluated
+    pub const SPECULAR: LobeFlags = LobeFlags(1 << 4);
+
+    pub fn contains(&self, flags: LobeFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.contains(LobeFlags::SPECULAR)
+    }
 }
 
-pub trait Material: MaterialBase + Send + Sync + std::fmt::Debug {}
+impl ops::BitOr for LobeFlags {
+    type Output = LobeFlags;
+
+    fn bitor(self, other: LobeFlags) -> LobeFlags {
+        LobeFlags(self.0 | other.0)
+    }
+}
+
+/// A direction picked by sampling a BSDF
+#[derive(Debug)]
+pub struct BsdfSample {
+    /// The direction light scatters in from, pointing away from the surface
+    pub wi: Vec3,
+    /// What a path's throughput is multiplied by for scattering in `wi`: `eval(wo, wi) / pdf`
+    pub weight: ColorRGB,
+    /// The density, per unit solid angle, of picking `wi`. For specular lobes, it's instead the
+    /// probability of picking the lobe.
+    pub pdf: f64,
+    pub flags: LobeFlags
+}
+
+/// How a material scatters light. Directions point away from the surface: `wo` towards where the
+/// light leaves (the viewer), and `wi` towards where it comes from.
+pub trait Bsdf {
+    /// The kinds of scattering the material can do
+    fn flags(&self) -> LobeFlags;
+
+    /// Picks a direction to scatter in, or `None` if the light is absorbed
+    fn sample(&self, _: &Vec3, _: &Hit, _: &mut Rand) -> Option<BsdfSample> { None }
+
+    /// The fraction of light arriving from `wi` that scatters towards `wo`, including the cosine
+    /// of the angle it arrives at. Specular lobes are never included.
+    fn eval(&self, _: &Vec3, _: &Vec3, _: &Hit) -> ColorRGB { colors::BLACK }
+
+    /// The density, per unit solid angle, with which `sample` picks `wi`
+    fn pdf(&self, _: &Vec3, _: &Vec3, _: &Hit) -> f64 { 0.0 }
+
+    /// The light given off by the surface
+    fn emit(&self, _: &Hit) -> ColorRGB { colors::BLACK }
+
+    fn is_emissive(&self) -> bool { false }
+
+    /// What fills the inside of the surface, for materials light can pass into
+    fn interior(&self) -> Option<&Medium> { None }
+}
+
+pub trait Material: Bsdf + Send + Sync + std::fmt::Debug {}
 
 #[derive(Debug)]
 pub struct DiffuseLambert {
-    albedo: ColorRGB
+    albedo: TextureRef<ColorRGB>
 }
 
 #[derive(Debug)]
 pub struct Reflective {
-    albedo: ColorRGB,
-    roughness: f64
+    albedo: TextureRef<ColorRGB>,
+    roughness: TextureRef<f64>
+}
+
+/// A metal whose microscopic bumps follow the GGX distribution, reflecting light with the Fresnel
+/// reflectance of its complex refractive index
+#[derive(Debug)]
+pub struct Conductor {
+    eta: ColorRGB,
+    k: ColorRGB,
+    roughness: TextureRef<f64>,
+    anisotropy: f64
+}
+
+/// Reflection off GGX microfacets, which is all a metal does, and part of what other materials do
+#[derive(Debug)]
+struct MicrofacetReflection {
+    distribution: Ggx,
+    fresnel: Fresnel
 }
 
+/// How the fraction of light a microfacet reflects depends on the angle it arrives at
+#[derive(Debug)]
+enum Fresnel {
+    /// The exact reflectance of a conductor with the refractive index `eta + ik`
+    Conductor { eta: ColorRGB, k: ColorRGB },
+    /// Schlick's approximation, from the reflectance at normal incidence
+    Schlick(ColorRGB),
+    /// The exact, colorless reflectance of a dielectric with the relative refractive index `eta`
+    Dielectric(f64)
+}
+
+/// Metals with measured refractive indices, for use with `Conductor::metal`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metal {
+    Gold,
+    Silver,
+    Copper,
+    Aluminium
+}
+
+/// Glass, water and other dielectrics, which both reflect and refract light
 #[derive(Debug)]
 pub struct Translucent {
+    albedo: TextureRef<ColorRGB>,
+    medium: Medium,
+    roughness: TextureRef<f64>
+}
+
+/// The surface of a `Translucent` material at one point, with its textures looked up
+#[derive(Debug)]
+struct RoughDielectric {
     albedo: ColorRGB,
-    ref_index: f64,
-    roughness: f64
+    ior: f64,
+    distribution: Ggx
+}
+
+/// What fills the inside of a closed surface that light can pass into
+#[derive(Clone, Debug)]
+pub struct Medium {
+    pub ior: f64,
+    /// How quickly each channel is absorbed, per unit distance travelled
+    pub absorption: ColorRGB
+}
+
+/// The media a path is inside of, innermost last. A path leaving one medium goes back into
+/// whichever it was in before, so media can be nested, e.g. liquid in a glass.
+#[derive(Debug, Default)]
+pub struct MediumStack(Vec<Arc<dyn Material>>);
+
+/// Disney's principled material (Burley, "Physically Based Shading at Disney"), which covers
+/// plastics, metals, cloth, varnished surfaces and glass with one set of artist-friendly parameters
+#[derive(Debug)]
+pub struct Principled {
+    params: PrincipledParams,
+    clearcoat: MicrofacetReflection,
+    medium: Medium
+}
+
+/// The lobes of a `Principled` material at one point, with its textures looked up
+struct PrincipledLayers {
+    base_color: ColorRGB,
+    metallic: f64,
+    diffuse: DisneyDiffuse,
+    specular: MicrofacetReflection,
+    glass: RoughDielectric
+}
+
+/// The parameters of a `Principled` material. Apart from the refractive index, they're all
+/// between 0 and 1.
+#[derive(Clone, Debug)]
+pub struct PrincipledParams {
+    pub base_color: TextureRef<ColorRGB>,
+    /// Blends from a dielectric to a metal, whose reflections take on the base color
+    pub metallic: TextureRef<f64>,
+    pub roughness: TextureRef<f64>,
+    /// Stretches highlights along the surface's first tangent, like brushed metal
+    pub anisotropy: f64,
+    /// How strongly a dielectric reflects light head-on; 0.5 is the 4% of most everyday materials
+    pub specular: f64,
+    /// A soft highlight at grazing angles, like on cloth
+    pub sheen: f64,
+    /// How much the sheen takes on the base color
+    pub sheen_tint: f64,
+    /// A second, colorless glossy layer on top, like varnish
+    pub clearcoat: f64,
+    pub clearcoat_roughness: f64,
+    /// Blends from an opaque surface to glass
+    pub transmission: f64,
+    /// The refractive index of the inside, for transmitted light
+    pub ior: f64
+}
+
+/// Burley's diffuse reflection, which darkens at grazing angles on smooth surfaces and brightens on
+/// rough ones, along with the sheen
+#[derive(Debug)]
+struct DisneyDiffuse {
+    base_color: ColorRGB,
+    roughness: f64,
+    sheen: ColorRGB
+}
+
+/// A blend of two materials, as if a fraction `weight` of the surface were made of the second
+#[derive(Debug)]
+pub struct Mix {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    weight: TextureRef<f64>
+}
+
+/// A smooth or glossy dielectric layer, like varnish or the clear coat on car paint, over any
+/// other material. Light reaching the base material is what the coat lets through on the way in,
+/// less what it absorbs, and the same goes on the way out.
+#[derive(Debug)]
+pub struct Coated {
+    base: Arc<dyn Material>,
+    coat: MicrofacetReflection,
+    ior: f64,
+    absorption: ColorRGB
+}
+
+/// Ways of tilting a surface's shading normal, to give it detail its geometry doesn't have
+#[derive(Debug)]
+pub enum NormalDetail {
+    /// A tangent-space normal map, whose colors hold the normal's coordinates along dpdu, the
+    /// bitangent and the surface normal, mapped from [-1, 1] to [0, 1]
+    Map(TextureRef<ColorRGB>),
+    /// A height field, shaded as if the surface were raised by the texture times the height
+    Bump(TextureRef<f64>, f64)
+}
+
+/// Any material, with its shading normal tilted by a normal or bump map before it scatters
+#[derive(Debug)]
+pub struct Bumped {
+    base: Arc<dyn Material>,
+    detail: NormalDetail
 }
 
 #[derive(Debug)]
 pub struct Emissive {
-    albedo: ColorRGB,
+    albedo: TextureRef<ColorRGB>,
     intensity: f64
 }
 
+/// Hits inside volumes have no surface normal, so materials scatter from them evenly in every
+/// direction instead
+fn in_volume(hit: &Hit) -> bool {
+    hit.normal == Vec3::O
+}
+
 impl DiffuseLambert {
-    pub fn new(albedo: ColorRGB) -> DiffuseLambert {
-        DiffuseLambert { albedo }
+    pub fn new<A: Texture<ColorRGB> + 'static>(albedo: A) -> DiffuseLambert {
+        DiffuseLambert { albedo: Arc::new(albedo) }
+    }
+
+    /// The density of the directions `sample` picks, which is proportional to their cosine
+    fn density(hit: &Hit, wi: &Vec3) -> f64 {
+        if in_volume(hit) {
+            return 1.0 / (4.0 * consts::PI);
+        }
+
+        f64::max(0.0, hit.normal.dot(wi) / consts::PI)
+    }
+
+    /// Picks a direction with `density`
+    fn direction(hit: &Hit, rand: &mut Rand) -> Vec3 {
+        // Offsetting a point on the unit sphere by the normal gives cosine-weighted directions
+        let wi = (&hit.normal + Vec3::random_unit(rand)).unit();
+        if wi == Vec3::O { hit.normal.clone() } else { wi }
     }
 }
 
 impl Material for DiffuseLambert {}
 
-impl MaterialBase for DiffuseLambert {
-    fn scatter(&self, _: &Ray, hit: &Hit, rand: &mut Rand) -> Option<Ray>
-    {
-        let random_unit = Vec3::random_unit(rand);
-        let dir = &hit.normal + random_unit;
-        Some(Ray::new(&hit.point, &dir))
+impl Bsdf for DiffuseLambert {
+    fn flags(&self) -> LobeFlags {
+        LobeFlags::DIFFUSE | LobeFlags::REFLECTION
+    }
+
+    fn sample(&self, _: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        let wi = DiffuseLambert::direction(hit, rand);
+        let pdf = DiffuseLambert::density(hit, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample { wi, weight: self.albedo.value(hit), pdf, flags: self.flags() })
     }
 
-    fn attenuation(&self) -> &ColorRGB {
-        &self.albedo
+    fn eval(&self, _: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        DiffuseLambert::density(hit, wi) * self.albedo.value(hit)
+    }
+
+    fn pdf(&self, _: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        DiffuseLambert::density(hit, wi)
     }
 }
 
 impl Reflective {
-    pub fn new(albedo: ColorRGB, roughness: f64) -> Reflective {
-        Reflective {
-            albedo,
-            roughness: f_clamp(roughness, 0.0, 1.0)
+    pub fn new<A, R>(albedo: A, roughness: R) -> Reflective
+    where
+        A: Texture<ColorRGB> + 'static,
+        R: Texture<f64> + 'static
+    {
+        Reflective { albedo: Arc::new(albedo), roughness: Arc::new(roughness) }
+    }
+
+    fn roughness(&self, hit: &Hit) -> f64 {
+        f_clamp(self.roughness.value(hit), 0.0, 1.0)
+    }
+
+    /// The density of the directions `sample` picks: those towards a point picked uniformly on a
+    /// sphere of radius `roughness` around the tip of the mirror direction
+    fn density(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        let roughness = self.roughness(hit);
+        if roughness == 0.0 || hit.normal.dot(wi) <= 0.0 {
+            return 0.0;
+        }
+
+        // Points at a distance t along wi are on the sphere where t^2 - 2bt + 1 - r^2 = 0
+        let b = wi.dot(&(-wo).reflect(&hit.normal));
+        let discriminant = b * b - 1.0 + roughness * roughness;
+        if discriminant <= 0.0 {
+            return 0.0;
         }
+
+        // Each point contributes its area density over the cosine it's seen at, times t^2. The
+        // cosine works out to sqrt(discriminant) / r for both points.
+        let root = discriminant.sqrt();
+        [b - root, b + root].iter()
+            .filter(|&&t| t > 0.0)
+            .map(|t| t * t / (4.0 * consts::PI * roughness * root))
+            .sum()
     }
 }
 
 impl Material for Reflective {}
 
-impl MaterialBase for Reflective {
-    fn scatter(&self, in_ray: &Ray, hit: &Hit, rand: &mut Rand) -> Option<Ray> {
-        let reflection_dir = in_ray.dir.reflect(&hit.normal);
-        let scattered = Ray::new(&hit.point, &(reflection_dir + self.roughness * Vec3::random_unit(rand)));
-        if scattered.dir.dot(&hit.normal) > 0.0 { Some(scattered) } else { None }
+impl Bsdf for Reflective {
+    fn flags(&self) -> LobeFlags {
+        let smooth = self.roughness.constant().is_some_and(|roughness| roughness <= 0.0);
+        let lobe = if smooth { LobeFlags::SPECULAR } else { LobeFlags::GLOSSY };
+        lobe | LobeFlags::REFLECTION
     }
 
-    fn attenuation(&self) -> &ColorRGB {
-        &self.albedo
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        let roughness = self.roughness(hit);
+        let reflection_dir = (-wo).reflect(&hit.normal);
+        let wi = (reflection_dir + roughness * Vec3::random_unit(rand)).unit();
+        if wi.dot(&hit.normal) <= 0.0 {
+            return None;
+        }
+
+        let (pdf, lobe) = if roughness == 0.0 {
+            (1.0, LobeFlags::SPECULAR)
+        } else {
+            (self.density(wo, &wi, hit), LobeFlags::GLOSSY)
+        };
+        Some(BsdfSample { wi, weight: self.albedo.value(hit), pdf, flags: lobe | LobeFlags::REFLECTION })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        self.density(wo, wi, hit) * self.albedo.value(hit)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        self.density(wo, wi, hit)
     }
 }
 
-impl Translucent {
-    const REF_INDEX_OF_AIR: f64 = 1.0;
+impl Metal {
+    /// Picks a metal from its name, e.g. "gold"
+    pub fn from_name(name: &str) -> Option<Metal> {
+        match &name.to_ascii_lowercase()[..] {
+            "gold" => Some(Metal::Gold),
+            "silver" => Some(Metal::Silver),
+            "copper" => Some(Metal::Copper),
+            "aluminium" | "aluminum" => Some(Metal::Aluminium),
+            _ => None
+        }
+    }
 
-    pub fn new(albedo: ColorRGB, ref_index: f64, roughness: f64) -> Translucent {
+    /// The real and imaginary parts of the metal's refractive index, at the wavelengths of red,
+    /// green and blue light
+    pub fn refractive_index(&self) -> (ColorRGB, ColorRGB) {
+        match self {
+            Metal::Gold => (ColorRGB::new(0.143119, 0.374957, 1.44248), ColorRGB::new(3.98316, 2.38572, 1.60322)),
+            Metal::Silver => (ColorRGB::new(0.155265, 0.116723, 0.138342), ColorRGB::new(4.82835, 3.12225, 2.14696)),
+            Metal::Copper => (ColorRGB::new(0.200438, 0.924033, 1.10221), ColorRGB::new(3.91295, 2.45285, 2.14219)),
+            Metal::Aluminium => (ColorRGB::new(1.65746, 0.880369, 0.521229), ColorRGB::new(9.22387, 6.26952, 4.837))
+        }
+    }
+}
+
+impl Conductor {
+    /// Creates a conductor from its refractive index `eta + ik`. Roughness and anisotropy are both
+    /// in [0, 1]; anisotropy stretches highlights along the surface's first tangent.
+    pub fn new<R: Texture<f64> + 'static>(eta: ColorRGB, k: ColorRGB, roughness: R, anisotropy: f64) -> Conductor {
+        Conductor { eta, k, roughness: Arc::new(roughness), anisotropy }
+    }
+
+    pub fn metal<R: Texture<f64> + 'static>(metal: Metal, roughness: R, anisotropy: f64) -> Conductor {
+        let (eta, k) = metal.refractive_index();
+        Conductor::new(eta, k, roughness, anisotropy)
+    }
+
+    fn reflection(&self, roughness: f64) -> MicrofacetReflection {
+        MicrofacetReflection::new(roughness, self.anisotropy,
+            Fresnel::Conductor { eta: self.eta.clone(), k: self.k.clone() })
+    }
+}
+
+impl Material for Conductor {}
+
+impl Bsdf for Conductor {
+    fn flags(&self) -> LobeFlags {
+        // The lobe can only be known to be smooth everywhere if the roughness doesn't vary
+        self.reflection(self.roughness.constant().unwrap_or(1.0)).flags()
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        self.reflection(self.roughness.value(hit)).sample(wo, hit, rand)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        self.reflection(self.roughness.value(hit)).eval(wo, wi, hit)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        self.reflection(self.roughness.value(hit)).pdf(wo, wi, hit)
+    }
+}
+
+impl Fresnel {
+    fn reflectance(&self, cos_theta: f64) -> ColorRGB {
+        match self {
+            Fresnel::Conductor { eta, k } => microfacet::fresnel_conductor(cos_theta, eta, k),
+            Fresnel::Schlick(normal) => {
+                let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+                lerp(normal.clone(), colors::WHITE, weight)
+            },
+            Fresnel::Dielectric(eta) => microfacet::fresnel_dielectric(cos_theta, *eta) * colors::WHITE
+        }
+    }
+}
+
+impl MicrofacetReflection {
+    fn new(roughness: f64, anisotropy: f64, fresnel: Fresnel) -> MicrofacetReflection {
+        MicrofacetReflection {
+            distribution: Ggx::new(f_clamp(roughness, 0.0, 1.0), f_clamp(anisotropy, 0.0, 1.0)),
+            fresnel
+        }
+    }
+}
+
+impl Bsdf for MicrofacetReflection {
+    fn flags(&self) -> LobeFlags {
+        let lobe = if self.distribution.is_smooth() { LobeFlags::SPECULAR } else { LobeFlags::GLOSSY };
+        lobe | LobeFlags::REFLECTION
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        // Volumes have no surface to reflect off, so they absorb all light
+        if in_volume(hit) {
+            return None;
+        }
+        let frame = hit.frame();
+        let wo = frame.to_local(wo);
+        if wo[Coord::Z] <= 0.0 {
+            return None;
+        }
+
+        if self.distribution.is_smooth() {
+            let wi = Vec3::new(-wo[Coord::X], -wo[Coord::Y], wo[Coord::Z]);
+            return Some(BsdfSample {
+                wi: frame.to_world(&wi),
+                weight: self.fresnel.reflectance(wo[Coord::Z]),
+                pdf: 1.0,
+                flags: self.flags()
+            });
+        }
+
+        let h = self.distribution.sample_visible(&wo, math::sample_2d(rand));
+        let wi = microfacet::reflect(&wo, &h);
+        if wi[Coord::Z] <= 0.0 {
+            return None;
+        }
+
+        // Most of eval / pdf cancels out
+        let pdf = self.distribution.visible_pdf(&wo, &h) / (4.0 * wo.dot(&h));
+        let shadowing = self.distribution.g(&wo, &wi) / self.distribution.g1(&wo);
+        Some(BsdfSample {
+            wi: frame.to_world(&wi),
+            weight: shadowing * self.fresnel.reflectance(wo.dot(&h)),
+            pdf,
+            flags: self.flags()
+        })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        if in_volume(hit) || self.distribution.is_smooth() {
+            return colors::BLACK;
+        }
+        let frame = hit.frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo[Coord::Z] <= 0.0 || wi[Coord::Z] <= 0.0 {
+            return colors::BLACK;
+        }
+
+        let h = (&wo + &wi).unit();
+        let d = self.distribution.d(&h);
+        let g = self.distribution.g(&wo, &wi);
+        (d * g / (4.0 * wo[Coord::Z])) * self.fresnel.reflectance(wo.dot(&h))
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        if in_volume(hit) || self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = hit.frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wi[Coord::Z] <= 0.0 {
+            return 0.0;
+        }
+
+        let h = (&wo + &wi).unit();
+        if wo.dot(&h) <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.visible_pdf(&wo, &h) / (4.0 * wo.dot(&h))
+    }
+}
+
+impl Medium {
+    pub const AIR: Medium = Medium { ior: 1.0, absorption: colors::BLACK };
+
+    /// The fraction of light left after travelling `distance` through the medium (the Beer-Lambert law)
+    pub fn transmittance(&self, distance: f64) -> ColorRGB {
+        ColorRGB::new(
+            (-self.absorption[Color::R] * distance).exp(),
+            (-self.absorption[Color::G] * distance).exp(),
+            (-self.absorption[Color::B] * distance).exp()
+        )
+    }
+}
+
+impl MediumStack {
+    pub fn new() -> MediumStack {
+        MediumStack(vec![])
+    }
+
+    /// The medium a path is travelling through, or `None` if it's in air
+    pub fn current(&self) -> Option<&Medium> {
+        self.0.last().and_then(|material| material.interior())
+    }
+
+    /// The refractive index of whatever surrounds the medium bounded by the surface `hit` found
+    pub fn exterior_ior(&self, hit: &Hit) -> f64 {
+        // Leaving a medium, what's outside it is whatever the path was in before entering it
+        let mut skipped = hit.outer;
+        for material in self.0.iter().rev() {
+            if !skipped && Arc::ptr_eq(material, &hit.material) {
+                skipped = true;
+                continue;
+            }
+            return material.interior().map_or(Medium::AIR.ior, |medium| medium.ior);
+        }
+
+        Medium::AIR.ior
+    }
+
+    /// Enters or leaves the medium bounded by the surface `hit` found, for a path passing through it
+    pub fn cross(&mut self, hit: &Hit) {
+        if hit.material.interior().is_none() {
+            return;
+        }
+
+        if hit.outer {
+            self.0.push(hit.material.clone());
+        } else if let Some(index) = self.0.iter().rposition(|material| Arc::ptr_eq(material, &hit.material)) {
+            self.0.remove(index);
+        }
+    }
+}
+
+impl Translucent {
+    pub fn new<A, R>(albedo: A, ref_index: f64, roughness: R) -> Translucent
+    where
+        A: Texture<ColorRGB> + 'static,
+        R: Texture<f64> + 'static
+    {
         Translucent {
-            albedo,
-            ref_index: if ref_index < 1.0 { 1.0 } else { ref_index },
-            roughness: f_clamp(roughness, 0.0, 1.0)
+            albedo: Arc::new(albedo),
+            medium: Medium { ior: ref_index.max(1.0), absorption: colors::BLACK },
+            roughness: Arc::new(roughness)
         }
     }
 
+    /// Makes light travelling through the inside lose the fraction `1 - exp(-absorption * d)` of
+    /// each channel over a distance `d`
+    pub fn with_absorption(mut self, absorption: ColorRGB) -> Translucent {
+        self.medium.absorption = absorption;
+        self
+    }
+
     pub fn get_refractive_index(&self) -> f64 {
-        self.ref_index
+        self.medium.ior
+    }
+
+    fn surface(&self, hit: &Hit) -> RoughDielectric {
+        RoughDielectric::new(self.albedo.value(hit), self.medium.ior, self.roughness.value(hit))
     }
 }
 
 impl Material for Translucent {}
 
-impl MaterialBase for Translucent {
-    fn scatter(&self, in_ray: &Ray, hit: &Hit, rand: &mut Rand) -> Option<Ray> {
-        let refraction_dir =
-            if hit.outer {
-                in_ray.dir.refract(&hit.normal, Translucent::REF_INDEX_OF_AIR, self.ref_index, rand)
+impl Bsdf for Translucent {
+    fn flags(&self) -> LobeFlags {
+        // The surface can only be known to be smooth everywhere if the roughness doesn't vary
+        RoughDielectric::new(colors::WHITE, self.medium.ior, self.roughness.constant().unwrap_or(1.0)).flags()
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        self.surface(hit).sample(wo, hit, rand)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        self.surface(hit).eval(wo, wi, hit)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        self.surface(hit).pdf(wo, wi, hit)
+    }
+
+    fn interior(&self) -> Option<&Medium> {
+        Some(&self.medium)
+    }
+}
+
+impl RoughDielectric {
+    fn new(albedo: ColorRGB, ior: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric { albedo, ior, distribution: Ggx::new(f_clamp(roughness, 0.0, 1.0), 0.0) }
+    }
+
+    /// The refractive index of the far side of the surface relative to the side the normal faces
+    fn eta(&self, hit: &Hit) -> f64 {
+        if hit.outer {
+            self.ior / hit.exterior_ior
+        } else {
+            hit.exterior_ior / self.ior
+        }
+    }
+
+    /// Surfaces between media of the same refractive index don't bend light, however rough they are
+    fn is_smooth(&self, eta: f64) -> bool {
+        self.distribution.is_smooth() || math::f_eq(eta, 1.0)
+    }
+
+    /// The microfacet normal that reflects or refracts `wo` into `wi`, if one facing both exists
+    fn half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+        let eta = if wi[Coord::Z] > 0.0 { 1.0 } else { eta };
+        let mut h = (wo + eta * wi).unit();
+        if h[Coord::Z] < 0.0 {
+            h = -h;
+        }
+
+        if h == Vec3::O || wi.dot(&h) * wi[Coord::Z] <= 0.0 || wo.dot(&h) <= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+
+    /// The change in the refracted direction's solid angle for one in the microfacet normal's
+    fn refraction_jacobian(wo: &Vec3, wi: &Vec3, h: &Vec3, eta: f64) -> f64 {
+        let denom = wi.dot(h) + wo.dot(h) / eta;
+        wi.dot(h).abs() / (denom * denom)
+    }
+}
+
+/// A dielectric whose microfacets reflect and refract light (Walter et al., "Microfacet Models for
+/// Refraction through Rough Surfaces"). Volumes have no surface to refract at, so they absorb all
+/// light.
+impl Bsdf for RoughDielectric {
+    fn flags(&self) -> LobeFlags {
+        let lobe = if self.distribution.is_smooth() { LobeFlags::SPECULAR } else { LobeFlags::GLOSSY };
+        lobe | LobeFlags::REFLECTION | LobeFlags::TRANSMISSION
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        if in_volume(hit) {
+            return None;
+        }
+        let frame = hit.frame();
+        let wo = frame.to_local(wo);
+        if wo[Coord::Z] <= 0.0 {
+            return None;
+        }
+        let eta = self.eta(hit);
+
+        if self.is_smooth(eta) {
+            let reflectance = microfacet::fresnel_dielectric(wo[Coord::Z], eta);
+            let sample = if math::sample_1d(rand) < reflectance {
+                BsdfSample {
+                    wi: frame.to_world(&Vec3::new(-wo[Coord::X], -wo[Coord::Y], wo[Coord::Z])),
+                    weight: self.albedo.clone(),
+                    pdf: reflectance,
+                    flags: LobeFlags::SPECULAR | LobeFlags::REFLECTION
+                }
             } else {
-                in_ray.dir.refract(&hit.normal, self.ref_index, Translucent::REF_INDEX_OF_AIR, rand)
+                // Radiance is compressed into a smaller solid angle when it enters a denser medium
+                BsdfSample {
+                    wi: frame.to_world(&microfacet::refract(&wo, &Vec3::K, eta)?),
+                    weight: (1.0 / (eta * eta)) * &self.albedo,
+                    pdf: 1.0 - reflectance,
+                    flags: LobeFlags::SPECULAR | LobeFlags::TRANSMISSION
+                }
             };
+            return Some(sample);
+        }
+
+        let h = self.distribution.sample_visible(&wo, math::sample_2d(rand));
+        let reflectance = microfacet::fresnel_dielectric(wo.dot(&h), eta);
+        let shadowing = |wi: &Vec3| self.distribution.g(&wo, wi) / self.distribution.g1(&wo);
+
+        if math::sample_1d(rand) < reflectance {
+            let wi = microfacet::reflect(&wo, &h);
+            if wi[Coord::Z] <= 0.0 {
+                return None;
+            }
+
+            let pdf = reflectance * self.distribution.visible_pdf(&wo, &h) / (4.0 * wo.dot(&h));
+            Some(BsdfSample {
+                weight: shadowing(&wi) * &self.albedo,
+                wi: frame.to_world(&wi),
+                pdf,
+                flags: LobeFlags::GLOSSY | LobeFlags::REFLECTION
+            })
+        } else {
+            let wi = microfacet::refract(&wo, &h, eta)?;
+            if wi[Coord::Z] >= 0.0 {
+                return None;
+            }
+
+            let pdf = (1.0 - reflectance) * self.distribution.visible_pdf(&wo, &h)
+                * RoughDielectric::refraction_jacobian(&wo, &wi, &h, eta);
+            Some(BsdfSample {
+                weight: (shadowing(&wi) / (eta * eta)) * &self.albedo,
+                wi: frame.to_world(&wi),
+                pdf,
+                flags: LobeFlags::GLOSSY | LobeFlags::TRANSMISSION
+            })
+        }
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        let eta = self.eta(hit);
+        if in_volume(hit) || self.is_smooth(eta) {
+            return colors::BLACK;
+        }
+        let frame = hit.frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wo[Coord::Z] <= 0.0 {
+            return colors::BLACK;
+        }
+        let h = match RoughDielectric::half_vector(&wo, &wi, eta) {
+            Some(h) => h,
+            None => return colors::BLACK
+        };
+
+        let reflectance = microfacet::fresnel_dielectric(wo.dot(&h), eta);
+        let dg = self.distribution.d(&h) * self.distribution.g(&wo, &wi);
+        if wi[Coord::Z] > 0.0 {
+            (reflectance * dg / (4.0 * wo[Coord::Z])) * &self.albedo
+        } else {
+            let jacobian = RoughDielectric::refraction_jacobian(&wo, &wi, &h, eta);
+            let transmission = (1.0 - reflectance) * dg * wo.dot(&h) * jacobian / wo[Coord::Z];
+            (transmission / (eta * eta)) * &self.albedo
+        }
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        let eta = self.eta(hit);
+        if in_volume(hit) || self.is_smooth(eta) {
+            return 0.0;
+        }
+        let frame = hit.frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let h = match RoughDielectric::half_vector(&wo, &wi, eta) {
+            Some(h) => h,
+            None => return 0.0
+        };
+
+        let reflectance = microfacet::fresnel_dielectric(wo.dot(&h), eta);
+        let visible = self.distribution.visible_pdf(&wo, &h);
+        if wi[Coord::Z] > 0.0 {
+            reflectance * visible / (4.0 * wo.dot(&h))
+        } else {
+            (1.0 - reflectance) * visible * RoughDielectric::refraction_jacobian(&wo, &wi, &h, eta)
+        }
+    }
+}
+
+/// One of several BSDFs whose scattering is added up, scaled by `weight`, and the relative
+/// probability of sampling it
+struct Lobe<'a> {
+    bsdf: &'a dyn Bsdf,
+    weight: f64,
+    probability: f64
+}
+
+impl<'a> Lobe<'a> {
+    fn new(bsdf: &'a dyn Bsdf, weight: f64, probability: f64) -> Lobe<'a> {
+        Lobe { bsdf, weight, probability }
+    }
+}
+
+/// Picks one of `lobes` to sample a direction from, then weighs the direction by all of them, so
+/// it's as if their sum had been sampled
+fn sample_lobes(lobes: &[Lobe], wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+    let total: f64 = lobes.iter().map(|lobe| lobe.probability).sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut u = math::sample_1d(rand) * total;
+    let lobe = lobes.iter()
+        .filter(|lobe| lobe.probability > 0.0)
+        .find(|lobe| {
+            u -= lobe.probability;
+            u < 0.0
+        })
+        .or_else(|| lobes.iter().rev().find(|lobe| lobe.probability > 0.0))?;
+    let sample = lobe.bsdf.sample(wo, hit, rand)?;
+
+    // Specular directions can only come from the lobe that picked them
+    let probability = lobe.probability / total;
+    if sample.flags.is_specular() {
+        return Some(BsdfSample {
+            weight: (lobe.weight / probability) * sample.weight,
+            pdf: probability * sample.pdf,
+            ..sample
+        });
+    }
+
+    let pdf = pdf_lobes(lobes, wo, &sample.wi, hit);
+    if pdf <= 0.0 {
+        return None;
+    }
+    Some(BsdfSample { weight: (1.0 / pdf) * eval_lobes(lobes, wo, &sample.wi, hit), pdf, ..sample })
+}
+
+fn eval_lobes(lobes: &[Lobe], wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+    let mut total = colors::BLACK;
+    for lobe in lobes.iter().filter(|lobe| lobe.weight > 0.0) {
+        total += lobe.weight * lobe.bsdf.eval(wo, wi, hit);
+    }
+    total
+}
+
+fn pdf_lobes(lobes: &[Lobe], wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+    let total: f64 = lobes.iter().map(|lobe| lobe.probability).sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+
+    lobes.iter()
+        .filter(|lobe| lobe.probability > 0.0)
+        .map(|lobe| lobe.probability / total * lobe.bsdf.pdf(wo, wi, hit))
+        .sum()
+}
 
-        Some(Ray::new(&hit.point,
-                &lerp(
-                    refraction_dir,
-                    &hit.normal + Vec3::random_unit(rand),
-                    self.roughness
-                )))
+impl Default for PrincipledParams {
+    fn default() -> PrincipledParams {
+        PrincipledParams {
+            base_color: Arc::new(ColorRGB::new(0.8, 0.8, 0.8)),
+            metallic: Arc::new(0.0),
+            roughness: Arc::new(0.5),
+            anisotropy: 0.0,
+            specular: 0.5,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.1,
+            transmission: 0.0,
+            ior: 1.5
+        }
+    }
+}
+
+impl Principled {
+    /// Perfectly smooth lobes can't be evaluated, and so can't be added to the others. Highlights
+    /// are already pin-sharp at this roughness.
+    const MIN_ROUGHNESS: f64 = 0.04;
+
+    pub fn new(params: PrincipledParams) -> Principled {
+        let unit = |value: f64| f_clamp(value, 0.0, 1.0);
+        let params = PrincipledParams {
+            anisotropy: unit(params.anisotropy),
+            specular: unit(params.specular),
+            sheen: unit(params.sheen),
+            sheen_tint: unit(params.sheen_tint),
+            clearcoat: unit(params.clearcoat),
+            clearcoat_roughness: f_clamp(params.clearcoat_roughness, Principled::MIN_ROUGHNESS, 1.0),
+            transmission: unit(params.transmission),
+            ..params
+        };
+
+        Principled {
+            clearcoat: MicrofacetReflection::new(params.clearcoat_roughness, 0.0,
+                Fresnel::Schlick(0.04 * colors::WHITE)),
+            medium: Medium { ior: params.ior.max(1.0), absorption: colors::BLACK },
+            params
+        }
     }
 
-    fn attenuation(&self) -> &ColorRGB {
-        &self.albedo
+    /// Looks up the textured parameters at `hit`, and builds the lobes that depend on them
+    fn layers(&self, hit: &Hit) -> PrincipledLayers {
+        let params = &self.params;
+        let base_color = params.base_color.value(hit);
+        let metallic = f_clamp(params.metallic.value(hit), 0.0, 1.0);
+        let roughness = f_clamp(params.roughness.value(hit), Principled::MIN_ROUGHNESS, 1.0);
+
+        // The base color's hue and saturation, without its brightness
+        let brightness = luminance(&base_color);
+        let tint = if brightness > 0.0 { (1.0 / brightness) * &base_color } else { colors::WHITE };
+        let dielectric = (0.08 * params.specular) * colors::WHITE;
+
+        PrincipledLayers {
+            diffuse: DisneyDiffuse {
+                base_color: base_color.clone(),
+                roughness,
+                sheen: params.sheen * lerp(colors::WHITE, tint, params.sheen_tint)
+            },
+            specular: MicrofacetReflection::new(roughness, params.anisotropy,
+                Fresnel::Schlick(lerp(dielectric, base_color.clone(), metallic))),
+            glass: RoughDielectric::new(base_color.clone(), self.medium.ior, roughness),
+            base_color,
+            metallic
+        }
+    }
+
+    /// The diffuse, specular, glass and clearcoat lobes, with the probabilities of sampling them
+    /// roughly in proportion to how much light they reflect towards `wo`
+    fn lobes<'a>(&'a self, layers: &'a PrincipledLayers, wo: &Vec3, hit: &Hit) -> [Lobe<'a>; 4] {
+        let params = &self.params;
+        let lobe = Lobe::new;
+
+        // Only the diffuse lobe scatters inside volumes
+        if in_volume(hit) {
+            return [
+                lobe(&layers.diffuse, 1.0, 1.0),
+                lobe(&layers.specular, 0.0, 0.0),
+                lobe(&layers.glass, 0.0, 0.0),
+                lobe(&self.clearcoat, 0.0, 0.0)
+            ];
+        }
+
+        let cos_theta = wo.dot(&hit.normal).abs();
+        let dielectric = 1.0 - layers.metallic;
+        let opaque = dielectric * (1.0 - params.transmission);
+        let specular = 1.0 - dielectric * params.transmission;
+        let glass = dielectric * params.transmission;
+        let clearcoat = 0.25 * params.clearcoat;
+
+        [
+            lobe(&layers.diffuse, opaque, opaque * (luminance(&layers.base_color) + params.sheen)),
+            lobe(&layers.specular, specular, specular * luminance(&layers.specular.fresnel.reflectance(cos_theta))),
+            lobe(&layers.glass, glass, glass),
+            lobe(&self.clearcoat, clearcoat, clearcoat * luminance(&self.clearcoat.fresnel.reflectance(cos_theta)))
+        ]
+    }
+}
+
+impl Material for Principled {}
+
+impl Bsdf for Principled {
+    fn flags(&self) -> LobeFlags {
+        let flags = LobeFlags::DIFFUSE | LobeFlags::GLOSSY | LobeFlags::REFLECTION;
+        if self.params.transmission > 0.0 { flags | LobeFlags::TRANSMISSION } else { flags }
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        let layers = self.layers(hit);
+        sample_lobes(&self.lobes(&layers, wo, hit), wo, hit, rand)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        let layers = self.layers(hit);
+        eval_lobes(&self.lobes(&layers, wo, hit), wo, wi, hit)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        let layers = self.layers(hit);
+        pdf_lobes(&self.lobes(&layers, wo, hit), wo, wi, hit)
+    }
+
+    fn interior(&self) -> Option<&Medium> {
+        if self.params.transmission > 0.0 { Some(&self.medium) } else { None }
+    }
+}
+
+impl DisneyDiffuse {
+    /// Schlick's weighting of how close an angle is to grazing
+    fn grazing(cos_theta: f64) -> f64 {
+        (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+    }
+}
+
+impl Bsdf for DisneyDiffuse {
+    fn flags(&self) -> LobeFlags {
+        LobeFlags::DIFFUSE | LobeFlags::REFLECTION
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        let wi = DiffuseLambert::direction(hit, rand);
+        let pdf = DiffuseLambert::density(hit, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(BsdfSample { weight: (1.0 / pdf) * self.eval(wo, &wi, hit), wi, pdf, flags: self.flags() })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        if in_volume(hit) {
+            return DiffuseLambert::density(hit, wi) * &self.base_color;
+        }
+        let (cos_theta_o, cos_theta_i) = (wo.dot(&hit.normal), wi.dot(&hit.normal));
+        if cos_theta_o <= 0.0 || cos_theta_i <= 0.0 {
+            return colors::BLACK;
+        }
+
+        // Light is retroreflected more at grazing angles the rougher the surface is
+        let cos_theta_d = wi.dot(&(wo + wi).unit());
+        let retro = 0.5 + 2.0 * self.roughness * cos_theta_d * cos_theta_d;
+        let diffuse = (1.0 + (retro - 1.0) * DisneyDiffuse::grazing(cos_theta_i))
+            * (1.0 + (retro - 1.0) * DisneyDiffuse::grazing(cos_theta_o)) / consts::PI;
+
+        cos_theta_i * (diffuse * &self.base_color + DisneyDiffuse::grazing(cos_theta_d) * &self.sheen)
+    }
+
+    fn pdf(&self, _: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        DiffuseLambert::density(hit, wi)
+    }
+}
+
+impl Mix {
+    pub fn new<W: Texture<f64> + 'static>(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: W) -> Mix {
+        Mix { first, second, weight: Arc::new(weight) }
+    }
+
+    fn weight(&self, hit: &Hit) -> f64 {
+        f_clamp(self.weight.value(hit), 0.0, 1.0)
+    }
+
+    fn lobes(&self, hit: &Hit) -> [Lobe<'_>; 2] {
+        let weight = self.weight(hit);
+        [
+            Lobe::new(self.first.as_ref(), 1.0 - weight, 1.0 - weight),
+            Lobe::new(self.second.as_ref(), weight, weight)
+        ]
+    }
+}
+
+impl Material for Mix {}
+
+/// Paths can only be inside one medium, so light passing into a mix of two transparent materials
+/// is taken to be passing into the first
+impl Bsdf for Mix {
+    fn flags(&self) -> LobeFlags {
+        self.first.flags() | self.second.flags()
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        sample_lobes(&self.lobes(hit), wo, hit, rand)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        eval_lobes(&self.lobes(hit), wo, wi, hit)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        pdf_lobes(&self.lobes(hit), wo, wi, hit)
+    }
+
+    fn emit(&self, hit: &Hit) -> ColorRGB {
+        lerp(self.first.emit(hit), self.second.emit(hit), self.weight(hit))
+    }
+
+    fn is_emissive(&self) -> bool {
+        // A varying weight could let either material show through somewhere
+        match self.weight.constant().map(|weight| f_clamp(weight, 0.0, 1.0)) {
+            Some(weight) => self.first.is_emissive() && weight < 1.0 || self.second.is_emissive() && weight > 0.0,
+            None => self.first.is_emissive() || self.second.is_emissive()
+        }
+    }
+
+    fn interior(&self) -> Option<&Medium> {
+        self.first.interior().or_else(|| self.second.interior())
+    }
+}
+
+impl Coated {
+    /// Coats `base` in a dielectric with the refractive index `ior`. A roughness of 0 gives a
+    /// perfectly smooth coat.
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64) -> Coated {
+        let ior = ior.max(1.0);
+        Coated {
+            base,
+            coat: MicrofacetReflection::new(roughness, 0.0, Fresnel::Dielectric(ior)),
+            ior,
+            absorption: colors::BLACK
+        }
+    }
+
+    /// Makes the coat absorb the fraction `1 - exp(-absorption)` of each channel of light passing
+    /// straight through it, and more at an angle, where the path through it is longer
+    pub fn with_absorption(mut self, absorption: ColorRGB) -> Coated {
+        self.absorption = absorption;
+        self
+    }
+
+    /// The fraction of light that makes it through the coat along `wo` and `wi`
+    fn transmittance(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        let (cos_theta_o, cos_theta_i) = (wo.dot(&hit.normal).abs(), wi.dot(&hit.normal).abs());
+        let transmitted = (1.0 - microfacet::fresnel_dielectric(cos_theta_o, self.ior))
+            * (1.0 - microfacet::fresnel_dielectric(cos_theta_i, self.ior));
+
+        // The length of the path through a coat of unit thickness, after bending towards the normal
+        let inside = |cos_theta: f64| (1.0 - (1.0 - cos_theta * cos_theta) / (self.ior * self.ior)).sqrt();
+        let length = 1.0 / inside(cos_theta_o) + 1.0 / inside(cos_theta_i);
+        transmitted * Medium { ior: self.ior, absorption: self.absorption.clone() }.transmittance(length)
+    }
+
+    /// The probability of sampling the coat rather than the base. It's at least the fraction of
+    /// light the coat reflects towards `wo`, and enough to find its highlights over bright bases.
+    fn coat_probability(&self, wo: &Vec3, hit: &Hit) -> f64 {
+        microfacet::fresnel_dielectric(wo.dot(&hit.normal).abs(), self.ior).max(0.25)
+    }
+}
+
+impl Material for Coated {}
+
+impl Bsdf for Coated {
+    fn flags(&self) -> LobeFlags {
+        self.coat.flags() | self.base.flags()
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        if in_volume(hit) {
+            return self.base.sample(wo, hit, rand);
+        }
+
+        let coat_probability = self.coat_probability(wo, hit);
+        let sample = if math::sample_1d(rand) < coat_probability {
+            let sample = self.coat.sample(wo, hit, rand)?;
+            if sample.flags.is_specular() {
+                return Some(BsdfSample {
+                    weight: (1.0 / coat_probability) * sample.weight,
+                    pdf: coat_probability * sample.pdf,
+                    ..sample
+                });
+            }
+            sample
+        } else {
+            let sample = self.base.sample(wo, hit, rand)?;
+            if sample.flags.is_specular() {
+                let base_probability = 1.0 - coat_probability;
+                return Some(BsdfSample {
+                    weight: (1.0 / base_probability) * self.transmittance(wo, &sample.wi, hit) * sample.weight,
+                    pdf: base_probability * sample.pdf,
+                    ..sample
+                });
+            }
+            sample
+        };
+
+        let pdf = self.pdf(wo, &sample.wi, hit);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample { weight: (1.0 / pdf) * self.eval(wo, &sample.wi, hit), pdf, ..sample })
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        if in_volume(hit) {
+            return self.base.eval(wo, wi, hit);
+        }
+
+        self.coat.eval(wo, wi, hit) + self.transmittance(wo, wi, hit) * self.base.eval(wo, wi, hit)
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        if in_volume(hit) {
+            return self.base.pdf(wo, wi, hit);
+        }
+
+        let coat_probability = self.coat_probability(wo, hit);
+        coat_probability * self.coat.pdf(wo, wi, hit) + (1.0 - coat_probability) * self.base.pdf(wo, wi, hit)
+    }
+
+    fn emit(&self, hit: &Hit) -> ColorRGB {
+        self.base.emit(hit)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn interior(&self) -> Option<&Medium> {
+        self.base.interior()
+    }
+}
+
+impl Bumped {
+    /// How far the surface is nudged along u and v to find the slope of a bump map
+    const BUMP_DELTA: f64 = 5e-4;
+
+    pub fn new(base: Arc<dyn Material>, detail: NormalDetail) -> Bumped {
+        Bumped { base, detail }
+    }
+
+    /// The shading normal the detail gives at `hit`, or `None` where the surface isn't
+    /// parameterized well enough to tell
+    fn normal(&self, hit: &Hit) -> Option<Vec3> {
+        match &self.detail {
+            NormalDetail::Map(map) => {
+                let color = map.value(hit);
+                let local = Vec3::new(
+                    2.0 * color[Color::R] - 1.0, 2.0 * color[Color::G] - 1.0, 2.0 * color[Color::B] - 1.0
+                );
+                Some(hit.frame().to_world(&local).unit())
+            },
+            NormalDetail::Bump(height, scale) => {
+                let nudged = |du: f64, dv: f64| {
+                    let mut nudged = hit.clone();
+                    nudged.uv = (hit.uv.0 + du, hit.uv.1 + dv);
+                    nudged.point = &hit.point + du * &hit.dpdu + dv * &hit.dpdv;
+                    scale * height.value(&nudged)
+                };
+
+                // Raising each point along the normal changes how the surface moves with u and v
+                let displacement = nudged(0.0, 0.0);
+                let slope_u = (nudged(Bumped::BUMP_DELTA, 0.0) - displacement) / Bumped::BUMP_DELTA;
+                let slope_v = (nudged(0.0, Bumped::BUMP_DELTA) - displacement) / Bumped::BUMP_DELTA;
+                let dpdu = &hit.dpdu + slope_u * &hit.normal;
+                let dpdv = &hit.dpdv + slope_v * &hit.normal;
+
+                let normal = dpdu.cross(&dpdv).unit();
+                if normal == Vec3::O {
+                    return None;
+                }
+                // The derivatives' cross product can point out of either side of the surface
+                let unbumped = hit.dpdu.cross(&hit.dpdv);
+                Some(if unbumped.dot(&hit.normal) < 0.0 { -normal } else { normal })
+            }
+        }
+    }
+
+    /// A copy of `hit` to shade with, with its normal tilted. Tilts so far that the normal turns
+    /// away from the side that was hit are left out.
+    fn shading_hit(&self, hit: &Hit) -> Hit {
+        let mut shading = hit.clone();
+        if in_volume(hit) {
+            return shading;
+        }
+
+        if let Some(normal) = self.normal(hit) {
+            if normal.dot(&hit.geometric_normal) > 0.0 {
+                shading.normal = normal;
+            }
+        }
+        shading
+    }
+}
+
+impl Material for Bumped {}
+
+impl Bsdf for Bumped {
+    fn flags(&self) -> LobeFlags {
+        self.base.flags()
+    }
+
+    fn sample(&self, wo: &Vec3, hit: &Hit, rand: &mut Rand) -> Option<BsdfSample> {
+        self.base.sample(wo, &self.shading_hit(hit), rand)
+    }
+
+    fn eval(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> ColorRGB {
+        self.base.eval(wo, wi, &self.shading_hit(hit))
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3, hit: &Hit) -> f64 {
+        self.base.pdf(wo, wi, &self.shading_hit(hit))
+    }
+
+    fn emit(&self, hit: &Hit) -> ColorRGB {
+        self.base.emit(hit)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.base.is_emissive()
+    }
+
+    fn interior(&self) -> Option<&Medium> {
+        self.base.interior()
     }
 }
 
 impl Emissive {
-    pub fn new(albedo: ColorRGB, intensity: f64) -> Emissive {
+    pub fn new<A: Texture<ColorRGB> + 'static>(albedo: A, intensity: f64) -> Emissive {
         Emissive {
-            albedo,
+            albedo: Arc::new(albedo),
             intensity: if intensity < 0.0 { 0.0 } else { intensity }
         }
     }
@@ -129,12 +1327,252 @@ impl Emissive {
 
 impl Material for Emissive {}
 
-impl MaterialBase for Emissive {
-    fn attenuation(&self) -> &ColorRGB {
-        &self.albedo
+impl Bsdf for Emissive {
+    fn flags(&self) -> LobeFlags {
+        LobeFlags::NONE
+    }
+
+    fn emit(&self, hit: &Hit) -> ColorRGB {
+        self.intensity * self.albedo.value(hit)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.intensity > 0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::texture::{ Checker, Mapping };
+    use crate::vec::Point3;
+
+    fn hit_on_floor(material: Arc<dyn Material>) -> Hit {
+        Hit::new(Point3::O, Vec3::J, 1.0, true, material)
     }
 
-    fn emit(&self) -> ColorRGB {
-        ColorRGB::new(self.intensity, self.intensity, self.intensity)
+    /// Integrates a direction's density over the sphere numerically, on a grid of `steps` by `steps`
+    fn total_density(steps: u32, pdf: &dyn Fn(&Vec3) -> f64) -> f64 {
+        let mut total = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u = (f64::from(i) + 0.5) / f64::from(steps);
+                let v = (f64::from(j) + 0.5) / f64::from(steps);
+                total += pdf(&Vec3::on_unit_sphere(u, v));
+            }
+        }
+
+        // Every cell covers the same area
+        total * 4.0 * consts::PI / f64::from(steps * steps)
+    }
+
+    #[test]
+    fn samples_match_evaluation() {
+        let materials: Vec<Arc<dyn Material>> = vec![
+            Arc::new(DiffuseLambert::new(ColorRGB::new(0.8, 0.5, 0.2))),
+            Arc::new(Reflective::new(ColorRGB::new(0.8, 0.5, 0.2), 0.4)),
+            Arc::new(Conductor::metal(Metal::Copper, 0.5, 0.6)),
+            Arc::new(Translucent::new(ColorRGB::new(0.8, 0.5, 0.2), 1.5, 0.4)),
+            Arc::new(Principled::new(PrincipledParams {
+                base_color: Arc::new(ColorRGB::new(0.8, 0.5, 0.2)), sheen: 0.5, clearcoat: 1.0, ..Default::default()
+            })),
+            Arc::new(Principled::new(PrincipledParams {
+                metallic: Arc::new(0.3), anisotropy: 0.5, transmission: 0.6, ..Default::default()
+            })),
+            Arc::new(Mix::new(Arc::new(DiffuseLambert::new(colors::WHITE)),
+                Arc::new(Conductor::metal(Metal::Copper, 0.3, 0.0)), 0.3)),
+            Arc::new(Coated::new(Arc::new(Conductor::metal(Metal::Silver, 0.6, 0.0)), 1.5, 0.2)
+                .with_absorption(ColorRGB::new(0.1, 0.2, 0.3)))
+        ];
+        let wo = Vec3::new(1.0, 1.0, 0.0).unit();
+
+        // Hit from outside, then from inside
+        for material in materials {
+            for hit in [hit_on_floor(material.clone()), Hit::new(Point3::O, -Vec3::J, 1.0, false, material.clone())] {
+                let mut rand = Rand::seeded(3);
+                for _ in 0..100 {
+                    let sample = match material.sample(&wo, &hit, &mut rand) {
+                        Some(sample) => sample,
+                        None => continue
+                    };
+                    assert!(math::f_eq(sample.pdf, material.pdf(&wo, &sample.wi, &hit)));
+                    assert_eq!(sample.weight, material.eval(&wo, &sample.wi, &hit) * (1.0 / sample.pdf));
+                    assert!(!sample.flags.is_specular());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let lambert = DiffuseLambert::new(colors::WHITE);
+        let hit = hit_on_floor(Arc::new(DiffuseLambert::new(colors::WHITE)));
+        let total = total_density(400, &|wi| lambert.pdf(&Vec3::J, wi, &hit));
+        assert!((total - 1.0).abs() < 1e-3, "Lambertian density integrates to {}", total);
+
+        // Looking straight down, no fuzzed directions end up below the surface
+        for &roughness in &[0.3, 0.9] {
+            let fuzzy = Reflective::new(colors::WHITE, roughness);
+            // The grid is fine because the density grows without bound at the edge of its cone
+            let total = total_density(1600, &|wi| fuzzy.pdf(&Vec3::J, wi, &hit));
+            assert!((total - 1.0).abs() < 1e-2, "Fuzzy reflection density integrates to {}", total);
+        }
+
+        // Some of the directions scattered by microfacets end up on the wrong side of the surface
+        // and are dropped, so the density integrates to the fraction of samples that aren't
+        let wo = Vec3::new(1.0, 0.5, 0.0).unit();
+        let microfacets: Vec<(Arc<dyn Material>, bool)> = vec![
+            (Arc::new(Conductor::metal(Metal::Gold, 0.5, 0.0)), true),
+            (Arc::new(Conductor::metal(Metal::Gold, 0.7, 0.9)), true),
+            (Arc::new(Translucent::new(colors::WHITE, 1.5, 0.5)), true),
+            (Arc::new(Translucent::new(colors::WHITE, 1.5, 0.5)), false),
+            (Arc::new(Principled::new(PrincipledParams { clearcoat: 1.0, ..Default::default() })), true),
+            (Arc::new(Principled::new(PrincipledParams { roughness: Arc::new(0.3), transmission: 0.5, ..Default::default() })), false),
+            (Arc::new(Mix::new(Arc::new(DiffuseLambert::new(colors::WHITE)),
+                Arc::new(Translucent::new(colors::WHITE, 1.5, 0.5)), 0.6)), true),
+            (Arc::new(Coated::new(Arc::new(DiffuseLambert::new(colors::WHITE)), 1.5, 0.3)), true)
+        ];
+        for (material, outer) in microfacets {
+            let hit = Hit::new(Point3::O, if outer { Vec3::J } else { -Vec3::J }, 1.0, outer, material.clone());
+            let total = total_density(500, &|wi| material.pdf(&wo, wi, &hit));
+
+            let mut rand = Rand::seeded(5);
+            let kept = (0..20000).filter(|_| material.sample(&wo, &hit, &mut rand).is_some()).count();
+            let expected = kept as f64 / 20000.0;
+            assert!((total - expected).abs() < 1e-2, "{:?} density integrates to {}, not {}", material, total, expected);
+        }
+    }
+
+    #[test]
+    fn layers_and_mixes() {
+        let mut rand = Rand::seeded(1);
+        let white: Arc<dyn Material> = Arc::new(DiffuseLambert::new(colors::WHITE));
+        let hit = hit_on_floor(white.clone());
+        let wo = Vec3::new(1.0, 1.0, 0.0).unit();
+
+        // A coat reflects some light itself, and lets the rest through to be reflected by the base,
+        // so a white base under a clear coat reflects close to all of it
+        let varnished = Coated::new(white.clone(), 1.5, 0.0);
+        let mut reflected = colors::BLACK;
+        for _ in 0..4000 {
+            if let Some(sample) = varnished.sample(&wo, &hit, &mut rand) {
+                reflected += sample.weight;
+            }
+        }
+        let albedo = (1.0 / 4000.0) * reflected;
+        assert!(albedo[Color::G] > 0.8 && albedo[Color::G] < 1.0, "Reflected {}", albedo);
+
+        // Mixing a material with itself changes nothing
+        let mix = Mix::new(white.clone(), white.clone(), 0.3);
+        assert_eq!(mix.eval(&wo, &Vec3::J, &hit), white.eval(&wo, &Vec3::J, &hit));
+        assert!(math::f_eq(mix.pdf(&wo, &Vec3::J, &hit), white.pdf(&wo, &Vec3::J, &hit)));
+
+        let glowing = Mix::new(white, Arc::new(Emissive::new(colors::WHITE, 2.0)), 0.25);
+        assert!(glowing.is_emissive());
+        assert_eq!(glowing.emit(&hit), ColorRGB::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn nested_media() {
+        let glass: Arc<dyn Material> = Arc::new(Translucent::new(colors::WHITE, 1.5, 0.0));
+        let water: Arc<dyn Material> = Arc::new(Translucent::new(colors::WHITE, 1.33, 0.0)
+            .with_absorption(ColorRGB::new(0.5, 0.1, 0.0)));
+        let hit = |material: &Arc<dyn Material>, outer| Hit::new(Point3::O, Vec3::J, 1.0, outer, material.clone());
+
+        // Into a glass, and then into the water in it
+        let mut media = MediumStack::new();
+        assert_eq!(media.exterior_ior(&hit(&glass, true)), 1.0);
+        media.cross(&hit(&glass, true));
+        assert_eq!(media.exterior_ior(&hit(&water, true)), 1.5);
+        media.cross(&hit(&water, true));
+        assert_eq!(media.current().unwrap().transmittance(2.0), ColorRGB::new((-1.0_f64).exp(), (-0.2_f64).exp(), 1.0));
+
+        // Leaving the water goes back into the glass, and leaving the glass goes back into air
+        assert_eq!(media.exterior_ior(&hit(&water, false)), 1.5);
+        media.cross(&hit(&water, false));
+        assert_eq!(media.exterior_ior(&hit(&glass, false)), 1.0);
+        media.cross(&hit(&glass, false));
+        assert!(media.current().is_none());
+
+        // Surfaces without insides don't change the medium
+        let wall: Arc<dyn Material> = Arc::new(DiffuseLambert::new(colors::WHITE));
+        media.cross(&hit(&wall, true));
+        assert!(media.current().is_none());
+    }
+
+    #[test]
+    fn specular_lobes() {
+        let mut rand = Rand::seeded(0);
+        let mirror = Reflective::new(colors::WHITE, 0.0);
+        let hit = hit_on_floor(Arc::new(Reflective::new(colors::WHITE, 0.0)));
+        let sample = mirror.sample(&Vec3::new(1.0, 1.0, 0.0).unit(), &hit, &mut rand).unwrap();
+        assert_eq!(sample.wi, Vec3::new(-1.0, 1.0, 0.0).unit());
+        assert!(sample.flags.contains(LobeFlags::SPECULAR | LobeFlags::REFLECTION));
+
+        let glass = Translucent::new(colors::WHITE, 1.5, 0.0);
+        let sample = glass.sample(&Vec3::J, &hit, &mut rand).unwrap();
+        assert!(sample.flags.is_specular());
+        assert!(sample.flags.contains(LobeFlags::TRANSMISSION) || sample.flags.contains(LobeFlags::REFLECTION));
+
+        let light = Emissive::new(colors::WHITE, 2.0);
+        assert!(light.sample(&Vec3::J, &hit, &mut rand).is_none());
+        assert_eq!(light.emit(&hit), ColorRGB::new(2.0, 2.0, 2.0));
+    }
+
+    #[test]
+    fn textured_parameters() {
+        let mut rand = Rand::seeded(0);
+        let checker: TextureRef<f64> = Arc::new(Checker::new(Arc::new(0.0), Arc::new(0.5), 2.0, Mapping::Uv));
+        let wo = Vec3::new(1.0, 1.0, 0.0).unit();
+
+        // Rough in some places and a mirror in others, so it has to be glossy overall
+        let metal = Reflective::new(colors::WHITE, checker.clone());
+        assert!(metal.flags().contains(LobeFlags::GLOSSY));
+        let mirror_hit = hit_on_floor(Arc::new(DiffuseLambert::new(colors::WHITE))).with_uv((0.1, 0.1));
+        assert!(metal.sample(&wo, &mirror_hit, &mut rand).unwrap().flags.is_specular());
+        let rough_hit = hit_on_floor(Arc::new(DiffuseLambert::new(colors::WHITE))).with_uv((0.6, 0.1));
+        assert!(!metal.sample(&wo, &rough_hit, &mut rand).unwrap().flags.is_specular());
+
+        let mix = Mix::new(Arc::new(DiffuseLambert::new(colors::BLACK)), Arc::new(DiffuseLambert::new(colors::WHITE)),
+            checker);
+        assert_eq!(mix.eval(&wo, &Vec3::J, &mirror_hit), colors::BLACK);
+        assert_eq!(mix.eval(&wo, &Vec3::J, &rough_hit), (0.5 / consts::PI) * colors::WHITE);
+    }
+
+    /// A height field that rises steadily along u
+    #[derive(Debug)]
+    struct Ramp;
+
+    impl Texture<f64> for Ramp {
+        fn value(&self, hit: &Hit) -> f64 {
+            hit.uv.0
+        }
+    }
+
+    #[test]
+    fn normal_detail_tilts_shading_normal() {
+        let lambert: Arc<dyn Material> = Arc::new(DiffuseLambert::new(colors::WHITE));
+        let hit = hit_on_floor(lambert.clone()).with_surface((0.5, 0.5), Vec3::I, -Vec3::K);
+        let tilted = Vec3::new(-1.0, 1.0, 0.0).unit();
+
+        // The flat color of a normal map leaves the normal alone
+        let flat = Bumped::new(lambert.clone(), NormalDetail::Map(Arc::new(ColorRGB::new(0.5, 0.5, 1.0))));
+        assert_eq!(flat.shading_hit(&hit).normal, Vec3::J);
+
+        let mapped = Bumped::new(lambert.clone(), NormalDetail::Map(Arc::new(ColorRGB::new(0.0, 0.5, 1.0))));
+        let shading = mapped.shading_hit(&hit);
+        assert_eq!(shading.normal, tilted);
+        assert_eq!(shading.geometric_normal, Vec3::J);
+
+        // A surface rising along x faces back towards -x
+        let bumped = Bumped::new(lambert.clone(), NormalDetail::Bump(Arc::new(Ramp), 1.0));
+        assert_eq!(bumped.shading_hit(&hit).normal, tilted);
+        assert_eq!(bumped.eval(&Vec3::J, &tilted, &hit), (1.0 / consts::PI) * colors::WHITE);
+
+        // Detail can't turn the normal away from the side that was hit
+        let flipped = Bumped::new(lambert, NormalDetail::Map(Arc::new(ColorRGB::new(0.5, 0.5, 0.0))));
+        assert_eq!(flipped.shading_hit(&hit).normal, Vec3::J);
     }
 }
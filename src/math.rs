@@ -1,15 +1,45 @@
 use rand::{
     distributions::{ Distribution, Uniform },
-    rngs::ThreadRng
+    RngCore,
+    SeedableRng
 };
+use rand_pcg::Pcg32;
 use std::ops;
 
+use crate::sampler::Sampler;
+
 const FLOAT_THRESHOLD: f64 = 0.0001;
 
+/// A source of random numbers, drawn from `dist` using a seedable generator
 #[derive(Debug)]
-pub struct Rand {
+pub struct Rand<R = Pcg32> {
     pub dist: Uniform<f64>,
-    pub rng: ThreadRng
+    pub rng: R,
+    /// Supplies the well-spread dimensions of the current sample, if there's one for it
+    pub sampler: Option<Box<dyn Sampler>>
+}
+
+impl<R: SeedableRng> Rand<R> {
+    /// Creates a generator of uniform numbers in [0, 1)
+    pub fn seeded(seed: u64) -> Rand<R> {
+        Rand { dist: Uniform::from(0.0..1.0), rng: R::seed_from_u64(seed), sampler: None }
+    }
+
+    /// Creates the generator for one sample of one pixel. Every sample gets its own independent
+    /// sequence derived from the render's seed, so images don't depend on which thread rendered
+    /// which sample.
+    pub fn for_sample(seed: u64, pixel: u64, sample: u32) -> Rand<R> {
+        Rand::seeded(mix(mix(mix(seed) ^ pixel) ^ u64::from(sample)))
+    }
+}
+
+/// Scrambles the bits of a 64-bit value (the SplitMix64 finalizer), so that nearby inputs give
+/// unrelated outputs
+pub fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
 }
 
 /// Evaluate the equality of two floats to within a threshold value of FLOAT_THRESHOLD
@@ -45,19 +75,43 @@ pub fn f_clamp(val: f64, min: f64, max: f64) -> f64 {
 }
 
 /// Generate a random float given a distribution
-pub fn rand_f64(rand: &mut Rand) -> f64 {
+pub fn rand_f64<R: RngCore>(rand: &mut Rand<R>) -> f64 {
     rand.dist.sample(&mut rand.rng)
 }
 
+/// Takes the next dimension of the current sample from its sampler, or a random float without one
+pub fn sample_1d<R: RngCore>(rand: &mut Rand<R>) -> f64 {
+    match rand.sampler.as_mut() {
+        Some(sampler) => sampler.get_1d(),
+        None => rand_f64(rand)
+    }
+}
+
+/// Takes the next two dimensions of the current sample from its sampler, or two random floats
+/// without one
+pub fn sample_2d<R: RngCore>(rand: &mut Rand<R>) -> (f64, f64) {
+    match rand.sampler.as_mut() {
+        Some(sampler) => sampler.get_2d(),
+        None => (rand_f64(rand), rand_f64(rand))
+    }
+}
+
 /// Calculate a Schlick approximation for the specular reflection coefficient
 pub fn schlick(cos_theta_i: f64, index_i: f64, index_r: f64) -> f64 {
     let r_0 = ((index_i - index_r) / (index_i + index_r)).powi(2);
     r_0 + (1.0 - r_0) * (1.0 - cos_theta_i).powi(5)
 }
 
+/// Weighs a sample taken with density `pdf` against another way of sampling the same thing with
+/// density `other_pdf`, using Veach's power heuristic for multiple importance sampling
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (pdf, other_pdf) = (pdf * pdf, other_pdf * other_pdf);
+    if pdf + other_pdf > 0.0 { pdf / (pdf + other_pdf) } else { 0.0 }
+}
+
 /// Find the max of more than two values
 pub fn f_max_all(floats: Vec<f64>) -> f64 {
-    let max = floats.get(0);
+    let max = floats.first();
     if let Some(max) = max {
         let mut max = *max;
         for f in floats {
@@ -72,7 +126,7 @@ pub fn f_max_all(floats: Vec<f64>) -> f64 {
 
 /// Find the min of more than two values
 pub fn f_min_all(floats: Vec<f64>) -> f64 {
-    let min = floats.get(0);
+    let min = floats.first();
     if let Some(min) = min {
         let mut min = *min;
         for f in floats {
@@ -96,6 +150,16 @@ mod tests {
         assert!(f_eq(f1, f2), "{} =/= {}", f1, f2);
     }
 
+    #[test]
+    fn seeded_sequences() {
+        let draw = |mut rand: Rand| (0..4).map(|_| rand_f64(&mut rand)).collect::<Vec<_>>();
+        assert_eq!(draw(Rand::seeded(1)), draw(Rand::seeded(1)));
+        assert_ne!(draw(Rand::seeded(1)), draw(Rand::seeded(2)));
+        assert_eq!(draw(Rand::for_sample(1, 10, 3)), draw(Rand::for_sample(1, 10, 3)));
+        assert_ne!(draw(Rand::for_sample(1, 10, 3)), draw(Rand::for_sample(1, 10, 4)));
+        assert_ne!(draw(Rand::for_sample(1, 10, 3)), draw(Rand::for_sample(1, 11, 3)));
+    }
+
     #[test]
     fn lerp_floats() {
         let start = 1.0;
@@ -0,0 +1,319 @@
+use std::f64::consts;
+
+use crate::vec::{ Color, ColorRGB, Coord, Vec3 };
+
+/// An orthonormal basis around a surface normal, for working with directions in the surface's own
+/// coordinates, where the normal is +z
+#[derive(Debug)]
+pub struct Frame {
+    s: Vec3,
+    t: Vec3,
+    n: Vec3
+}
+
+impl Frame {
+    /// Builds a basis around a unit normal, choosing the tangents arbitrarily but continuously
+    pub fn from_normal(n: &Vec3) -> Frame {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let (x, y, z) = (n[Coord::X], n[Coord::Y], n[Coord::Z]);
+        let sign = 1.0_f64.copysign(z);
+        let a = -1.0 / (sign + z);
+        let b = x * y * a;
+
+        Frame {
+            s: Vec3::new(1.0 + sign * x * x * a, sign * b, -sign * x),
+            t: Vec3::new(b, sign + y * y * a, -y),
+            n: n.clone()
+        }
+    }
+
+    /// Builds a basis around a unit normal whose first tangent follows `tangent` as closely as
+    /// possible, so anisotropy lines up with the surface's parameterization. Falls back to an
+    /// arbitrary basis where the tangent is degenerate.
+    pub fn from_normal_tangent(n: &Vec3, tangent: &Vec3) -> Frame {
+        let s = tangent - n.dot(tangent) * n;
+        if s.norm() < 1e-9 {
+            return Frame::from_normal(n);
+        }
+
+        let s = s.unit();
+        Frame { t: n.cross(&s), s, n: n.clone() }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v[Coord::X] * &self.s + v[Coord::Y] * &self.t + v[Coord::Z] * &self.n
+    }
+}
+
+/// The GGX (Trowbridge-Reitz) distribution of microfacet normals, with separate roughnesses along
+/// the two tangents. Directions are in surface coordinates.
+#[derive(Clone, Debug)]
+pub struct Ggx {
+    alpha_x: f64,
+    alpha_y: f64
+}
+
+impl Ggx {
+    /// Below this roughness, surfaces are treated as perfectly smooth
+    const SMOOTH_ALPHA: f64 = 1e-3;
+
+    /// Maps perceptual roughness and anisotropy, both in [0, 1], to the distribution's widths the
+    /// way Disney's principled BRDF does: alpha is the square of the roughness, and anisotropy
+    /// stretches it along the first tangent
+    pub fn new(roughness: f64, anisotropy: f64) -> Ggx {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        Ggx { alpha_x: alpha / aspect, alpha_y: alpha * aspect }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < Ggx::SMOOTH_ALPHA
+    }
+
+    /// The density of microfacets facing `h`, per unit solid angle and projected area
+    pub fn d(&self, h: &Vec3) -> f64 {
+        if h[Coord::Z] <= 0.0 {
+            return 0.0;
+        }
+
+        let x = h[Coord::X] / self.alpha_x;
+        let y = h[Coord::Y] / self.alpha_y;
+        let denom = x * x + y * y + h[Coord::Z] * h[Coord::Z];
+        1.0 / (consts::PI * self.alpha_x * self.alpha_y * denom * denom)
+    }
+
+    /// Smith's auxiliary function, which measures how much of the surface is hidden from `w`
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let z2 = w[Coord::Z] * w[Coord::Z];
+        if z2 <= 0.0 {
+            return f64::INFINITY;
+        }
+
+        let x = self.alpha_x * w[Coord::X];
+        let y = self.alpha_y * w[Coord::Y];
+        0.5 * ((1.0 + (x * x + y * y) / z2).sqrt() - 1.0)
+    }
+
+    /// The fraction of microfacets facing `w` that aren't hidden from it
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// The fraction of microfacets visible from both `wo` and `wi`, with heights correlated
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Picks a microfacet normal in proportion to how much of it is visible from `wo`, which must
+    /// be above the surface (Heitz, "Sampling the GGX Distribution of Visible Normals")
+    pub fn sample_visible(&self, wo: &Vec3, (u, v): (f64, f64)) -> Vec3 {
+        // Stretch the view into the configuration where the distribution is a hemisphere
+        let view = Vec3::new(self.alpha_x * wo[Coord::X], self.alpha_y * wo[Coord::Y], wo[Coord::Z]).unit();
+
+        let len_sq = view[Coord::X] * view[Coord::X] + view[Coord::Y] * view[Coord::Y];
+        let t1 = if len_sq > 0.0 {
+            (1.0 / len_sq.sqrt()) * Vec3::new(-view[Coord::Y], view[Coord::X], 0.0)
+        } else {
+            Vec3::I
+        };
+        let t2 = view.cross(&t1);
+
+        // Pick a point on the half of the disc facing the view, then project it up onto the hemisphere
+        let r = u.sqrt();
+        let phi = 2.0 * consts::PI * v;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + view[Coord::Z]);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let normal = p1 * t1 + p2 * t2 + p3 * view;
+
+        Vec3::new(self.alpha_x * normal[Coord::X], self.alpha_y * normal[Coord::Y],
+            normal[Coord::Z].max(0.0)).unit()
+    }
+
+    /// The density, per unit solid angle, with which `sample_visible` picks `h`
+    pub fn visible_pdf(&self, wo: &Vec3, h: &Vec3) -> f64 {
+        if wo[Coord::Z] <= 0.0 {
+            return 0.0;
+        }
+
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo[Coord::Z]
+    }
+}
+
+/// The direction a ray leaving in `wo` arrived from if it was mirrored about `h`
+pub fn reflect(wo: &Vec3, h: &Vec3) -> Vec3 {
+    2.0 * wo.dot(h) * h - wo
+}
+
+/// The direction a ray leaving in `wo` arrived from if it was refracted through a surface facing
+/// `n`, where `eta` is the refractive index of the far side relative to the side `n` faces. Returns
+/// `None` if the light would be totally internally reflected instead.
+pub fn refract(wo: &Vec3, n: &Vec3, eta: f64) -> Option<Vec3> {
+    let (mut cos_theta_o, mut eta, mut n) = (wo.dot(n), eta, n.clone());
+    if cos_theta_o < 0.0 {
+        cos_theta_o = -cos_theta_o;
+        eta = 1.0 / eta;
+        n = -n;
+    }
+
+    let sin2_theta_i = (1.0 - cos_theta_o * cos_theta_o).max(0.0) / (eta * eta);
+    if sin2_theta_i >= 1.0 {
+        return None;
+    }
+    let cos_theta_i = (1.0 - sin2_theta_i).sqrt();
+    Some((-1.0 / eta) * wo + (cos_theta_o / eta - cos_theta_i) * n)
+}
+
+/// The fraction of light a dielectric reflects at an angle whose cosine is `cos_theta_i`, where
+/// `eta` is the refractive index of the far side of the surface relative to the near side. Light
+/// arriving from below the surface, with a negative cosine, sees the inverse index.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (mut cos_theta_i, mut eta) = (cos_theta_i.clamp(-1.0, 1.0), eta);
+    if cos_theta_i < 0.0 {
+        cos_theta_i = -cos_theta_i;
+        eta = 1.0 / eta;
+    }
+
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// The fraction of light a conductor reflects at an angle whose cosine is `cos_theta_i`, from the
+/// real (`eta`) and imaginary (`k`) parts of its refractive index relative to the outside
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &ColorRGB, k: &ColorRGB) -> ColorRGB {
+    let cos2 = cos_theta_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+
+    let mut reflectance = ColorRGB::O;
+    for &channel in &[Color::R, Color::G, Color::B] {
+        let (eta2, k2) = (eta[channel] * eta[channel], k[channel] * k[channel]);
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+        let t1 = a2_plus_b2 + cos2;
+        let t2 = 2.0 * cos2.sqrt() * a;
+        let r_s = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let r_p = r_s * (t3 - t4) / (t3 + t4);
+
+        reflectance[channel] = 0.5 * (r_s + r_p);
+    }
+
+    reflectance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math;
+
+    #[test]
+    fn frame_round_trip() {
+        for n in &[Vec3::K, -Vec3::K, Vec3::new(0.3, -0.5, 0.8).unit()] {
+            let frame = Frame::from_normal(n);
+            assert_eq!(frame.to_local(n), Vec3::K);
+
+            let v = Vec3::new(0.2, 0.7, -0.4);
+            assert_eq!(frame.to_world(&frame.to_local(&v)), v);
+        }
+
+        let n = Vec3::new(0.3, -0.5, 0.8).unit();
+        let frame = Frame::from_normal_tangent(&n, &Vec3::I);
+        assert_eq!(frame.to_local(&n), Vec3::K);
+        assert!(frame.to_local(&Vec3::I)[Coord::X] > 0.0 && math::f_eq(frame.to_local(&Vec3::I)[Coord::Y], 0.0));
+        assert_eq!(frame.to_world(&frame.to_local(&Vec3::J)), Vec3::J);
+        // A tangent along the normal says nothing about the surface, so any basis will do
+        assert_eq!(Frame::from_normal_tangent(&n, &n).to_local(&n), Vec3::K);
+    }
+
+    #[test]
+    fn projected_normals_cover_the_surface() {
+        // Microfacets' projected areas add up to the area of the surface they make up
+        for ggx in &[Ggx::new(0.5, 0.0), Ggx::new(0.7, 0.8)] {
+            let steps = 800;
+            let mut total = 0.0;
+            for i in 0..steps {
+                for j in 0..steps {
+                    let h = Vec3::on_unit_sphere((f64::from(i) + 0.5) / f64::from(steps),
+                        (f64::from(j) + 0.5) / f64::from(steps));
+                    total += ggx.d(&h) * h[Coord::Z].max(0.0);
+                }
+            }
+            total *= 4.0 * consts::PI / f64::from(steps * steps);
+            assert!((total - 1.0).abs() < 1e-2, "Projected area is {}", total);
+        }
+    }
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let ggx = Ggx::new(0.6, 0.5);
+        let wo = Vec3::new(0.6, -0.3, 0.5).unit();
+        for i in 0..10 {
+            for j in 0..10 {
+                let h = ggx.sample_visible(&wo, ((f64::from(i) + 0.5) / 10.0, (f64::from(j) + 0.5) / 10.0));
+                assert!(math::f_eq(h.norm(), 1.0));
+                assert!(h[Coord::Z] >= 0.0 && wo.dot(&h) >= 0.0);
+                assert!(ggx.visible_pdf(&wo, &h) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn dielectric_fresnel() {
+        // At normal incidence, reflectance is ((eta - 1) / (eta + 1))^2 from either side
+        assert!(math::f_eq(fresnel_dielectric(1.0, 1.5), 0.04));
+        assert!(math::f_eq(fresnel_dielectric(-1.0, 1.5), 0.04));
+        assert_eq!(fresnel_dielectric(0.0, 1.5), 1.0);
+
+        // Past the critical angle inside glass, everything is reflected
+        let critical = (1.0_f64 / 1.5).asin();
+        assert!(fresnel_dielectric((critical - 0.01).cos(), 1.0 / 1.5) < 1.0);
+        assert_eq!(fresnel_dielectric((critical + 0.01).cos(), 1.0 / 1.5), 1.0);
+    }
+
+    #[test]
+    fn refraction_follows_snells_law() {
+        let n = Vec3::K;
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        let wi = refract(&wo, &n, 1.5).unwrap();
+        assert!(math::f_eq(wi.norm(), 1.0));
+        assert!(math::f_eq(wi[Coord::X], -0.6 / 1.5));
+        assert!(wi[Coord::Z] < 0.0);
+
+        // Light can retrace its path, unless it hits the surface from inside too steeply
+        assert_eq!(refract(&wi, &n, 1.5).unwrap(), wo);
+        assert!(refract(&Vec3::new(0.8, 0.0, -0.6), &n, 1.5).is_none());
+    }
+
+    #[test]
+    fn conductor_fresnel() {
+        let eta = ColorRGB::new(0.2, 0.9, 1.1);
+        let k = ColorRGB::new(3.9, 2.5, 2.1);
+
+        // At normal incidence, reflectance is ((eta - 1)^2 + k^2) / ((eta + 1)^2 + k^2)
+        let normal = fresnel_conductor(1.0, &eta, &k);
+        for &channel in &[Color::R, Color::G, Color::B] {
+            let (n, k) = (eta[channel], k[channel]);
+            let expected = ((n - 1.0).powi(2) + k * k) / ((n + 1.0).powi(2) + k * k);
+            assert!(math::f_eq(normal[channel], expected));
+        }
+
+        // Everything is reflected at grazing angles
+        assert_eq!(fresnel_conductor(0.0, &eta, &k), ColorRGB::new(1.0, 1.0, 1.0));
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::{ self, Write };
+
+use super::Encoder;
+use crate::framebuffer::Framebuffer;
+use crate::vec::Color;
+
+const MAGIC: [u8; 4] = [0x76, 0x2F, 0x31, 0x01];
+const PIXEL_TYPE_FLOAT: i32 = 2;
+
+/// Writes uncompressed scanline OpenEXR images of the linear, unclamped radiance, with 32-bit
+/// float channels
+pub struct ExrEncoder;
+
+fn write_attribute(out: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.extend_from_slice(kind.as_bytes());
+    out.push(0);
+    out.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn header(width: u32, height: u32) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    // Version 2, single-part scanline image
+    header.extend_from_slice(&2_i32.to_le_bytes());
+
+    // Channels are stored in alphabetical order
+    let mut channels = vec![];
+    for name in &["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and reserved bytes, then x and y sampling
+        channels.extend_from_slice(&[0, 0, 0, 0]);
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+        channels.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    channels.push(0);
+    write_attribute(&mut header, "channels", "chlist", &channels);
+
+    write_attribute(&mut header, "compression", "compression", &[0]);
+
+    let mut window = vec![];
+    for bound in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&bound.to_le_bytes());
+    }
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(&mut header, "pixelAspectRatio", "float", &1_f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(&mut header, "screenWindowWidth", "float", &1_f32.to_le_bytes());
+    header.push(0);
+
+    header
+}
+
+impl Encoder for ExrEncoder {
+    fn encode(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        let (width, height) = (image.width(), image.height());
+        let header = header(width, height);
+        out.write_all(&header)?;
+
+        // Each scanline is its own chunk: a y coordinate, a byte count, then every channel's row
+        let line_size = 3 * 4 * width as usize;
+        let chunk_size = (4 + 4 + line_size) as u64;
+        let first_chunk = (header.len() + 8 * height as usize) as u64;
+        for y in 0..u64::from(height) {
+            out.write_all(&(first_chunk + y * chunk_size).to_le_bytes())?;
+        }
+
+        let mut chunk = Vec::with_capacity(8 + line_size);
+        for (y, row) in image.rows().enumerate() {
+            chunk.clear();
+            chunk.extend_from_slice(&(y as i32).to_le_bytes());
+            chunk.extend_from_slice(&(line_size as i32).to_le_bytes());
+            for &channel in &[Color::B, Color::G, Color::R] {
+                for pixel in row {
+                    chunk.extend_from_slice(&(pixel[channel] as f32).to_le_bytes());
+                }
+            }
+            out.write_all(&chunk)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::ColorRGB;
+
+    #[test]
+    fn exr_layout() {
+        let mut out = vec![];
+        let image = Framebuffer::from_pixels(3, 2, vec![ColorRGB::new(1.0, 2.0, 4.0); 6]).unwrap();
+        ExrEncoder.encode(&image, &mut out).unwrap();
+
+        let header_len = header(3, 2).len();
+        assert_eq!(&out[..4], &MAGIC);
+        assert_eq!(out.len(), header_len + 2 * 8 + 2 * (8 + 3 * 4 * 3));
+
+        let first_chunk = u64::from_le_bytes([
+            out[header_len], out[header_len + 1], out[header_len + 2], out[header_len + 3],
+            out[header_len + 4], out[header_len + 5], out[header_len + 6], out[header_len + 7]
+        ]) as usize;
+        assert_eq!(first_chunk, header_len + 16);
+
+        // The first value of the first scanline is the blue channel
+        let blue = &out[first_chunk + 8..first_chunk + 12];
+        assert_eq!(f32::from_le_bytes([blue[0], blue[1], blue[2], blue[3]]), 4.0);
+    }
+}
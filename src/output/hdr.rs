@@ -0,0 +1,108 @@
+use std::io::{ self, Write };
+
+use super::Encoder;
+use crate::framebuffer::Framebuffer;
+use crate::vec::{ Color, ColorRGB };
+
+/// Writes Radiance RGBE (.hdr) images of the linear, unclamped radiance
+pub struct HdrEncoder;
+
+/// Packs a color into a shared-exponent RGBE pixel
+pub fn to_rgbe(color: &ColorRGB) -> [u8; 4] {
+    let r = f64::max(0.0, color[Color::R]);
+    let g = f64::max(0.0, color[Color::G]);
+    let b = f64::max(0.0, color[Color::B]);
+    let max = f64::max(r, f64::max(g, b));
+
+    if max < 1e-32 || !max.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // max = mantissa * 2^exponent, with the mantissa in [0.5, 1)
+    let mut exponent = max.log2().floor() as i32 + 1;
+    let mut mantissa = max / 2_f64.powi(exponent);
+    if mantissa >= 1.0 {
+        mantissa *= 0.5;
+        exponent += 1;
+    }
+
+    let scale = mantissa * 256.0 / max;
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (exponent + 128).clamp(0, 255) as u8
+    ]
+}
+
+/// Run-length encodes one channel of a scanline
+fn write_rle_channel(out: &mut Vec<u8>, values: &[u8]) {
+    let mut i = 0;
+    while i < values.len() {
+        let run = values[i..].iter().take(127).take_while(|&&v| v == values[i]).count();
+        if run >= 3 {
+            out.push(128 + run as u8);
+            out.push(values[i]);
+            i += run;
+            continue;
+        }
+
+        // Collect literals up to the start of the next run
+        let start = i;
+        while i < values.len() && i - start < 128 {
+            let ahead = values[i..].iter().take(3).take_while(|&&v| v == values[i]).count();
+            if ahead >= 3 { break; }
+            i += 1;
+        }
+        out.push((i - start) as u8);
+        out.extend_from_slice(&values[start..i]);
+    }
+}
+
+impl Encoder for HdrEncoder {
+    fn encode(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        let width = image.width();
+        write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height(), width)?;
+
+        let rle = (8..0x8000).contains(&width);
+        let mut scanline = Vec::with_capacity(4 * width as usize);
+        for row in image.rows() {
+            let rgbe: Vec<_> = row.iter().map(to_rgbe).collect();
+
+            scanline.clear();
+            if rle {
+                scanline.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xFF) as u8]);
+                for channel in 0..4 {
+                    let values: Vec<_> = rgbe.iter().map(|pixel| pixel[channel]).collect();
+                    write_rle_channel(&mut scanline, &values);
+                }
+            } else {
+                for pixel in &rgbe {
+                    scanline.extend_from_slice(pixel);
+                }
+            }
+            out.write_all(&scanline)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe_pixels() {
+        assert_eq!(to_rgbe(&ColorRGB::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe(&ColorRGB::new(16.0, 16.0, 16.0)), [128, 128, 128, 133]);
+        assert_eq!(to_rgbe(&ColorRGB::new(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rle_channel() {
+        let mut out = vec![];
+        write_rle_channel(&mut out, &[1, 2, 3, 3, 3, 3, 4]);
+        assert_eq!(out, vec![2, 1, 2, 132, 3, 1, 4]);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::{ self, File };
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+use std::process;
+
+use crate::framebuffer::Framebuffer;
+use crate::math::f_clamp;
+use crate::tonemap::ToneMapping;
+use crate::vec::{ Color, ColorRGB };
+
+pub mod exr;
+pub mod hdr;
+pub mod png;
+pub mod ppm;
+pub mod zlib;
+
+/// Writes an image to any byte stream
+pub trait Encoder {
+    fn encode(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    PpmAscii,
+    PpmBinary,
+    Png,
+    Hdr,
+    Exr
+}
+
+impl ImageFormat {
+    /// Picks a format from a name or file extension, e.g. "png"
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match &name.to_ascii_lowercase()[..] {
+            "ppm" | "p6" => Some(ImageFormat::PpmBinary),
+            "p3" | "ppm-ascii" => Some(ImageFormat::PpmAscii),
+            "png" => Some(ImageFormat::Png),
+            "hdr" => Some(ImageFormat::Hdr),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None
+        }
+    }
+
+    /// Picks a format from a path's file extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        path.extension().and_then(|ext| ext.to_str()).and_then(ImageFormat::from_name)
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::PpmAscii | ImageFormat::PpmBinary => "ppm",
+            ImageFormat::Png => "png",
+            ImageFormat::Hdr => "hdr",
+            ImageFormat::Exr => "exr"
+        }
+    }
+
+    /// Whether the format stores linear radiance rather than tone-mapped display values
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ImageFormat::Hdr | ImageFormat::Exr)
+    }
+
+    /// Creates an encoder for the format. `tone_map` is only used by 8-bit formats.
+    pub fn encoder(&self, tone_map: ToneMapping) -> Box<dyn Encoder> {
+        match self {
+            ImageFormat::PpmAscii => Box::new(ppm::PpmEncoder { binary: false, tone_map }),
+            ImageFormat::PpmBinary => Box::new(ppm::PpmEncoder { binary: true, tone_map }),
+            ImageFormat::Png => Box::new(png::PngEncoder { tone_map }),
+            ImageFormat::Hdr => Box::new(hdr::HdrEncoder),
+            ImageFormat::Exr => Box::new(exr::ExrEncoder)
+        }
+    }
+}
+
+/// Maps a linear pixel to 8-bit color through the display transform
+pub fn quantize(pixel: &ColorRGB, tone_map: &ToneMapping) -> [u8; 3] {
+    let display = tone_map.apply(pixel);
+    let (r, g, b) = (display[Color::R], display[Color::G], display[Color::B]);
+
+    [
+        (256.0 * f_clamp(r, 0.0, 0.999)) as u8,
+        (256.0 * f_clamp(g, 0.0, 0.999)) as u8,
+        (256.0 * f_clamp(b, 0.0, 0.999)) as u8
+    ]
+}
+
+/// Quantizes a whole image into packed 8-bit RGB
+pub fn quantize_all(pixels: &[ColorRGB], tone_map: &ToneMapping) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(3 * pixels.len());
+    for pixel in pixels {
+        bytes.extend_from_slice(&quantize(pixel, tone_map));
+    }
+
+    bytes
+}
+
+/// Writes a file by writing to a temporary file next to `path`, which then replaces it, so readers
+/// never see a half-written file
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> io::Result<()>
+{
+    let file_name = path.file_name().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput, format!("{} is not a file path", path.display())
+    ))?;
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name.to_string_lossy(), process::id()));
+
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        out.flush()
+    });
+
+    match result.and_then(|_| fs::rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+/// Encodes an image into a file, atomically replacing anything already there
+pub fn write_image(path: &Path, format: ImageFormat, image: &Framebuffer, tone_map: &ToneMapping)
+    -> io::Result<()>
+{
+    write_atomically(path, |out| format.encoder(*tone_map).encode(image, out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_clamps() {
+        let pixel = ColorRGB::new(1.0, 0.25, -0.25);
+        assert_eq!(quantize(&pixel, &ToneMapping::default()), [255, 137, 0]);
+    }
+
+    #[test]
+    fn writes_replace_files() {
+        let dir = std::env::temp_dir().join(format!("raytracer-output-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.ppm");
+        fs::write(&path, "old").unwrap();
+
+        let image = Framebuffer::new(2, 2);
+        write_image(&path, ImageFormat::PpmBinary, &image, &ToneMapping::default()).unwrap();
+
+        assert!(fs::read(&path).unwrap().starts_with(b"P6\n2 2\n"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(ImageFormat::from_path(Path::new("out.PNG")), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path(Path::new("frames/out.ppm")), Some(ImageFormat::PpmBinary));
+        assert_eq!(ImageFormat::from_path(Path::new("render.exr")), Some(ImageFormat::Exr));
+        assert_eq!(ImageFormat::from_path(Path::new("out")), None);
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::{ self, Write };
+
+use super::{ quantize_all, zlib, Encoder };
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::ToneMapping;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const BYTES_PER_PIXEL: usize = 3;
+
+/// Writes 8-bit RGB PNGs
+pub struct PngEncoder {
+    pub tone_map: ToneMapping
+}
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+
+    table
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let table = crc_table();
+    let mut crc = 0xFFFF_FFFF;
+    for &byte in data {
+        crc = table[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    crc ^ 0xFFFF_FFFF
+}
+
+fn write_chunk(out: &mut dyn Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+
+    let mut contents = kind.to_vec();
+    contents.extend_from_slice(data);
+    out.write_all(&contents)?;
+    out.write_all(&crc32(&contents).to_be_bytes())
+}
+
+pub fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Applies a PNG filter to one scanline, given the unfiltered scanline above it
+fn filter_row(filter: u8, row: &[u8], above: &[u8], out: &mut Vec<u8>) {
+    out.push(filter);
+    for i in 0..row.len() {
+        let left = if i >= BYTES_PER_PIXEL { row[i - BYTES_PER_PIXEL] } else { 0 };
+        let up = above[i];
+        let up_left = if i >= BYTES_PER_PIXEL { above[i - BYTES_PER_PIXEL] } else { 0 };
+
+        let predicted = match filter {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+            _ => paeth(left, up, up_left)
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+/// Filters every scanline, picking the filter whose output has the smallest sum of absolute
+/// differences
+fn filter_image(bytes: &[u8], width: usize) -> Vec<u8> {
+    let stride = width * BYTES_PER_PIXEL;
+    let zeros = vec![0; stride];
+    let mut filtered = Vec::with_capacity(bytes.len() + bytes.len() / stride.max(1));
+    let mut candidate = Vec::with_capacity(stride + 1);
+
+    for (i, row) in bytes.chunks(stride).enumerate() {
+        let above = if i == 0 { &zeros[..] } else { &bytes[(i - 1) * stride..i * stride] };
+
+        let mut best: Option<(u64, Vec<u8>)> = None;
+        for filter in 0..5 {
+            candidate.clear();
+            filter_row(filter, row, above, &mut candidate);
+            let cost = candidate[1..].iter().map(|&b| u64::from((b as i8).unsigned_abs())).sum();
+            match best {
+                Some((best_cost, _)) if best_cost <= cost => {},
+                _ => best = Some((cost, candidate.clone()))
+            }
+        }
+
+        if let Some((_, row)) = best {
+            filtered.extend(row);
+        }
+    }
+
+    filtered
+}
+
+impl Encoder for PngEncoder {
+    fn encode(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&SIGNATURE)?;
+
+        let mut header = vec![];
+        header.extend_from_slice(&image.width().to_be_bytes());
+        header.extend_from_slice(&image.height().to_be_bytes());
+        // 8 bits per channel, RGB, deflate, adaptive filtering, no interlacing
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        let bytes = quantize_all(image.pixels(), &self.tone_map);
+        let filtered = filter_image(&bytes, image.width() as usize);
+        write_chunk(out, b"IDAT", &zlib::compress(&filtered))?;
+
+        write_chunk(out, b"IEND", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::colors;
+
+    #[test]
+    fn crc32_checksum() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn png_header() {
+        let mut out = vec![];
+        let image = Framebuffer::from_pixels(3, 2, vec![colors::WHITE; 6]).unwrap();
+        PngEncoder { tone_map: ToneMapping::default() }.encode(&image, &mut out).unwrap();
+
+        assert_eq!(&out[..8], &SIGNATURE);
+        assert_eq!(&out[12..16], b"IHDR");
+        assert_eq!(&out[16..24], &[0, 0, 0, 3, 0, 0, 0, 2]);
+        assert_eq!(&out[out.len() - 8..out.len() - 4], b"IEND");
+    }
+
+    #[test]
+    fn paeth_predictor() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 20), 10);
+    }
+}
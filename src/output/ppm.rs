@@ -0,0 +1,55 @@
+use std::io::{ self, Write };
+
+use super::{ quantize, Encoder };
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::ToneMapping;
+
+const MAX_COLORS: u32 = 255;
+
+/// Writes ASCII (P3) or binary (P6) PPMs
+pub struct PpmEncoder {
+    pub binary: bool,
+    pub tone_map: ToneMapping
+}
+
+impl Encoder for PpmEncoder {
+    fn encode(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        let magic = if self.binary { "P6" } else { "P3" };
+        write!(out, "{}\n{} {}\n{}\n", magic, image.width(), image.height(), MAX_COLORS)?;
+
+        for pixel in image.pixels() {
+            let [r, g, b] = quantize(pixel, &self.tone_map);
+            if self.binary {
+                out.write_all(&[r, g, b])?;
+            } else {
+                writeln!(out, "{} {} {}", r, g, b)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec::colors;
+
+    fn two_pixels(width: u32, height: u32) -> Framebuffer {
+        Framebuffer::from_pixels(width, height, vec![colors::WHITE, colors::BLACK]).unwrap()
+    }
+
+    #[test]
+    fn binary_ppm() {
+        let mut out = vec![];
+        PpmEncoder { binary: true, tone_map: ToneMapping::default() }.encode(&two_pixels(2, 1), &mut out).unwrap();
+        assert_eq!(out, b"P6\n2 1\n255\n\xff\xff\xff\x00\x00\x00");
+    }
+
+    #[test]
+    fn ascii_ppm() {
+        let mut out = vec![];
+        PpmEncoder { binary: false, tone_map: ToneMapping::default() }.encode(&two_pixels(1, 2), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "P3\n1 2\n255\n255 255 255\n0 0 0\n");
+    }
+}
@@ -0,0 +1,428 @@
+/// Base lengths for length symbols 257 through 285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+];
+/// Base distances for distance symbols 0 through 29
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+];
+
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 128;
+const HASH_BITS: usize = 15;
+
+/// Packs bits least-significant first, as DEFLATE expects
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { bytes: vec![], buffer: 0, count: 0 }
+    }
+
+    fn write_bits(&mut self, bits: u32, count: u32) {
+        self.buffer |= bits << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed most-significant bit first
+    fn write_code(&mut self, code: u32, length: u32) {
+        let mut reversed = 0;
+        for i in 0..length {
+            reversed |= ((code >> i) & 1) << (length - 1 - i);
+        }
+        self.write_bits(reversed, length);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Writes a literal/length symbol using the fixed Huffman code
+fn write_fixed_literal(writer: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xC0 + symbol - 280, 8)
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, dist: usize) {
+    let length_index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+    write_fixed_literal(writer, 257 + length_index as u32);
+    writer.write_bits((length - LENGTH_BASE[length_index] as usize) as u32, u32::from(LENGTH_EXTRA[length_index]));
+
+    let dist_index = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+    writer.write_code(dist_index as u32, 5);
+    writer.write_bits((dist - DIST_BASE[dist_index] as usize) as u32, u32::from(DIST_EXTRA[dist_index]));
+}
+
+fn hash(data: &[u8], i: usize) -> usize {
+    let h = (u32::from(data[i]) << 16) | (u32::from(data[i + 1]) << 8) | u32::from(data[i + 2]);
+    (h.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Compresses data into a raw DEFLATE stream, using LZ77 matching and the fixed Huffman code
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    // A single final block using the fixed Huffman code
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+
+    // Chains of previous positions with the same hash, for finding matches
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(data, i);
+            let max_length = usize::min(MAX_MATCH, data.len() - i);
+
+            let mut candidate = head[h];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..].iter().zip(&data[i..i + max_length])
+                    .take_while(|(a, b)| a == b).count();
+                if length > best.0 {
+                    best = (length, i - candidate);
+                    if length == max_length { break; }
+                }
+
+                candidate = prev[candidate % WINDOW_SIZE];
+                chain += 1;
+            }
+        }
+
+        let advance = if best.0 >= MIN_MATCH {
+            write_match(&mut writer, best.0, best.1);
+            best.0
+        } else {
+            write_fixed_literal(&mut writer, u32::from(data[i]));
+            1
+        };
+
+        for j in i..i + advance {
+            if j + MIN_MATCH <= data.len() {
+                let h = hash(data, j);
+                prev[j % WINDOW_SIZE] = head[h];
+                head[h] = j;
+            }
+        }
+        i += advance;
+    }
+
+    write_fixed_literal(&mut writer, 256);
+    writer.finish()
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // Sums can go this many bytes before needing to be reduced without overflowing
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+/// Wraps a DEFLATE stream in a zlib header and checksum
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // 32K window, default compression level; the header must be a multiple of 31
+    let mut out = vec![0x78, 0x9C];
+    out.extend(deflate(data));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Reads bits least-significant first, as DEFLATE packs them
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    index: usize,
+    buffer: u32,
+    count: u32
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader { bytes, index: 0, buffer: 0, count: 0 }
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u32> {
+        while self.count < count {
+            let byte = *self.bytes.get(self.index)?;
+            self.buffer |= u32::from(byte) << self.count;
+            self.index += 1;
+            self.count += 8;
+        }
+
+        let bits = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Some(bits)
+    }
+
+    /// Skips to the next byte boundary, for stored blocks
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, decoded one bit at a time from the number of codes of each length
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0; 16];
+        for length in 1..16 {
+            offsets[length] = offsets[length - 1] + counts[length - 1];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Option<u16> {
+        // Codes of each length follow on from the last code of the length before
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for length in 1..16 {
+            code |= reader.read_bits(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - count < first {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        None
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8
+        };
+    }
+
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+/// Reads the code lengths a dynamic block starts with, which are themselves Huffman coded
+fn dynamic_codes(reader: &mut BitReader) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+    let literal_count = reader.read_bits(5)? as usize + 257;
+    let dist_count = reader.read_bits(5)? as usize + 1;
+    let length_count = reader.read_bits(4)? as usize + 4;
+
+    let mut code_lengths = [0; 19];
+    for &symbol in &ORDER[..length_count] {
+        code_lengths[symbol] = reader.read_bits(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + dist_count);
+    while lengths.len() < literal_count + dist_count {
+        let (length, repeat) = match code_lengths.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last()?, 3 + reader.read_bits(2)?),
+            17 => (0, 3 + reader.read_bits(3)?),
+            18 => (0, 11 + reader.read_bits(7)?),
+            _ => return None
+        };
+        lengths.extend((0..repeat).map(|_| length));
+    }
+    if lengths.len() > literal_count + dist_count {
+        return None;
+    }
+
+    Some((Huffman::new(&lengths[..literal_count]), Huffman::new(&lengths[literal_count..])))
+}
+
+fn inflate_block(reader: &mut BitReader, literals: &Huffman, dists: &Huffman, out: &mut Vec<u8>, limit: usize)
+    -> Option<()>
+{
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 if out.len() < limit => out.push(symbol as u8),
+            0..=255 => return None,
+            256 => return Some(()),
+            _ => {
+                let index = symbol - 257;
+                let length = *LENGTH_BASE.get(index)? as usize
+                    + reader.read_bits(u32::from(LENGTH_EXTRA[index]))? as usize;
+
+                let index = dists.decode(reader)? as usize;
+                let dist = *DIST_BASE.get(index)? as usize
+                    + reader.read_bits(u32::from(DIST_EXTRA[index]))? as usize;
+                if dist > out.len() || length > limit - out.len() {
+                    return None;
+                }
+
+                // Matches can overlap the bytes they produce, so they're copied a byte at a time
+                let start = out.len() - dist;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+/// Decompresses a raw DEFLATE stream, or returns `None` if it's malformed or would come out longer
+/// than `limit` bytes
+pub fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut out = vec![];
+
+    loop {
+        let last = reader.read_bits(1)? == 1;
+        match reader.read_bits(2)? {
+            0 => {
+                reader.align();
+                let header = data.get(reader.index..reader.index + 4)?;
+                let length = usize::from(u16::from_le_bytes([header[0], header[1]]));
+                if length != usize::from(!u16::from_le_bytes([header[2], header[3]])) {
+                    return None;
+                }
+                reader.index += 4;
+                if length > limit - out.len() {
+                    return None;
+                }
+                out.extend_from_slice(data.get(reader.index..reader.index + length)?);
+                reader.index += length;
+            },
+            1 => {
+                let (literals, dists) = fixed_codes();
+                inflate_block(&mut reader, &literals, &dists, &mut out, limit)?;
+            },
+            2 => {
+                let (literals, dists) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &literals, &dists, &mut out, limit)?;
+            },
+            _ => return None
+        }
+
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+/// Unwraps and decompresses a zlib stream, checking its checksum. Streams that would come out longer
+/// than `limit` bytes are rejected as soon as they pass it.
+pub fn decompress(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    if data.len() < 6 || data[0] & 0x0F != 8 || (u32::from(data[0]) << 8 | u32::from(data[1])) % 31 != 0 {
+        return None;
+    }
+
+    let out = inflate(&data[2..data.len() - 4], limit)?;
+    let checksum = u32::from_be_bytes([data[data.len() - 4], data[data.len() - 3], data[data.len() - 2], data[data.len() - 1]]);
+    if adler32(&out) == checksum { Some(out) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adler32_checksum() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn zlib_header() {
+        let compressed = compress(b"");
+        assert_eq!((u32::from(compressed[0]) << 8 | u32::from(compressed[1])) % 31, 0);
+        assert_eq!(&compressed[compressed.len() - 4..], &[0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn repeated_data_compresses() {
+        let data = vec![7; 10000];
+        assert!(deflate(&data).len() < 100);
+    }
+
+    #[test]
+    fn compressed_data_round_trips() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * i % 251) as u8).chain(vec![3; 500]).collect();
+        assert_eq!(decompress(&compress(&data), data.len()), Some(data.clone()));
+        assert_eq!(decompress(&compress(&data), data.len() - 1), None);
+    }
+
+    #[test]
+    fn inflates_stored_and_dynamic_blocks() {
+        // A stored block of "abc", then the same from zlib at its highest level, which uses a
+        // dynamic Huffman code
+        assert_eq!(inflate(&[0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'], 3), Some(b"abc".to_vec()));
+        assert_eq!(inflate(&[0x01, 0x03, 0x00, 0xFC, 0xFF, b'a', b'b', b'c'], 2), None);
+
+        let dynamic = [
+            0x78, 0xDA, 0x1D, 0x8A, 0x41, 0x11, 0x00, 0x40, 0x10, 0x82, 0xAA, 0x58, 0x0D, 0xB5, 0x7F,
+            0x86, 0x73, 0x4F, 0x5E, 0x32, 0x80, 0x51, 0x15, 0x10, 0x09, 0x35, 0x13, 0x5B, 0x06, 0xD5,
+            0x9D, 0xB9, 0x9C, 0x75, 0xB0, 0x57, 0xFE, 0xD0, 0xA6, 0x0F, 0x85, 0x3D, 0x15, 0x65
+        ];
+        assert_eq!(
+            decompress(&dynamic, usize::MAX),
+            Some(b"aaba d caa accadbaabaaaacacaad abaadbacaababcabb ca accabbad".to_vec())
+        );
+        assert_eq!(decompress(&[0x78, 0x9C, 0x03, 0x00, 0x00, 0x00, 0x00, 0x02], usize::MAX), None);
+    }
+}
@@ -0,0 +1,412 @@
+use std::convert;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::{ Arc, mpsc };
+use std::time::{ Duration, Instant };
+
+use crate::ImageConfig;
+use crate::camera::Camera;
+use crate::colors;
+use crate::framebuffer::{ Accumulator, Framebuffer };
+use crate::geom::{ World, hit::HittableGroup };
+use crate::math::{ self, Rand };
+use crate::threadpool::ThreadPool;
+use crate::tile::{ self, Tile, TileQueues };
+use crate::vec::ColorRGB;
+
+#[derive(Debug)]
+pub enum RenderError {
+    InvalidConfig(String),
+    WorkerPanicked,
+    Io(io::Error)
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            RenderError::InvalidConfig(msg) =>
+                write!(f, "Invalid image settings: {}", msg),
+            RenderError::WorkerPanicked =>
+                write!(f, "A render thread panicked"),
+            RenderError::Io(err) =>
+                write!(f, "Could not write output: {}", err)
+        }
+    }
+}
+
+impl Error for RenderError {}
+
+impl convert::From<io::Error> for RenderError {
+    fn from(err: io::Error) -> Self {
+        RenderError::Io(err)
+    }
+}
+
+/// The most pixels an image can have, which is 16384x16384
+const MAX_PIXELS: u64 = 1 << 28;
+
+/// Checks that an image can be rendered with `config`'s settings, before anything is allocated for
+/// it
+pub fn validate(config: &ImageConfig) -> Result<(), RenderError> {
+    if config.width < 2 || config.height < 2 {
+        return Err(RenderError::InvalidConfig(format!(
+            "the image must be at least 2x2 pixels, not {}x{}", config.width, config.height
+        )));
+    }
+    if u64::from(config.width) * u64::from(config.height) > MAX_PIXELS {
+        return Err(RenderError::InvalidConfig(format!(
+            "the image can have at most {} pixels, not {}x{}", MAX_PIXELS, config.width, config.height
+        )));
+    }
+    if config.samples == 0 {
+        return Err(RenderError::InvalidConfig("at least one sample per pixel is needed".to_string()));
+    }
+    if config.threads == 0 {
+        return Err(RenderError::InvalidConfig("at least one thread is needed".to_string()));
+    }
+    if config.tile_size == 0 {
+        return Err(RenderError::InvalidConfig("tiles must be at least one pixel wide".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Sends rays to each pixel on a single thread. The image's contents are described by `world`.
+pub fn render(world: &World, camera: &Camera, config: &ImageConfig) -> Result<Framebuffer, RenderError> {
+    validate(config)?;
+
+    let mut image = Framebuffer::new(config.width, config.height);
+    let lights = world.emitters();
+    let scale = 1.0 / f64::from(config.samples);
+
+    for i in 0..config.height {
+        let row = Tile { x: 0, y: i, width: config.width, height: 1 };
+        let sums = render_tile(world, &lights, camera, config, &row, 0..config.samples);
+        let pixels: Vec<_> = sums.iter().map(|sum| sum * scale).collect();
+        image.write_tile(&row, &pixels);
+
+        eprint!("\r{}/{} rows rendered", i + 1, config.height);
+    }
+
+    eprintln!("\nDone.");
+    Ok(image)
+}
+
+/// Takes one jittered sample of a pixel, sampling `lights` (the world's emitters) directly. The
+/// sample's random numbers are derived from the render's seed, the pixel and the sample's index, so
+/// a sample always comes out the same. Its position on the pixel and the lens and its bounces take
+/// their numbers from `config.sampler`.
+pub fn render_sample(world: &World, lights: &HittableGroup, camera: &Camera, config: &ImageConfig,
+    x: u32, y: u32, sample: u32) -> ColorRGB
+{
+    let pixel = u64::from(y) * u64::from(config.width) + u64::from(x);
+    let mut rand = Rand::for_sample(config.seed, pixel, sample);
+    rand.sampler = Some(config.sampler.sampler(config.seed, pixel, sample, config.samples));
+
+    let (jitter_u, jitter_v) = math::sample_2d(&mut rand);
+    let u = ((x as f64) + jitter_u) / f64::from(config.width - 1);
+    let v = ((y as f64) + jitter_v) / f64::from(config.height - 1);
+
+    let r = camera.ray(u, v, &mut rand);
+
+    r.get_color(world, lights, &*config.background, config.max_depth, &mut rand)
+}
+
+/// Renders one tile, taking the samples numbered `samples` within each of its pixels, and returns
+/// the sum of each pixel's samples in row-major order
+pub fn render_tile(world: &World, lights: &HittableGroup, camera: &Camera, config: &ImageConfig, tile: &Tile,
+    samples: Range<u32>) -> Vec<ColorRGB>
+{
+    let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+
+    for i in tile.y..tile.y + tile.height {
+        for j in tile.x..tile.x + tile.width {
+            let mut color = colors::BLACK;
+            for sample in samples.clone() {
+                color += render_sample(world, lights, camera, config, j, i, sample);
+            }
+
+            pixels.push(color);
+        }
+    }
+
+    pixels
+}
+
+/// Renders an image on `config.threads` workers. Each worker renders whole tiles into its own
+/// buffer, stealing tiles from the others once it runs out, and finished tiles are copied into the
+/// image on this thread.
+pub fn render_threaded(world: Arc<World>, camera: Arc<Camera>, config: Arc<ImageConfig>)
+    -> Result<Framebuffer, RenderError>
+{
+    validate(&config)?;
+
+    let tiles = tile::tiles(config.width, config.height, config.tile_size, config.tile_order);
+    let total_tiles = tiles.len();
+    let queues = Arc::new(TileQueues::new(tiles, config.threads));
+    let lights = Arc::new(world.emitters());
+    let (sender, receiver) = mpsc::channel();
+
+    eprintln!("Spawning thread pool with {} workers", config.threads);
+    let pool = ThreadPool::new(config.threads);
+
+    for worker in 0..config.threads {
+        let world = world.clone();
+        let lights = lights.clone();
+        let camera = camera.clone();
+        let config = config.clone();
+        let queues = queues.clone();
+        let sender = sender.clone();
+
+        pool.execute(move || {
+            let scale = 1.0 / f64::from(config.samples);
+            while let Some(tile) = queues.next(worker) {
+                let sums = render_tile(&world, &lights, &camera, &config, &tile, 0..config.samples);
+                let pixels: Vec<_> = sums.iter().map(|sum| sum * scale).collect();
+                if sender.send((tile, pixels)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop(sender);
+
+    let mut image = Framebuffer::new(config.width, config.height);
+    for (done, (tile, pixels)) in receiver.iter().enumerate() {
+        image.write_tile(&tile, &pixels);
+        eprint!("\r{}/{} tiles rendered", done + 1, total_tiles);
+    }
+    eprintln!();
+
+    pool.join().map_err(|_| RenderError::WorkerPanicked)?;
+    Ok(image)
+}
+
+/// How often a progressive render hands back a preview of the image so far
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PreviewInterval {
+    /// After every this many samples per pixel
+    Samples(u32),
+    /// After at least this much time has passed since the last preview
+    Time(Duration)
+}
+
+/// Renders an image in passes of one sample per pixel, using the same tiled workers as
+/// `render_threaded`, until every pixel has `config.samples` samples. Rendering carries on from
+/// `accumulator`, which can hold the samples of an earlier, interrupted render. Whenever `interval`
+/// has passed, `on_progress` is handed the samples so far, which it can use to write a preview or
+/// checkpoint. The finished samples are returned rather than handed over.
+pub fn render_progressive<F>(world: Arc<World>, camera: Arc<Camera>, config: Arc<ImageConfig>,
+    mut accumulator: Accumulator, interval: PreviewInterval, mut on_progress: F)
+    -> Result<Accumulator, RenderError>
+where
+    F: FnMut(&Accumulator) -> Result<(), RenderError>
+{
+    validate(&config)?;
+    if interval == PreviewInterval::Samples(0) {
+        return Err(RenderError::InvalidConfig("previews must be at least one sample apart".to_string()));
+    }
+    if (accumulator.width(), accumulator.height()) != (config.width, config.height) {
+        return Err(RenderError::InvalidConfig(format!(
+            "can't continue a {}x{} render at {}x{}",
+            accumulator.width(), accumulator.height(), config.width, config.height
+        )));
+    }
+
+    let tiles = tile::tiles(config.width, config.height, config.tile_size, config.tile_order);
+    let lights = Arc::new(world.emitters());
+    let first_pass = accumulator.min_samples() + 1;
+    let mut last_preview = (Instant::now(), first_pass - 1);
+    if first_pass > 1 {
+        eprintln!("Continuing from {} samples", first_pass - 1);
+    }
+
+    eprintln!("Spawning thread pool with {} workers", config.threads);
+    let pool = ThreadPool::new(config.threads);
+
+    for pass in first_pass..=config.samples {
+        // A checkpoint from partway through a pass already has some of this pass's tiles
+        let pass_tiles: Vec<_> = tiles.iter().filter(|tile| accumulator.min_samples_in(tile) < pass)
+            .copied().collect();
+        let tile_count = pass_tiles.len();
+        let queues = Arc::new(TileQueues::new(pass_tiles, config.threads));
+        let (sender, receiver) = mpsc::channel();
+
+        for worker in 0..config.threads {
+            let world = world.clone();
+            let lights = lights.clone();
+            let camera = camera.clone();
+            let config = config.clone();
+            let queues = queues.clone();
+            let sender = sender.clone();
+
+            pool.execute(move || {
+                while let Some(tile) = queues.next(worker) {
+                    let sums = render_tile(&world, &lights, &camera, &config, &tile, pass - 1..pass);
+                    if sender.send((tile, sums)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut received = 0;
+        for (tile, sums) in receiver.iter() {
+            accumulator.add_pass(&tile, &sums, pass);
+            received += 1;
+
+            // Time-based previews can land mid-pass, with some pixels a sample ahead of the rest
+            let due = match interval {
+                PreviewInterval::Time(time) => last_preview.0.elapsed() >= time,
+                PreviewInterval::Samples(_) => false
+            };
+            if due && received < tile_count {
+                on_progress(&accumulator)?;
+                last_preview = (Instant::now(), pass - 1);
+            }
+        }
+
+        // Workers only stop early if one of them panicked
+        if received < tile_count {
+            pool.join().map_err(|_| RenderError::WorkerPanicked)?;
+            return Err(RenderError::WorkerPanicked);
+        }
+        eprint!("\r{}/{} samples done", pass, config.samples);
+
+        let due = match interval {
+            PreviewInterval::Time(time) => last_preview.0.elapsed() >= time,
+            PreviewInterval::Samples(samples) => pass - last_preview.1 >= samples
+        };
+        if due && pass < config.samples {
+            on_progress(&accumulator)?;
+            last_preview = (Instant::now(), pass);
+        }
+    }
+    eprintln!();
+
+    pool.join().map_err(|_| RenderError::WorkerPanicked)?;
+    Ok(accumulator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::hit::HittableGroup;
+    use crate::geom::primitives::Sphere;
+    use crate::material::DiffuseLambert;
+    use crate::vec::{ Color, Point3 };
+
+    fn empty_scene(width: u32, height: u32) -> (World, Camera, ImageConfig) {
+        let config = ImageConfig { width, height, samples: 2, threads: 2, tile_size: 2, ..Default::default() };
+        let camera = Camera::new(
+            Point3::new(0.0, 0.0, 0.0), &Point3::new(0.0, 0.0, -1.0), 90.0, 0.0, width, height
+        );
+        (HittableGroup::new(vec![]), camera, config)
+    }
+
+    #[test]
+    fn renders_into_framebuffer() {
+        let (world, camera, config) = empty_scene(4, 3);
+        let image = render(&world, &camera, &config).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 3));
+        assert_eq!(image.pixels().len(), 12);
+
+        let (world, camera, config) = empty_scene(4, 3);
+        let threaded = render_threaded(Arc::new(world), Arc::new(camera), Arc::new(config)).unwrap();
+        assert_eq!(threaded.pixels().len(), 12);
+        // The empty scene is all background, which is never black
+        assert!(threaded.pixels().iter().all(|pixel| *pixel != colors::BLACK));
+    }
+
+    #[test]
+    fn progressive_previews() {
+        let (world, camera, mut config) = empty_scene(4, 3);
+        config.samples = 7;
+        let (world, camera, config) = (Arc::new(world), Arc::new(camera), Arc::new(config));
+
+        let mut passes = vec![];
+        let accumulator = render_progressive(world.clone(), camera.clone(), config.clone(),
+            Accumulator::new(4, 3), PreviewInterval::Samples(3), |accumulator| {
+                passes.push(accumulator.min_samples());
+                Ok(())
+            }).unwrap();
+
+        assert_eq!(passes, vec![3, 6]);
+        assert_eq!(accumulator.min_samples(), 7);
+        assert!(accumulator.image().pixels().iter().all(|pixel| *pixel != colors::BLACK));
+
+        // Carrying on from a finished render only adds the missing samples
+        let config = Arc::new(ImageConfig { samples: 9, ..empty_scene(4, 3).2 });
+        let mut passes = vec![];
+        let accumulator = render_progressive(world.clone(), camera.clone(), config.clone(), accumulator,
+            PreviewInterval::Samples(1), |accumulator| {
+                passes.push(accumulator.min_samples());
+                Ok(())
+            }).unwrap();
+        assert_eq!(passes, vec![8]);
+        assert!(accumulator.counts().iter().all(|&count| count == 9));
+
+        let wrong_size = render_progressive(world, camera, config, Accumulator::new(2, 2),
+            PreviewInterval::Samples(1), |_| Ok(()));
+        assert!(matches!(wrong_size, Err(RenderError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn resuming_with_other_tiles() {
+        let (world, camera, _) = empty_scene(4, 3);
+        let (world, camera) = (Arc::new(world), Arc::new(camera));
+        let render_to = |samples, tile_size, accumulator| {
+            let config = Arc::new(ImageConfig { samples, tile_size, ..empty_scene(4, 3).2 });
+            render_progressive(world.clone(), camera.clone(), config, accumulator, PreviewInterval::Samples(1),
+                |_| Ok(())).unwrap()
+        };
+        let (two, three) = (render_to(2, 2, Accumulator::new(4, 3)), render_to(3, 2, Accumulator::new(4, 3)));
+
+        // Stopped partway through the third pass, with only the top left 2x2 tile done, then
+        // carried on with 3x3 tiles, which straddle done and undone pixels
+        let done = |i: usize| i % 4 < 2 && i / 4 < 2;
+        let sums = (0..12).map(|i| if done(i) { three.sums()[i].clone() } else { two.sums()[i].clone() }).collect();
+        let counts = (0..12).map(|i| if done(i) { 3 } else { 2 }).collect();
+        let resumed = render_to(3, 3, Accumulator::from_parts(4, 3, sums, counts).unwrap());
+
+        assert!(resumed.counts().iter().all(|&count| count == 3));
+        assert_eq!(bits(&resumed.image()), bits(&three.image()));
+    }
+
+    fn bits(image: &Framebuffer) -> Vec<[u64; 3]> {
+        image.pixels().iter()
+            .map(|pixel| [pixel[Color::R].to_bits(), pixel[Color::G].to_bits(), pixel[Color::B].to_bits()])
+            .collect()
+    }
+
+    #[test]
+    fn seeded_renders_are_identical() {
+        let sphere = Sphere::new(Point3::new(0.0, 0.0, -1.0), 0.5, Arc::new(DiffuseLambert::new(colors::WHITE)));
+        let world = Arc::new(HittableGroup::new(vec![Arc::new(sphere)]));
+        let camera = Arc::new(empty_scene(6, 5).1);
+        let config = |threads, seed| Arc::new(ImageConfig { threads, seed, samples: 4, ..empty_scene(6, 5).2 });
+
+        let single = render(&world, &camera, &config(1, 7)).unwrap();
+        let threaded = render_threaded(world.clone(), camera.clone(), config(3, 7)).unwrap();
+        let progressive = render_progressive(world.clone(), camera.clone(), config(2, 7),
+            Accumulator::new(6, 5), PreviewInterval::Samples(1), |_| Ok(())).unwrap().image();
+        assert_eq!(bits(&single), bits(&threaded));
+        assert_eq!(bits(&single), bits(&progressive));
+
+        let reseeded = render_threaded(world, camera, config(3, 8)).unwrap();
+        assert_ne!(bits(&single), bits(&reseeded));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let (world, camera, config) = empty_scene(1, 3);
+        assert!(matches!(render(&world, &camera, &config), Err(RenderError::InvalidConfig(_))));
+
+        // Sizes whose pixel count would overflow a u32
+        let (world, camera, config) = empty_scene(70000, 70000);
+        assert!(matches!(render(&world, &camera, &config), Err(RenderError::InvalidConfig(_))));
+    }
+}
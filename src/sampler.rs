@@ -0,0 +1,390 @@
+use std::fmt;
+
+use rand::{ Rng, SeedableRng };
+use rand_pcg::Pcg32;
+
+use crate::math::mix;
+
+/// The largest f64 below 1
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// Keeps samplers' sequences unrelated to the generator `math::Rand::for_sample` creates
+const SAMPLER_SEED: u64 = 0x5341_4d50_4c45_5253;
+
+/// The first 64 primes, the bases of the Halton sequence's dimensions
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311
+];
+
+/// Supplies the numbers in [0, 1) that one sample of one pixel is built from, a dimension at a
+/// time. The samples of a pixel draw their dimensions in the same order (the position on the
+/// pixel, then the lens, then each bounce), so a sampler can spread each dimension's values evenly
+/// across the pixel's samples rather than leaving them to chance.
+pub trait Sampler: fmt::Debug {
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// The samplers an image can be rendered with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerKind {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered points in a grid of strata, shuffled differently for each dimension
+    Stratified,
+    /// The Halton sequence, with scrambled digits
+    Halton,
+    /// The Sobol sequence, Owen-scrambled and shuffled differently for each pair of dimensions
+    Sobol
+}
+
+impl SamplerKind {
+    /// Picks a sampler from its name, e.g. "sobol"
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match &name.to_ascii_lowercase()[..] {
+            "independent" | "random" => Some(SamplerKind::Independent),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            _ => None
+        }
+    }
+
+    /// Creates the sampler for sample number `sample` of the `samples` a pixel takes. The same
+    /// arguments always give the same numbers.
+    pub fn sampler(&self, seed: u64, pixel: u64, sample: u32, samples: u32) -> Box<dyn Sampler> {
+        let seed = mix(mix(seed ^ SAMPLER_SEED) ^ pixel);
+        let rng = Pcg32::seed_from_u64(mix(seed ^ u64::from(sample)));
+
+        match self {
+            SamplerKind::Independent => Box::new(Independent { rng }),
+            SamplerKind::Stratified =>
+                Box::new(Stratified { sample, samples: samples.max(1), seed, dimension: 0, rng }),
+            SamplerKind::Halton => Box::new(Halton { index: u64::from(sample), seed, dimension: 0 }),
+            SamplerKind::Sobol => Box::new(Sobol { index: sample, seed, dimension: 0 })
+        }
+    }
+}
+
+/// Derives an unrelated 32-bit seed for each dimension from a pixel's seed
+fn dimension_seed(seed: u64, dimension: u64) -> u32 {
+    mix(seed ^ dimension.wrapping_mul(0x9E37_79B9_7F4A_7C15)) as u32
+}
+
+#[derive(Debug)]
+struct Independent {
+    rng: Pcg32
+}
+
+impl Sampler for Independent {
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+/// Picks the `i`th element of a pseudo-random permutation of `0..len` chosen by `seed`, using
+/// Kensler's hash from "Correlated Multi-Jittered Sampling"
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut mask = len - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    // Hash within the next power of two up, and try again until the result is in range
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= mask;
+        i ^= i >> 5;
+
+        if i < len {
+            return (i + seed) % len;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stratified {
+    sample: u32,
+    samples: u32,
+    seed: u64,
+    dimension: u64,
+    rng: Pcg32
+}
+
+impl Stratified {
+    /// The stratum this sample falls in for the next dimension, and a seed for that dimension. Each
+    /// dimension shuffles the strata differently, so the dimensions aren't correlated with one
+    /// another.
+    fn next_stratum(&mut self) -> (u32, u32) {
+        let seed = dimension_seed(self.seed, self.dimension);
+        self.dimension += 1;
+        (permute(self.sample % self.samples, self.samples, seed), seed)
+    }
+}
+
+/// Splits `samples` into a grid of exactly that many cells, as close to square as it can be
+fn grid(samples: u32) -> (u32, u32) {
+    let mut cols = (f64::from(samples).sqrt() as u32).max(1);
+    while !samples.is_multiple_of(cols) {
+        cols -= 1;
+    }
+    (cols, samples / cols)
+}
+
+impl Sampler for Stratified {
+    fn get_1d(&mut self) -> f64 {
+        let (stratum, _) = self.next_stratum();
+        (f64::from(stratum) + self.rng.gen::<f64>()) / f64::from(self.samples)
+    }
+
+    /// Kensler's correlated multi-jittered sampling: one point in every cell of the grid, and
+    /// within its cell, one in every column and row of the finer grid the samples split each axis
+    /// into. So even when the grid is a single column, x is still stratified.
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (cols, rows) = grid(self.samples);
+        let (stratum, seed) = self.next_stratum();
+        let (col, row) = (stratum % cols, stratum / cols);
+
+        let sub_x = permute(row, rows, seed.wrapping_mul(0xa511_e9b3));
+        let sub_y = permute(col, cols, seed.wrapping_mul(0x63d8_3595));
+        let (cols, rows) = (f64::from(cols), f64::from(rows));
+        let x = (f64::from(col) + (f64::from(sub_x) + self.rng.gen::<f64>()) / rows) / cols;
+        let y = (f64::from(row) + (f64::from(sub_y) + self.rng.gen::<f64>()) / cols) / rows;
+        (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+    }
+}
+
+/// Finds the radical inverse of `index` in `base`, with every digit shuffled by a permutation that
+/// depends on the digits before it. This is a nested scramble, so the points stay just as evenly
+/// spread, but each seed gives a differently placed set of them.
+fn scrambled_radical_inverse(base: u64, mut index: u64, seed: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut scale = inv_base;
+    let mut value = 0.0;
+    let mut node = seed;
+
+    // Carry on past the index's last digit, so the zeros that follow are scrambled too
+    while scale > f64::EPSILON {
+        let digit = index % base;
+        value += f64::from(permute(digit as u32, base as u32, node as u32)) * scale;
+
+        node = mix(node.wrapping_add(digit + 1));
+        index /= base;
+        scale *= inv_base;
+    }
+
+    value.min(ONE_MINUS_EPSILON)
+}
+
+#[derive(Debug)]
+struct Halton {
+    index: u64,
+    seed: u64,
+    dimension: u64
+}
+
+impl Sampler for Halton {
+    fn get_1d(&mut self) -> f64 {
+        // Past the last prime, bases are reused with a different scramble
+        let base = PRIMES[(self.dimension % PRIMES.len() as u64) as usize];
+        let seed = mix(self.seed ^ self.dimension);
+        self.dimension += 1;
+        scrambled_radical_inverse(base, self.index, seed)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Scrambles the bits of `value` with a hash of `seed`, in the style of Laine and Karras: each
+/// bit is only affected by the ones below it
+fn laine_karras_permutation(mut value: u32, seed: u32) -> u32 {
+    value = value.wrapping_add(seed);
+    value ^= value.wrapping_mul(0x6c50_b47c);
+    value ^= value.wrapping_mul(0xb82f_1e52);
+    value ^= value.wrapping_mul(0xc7af_e638);
+    value ^= value.wrapping_mul(0x8d22_f6e6);
+    value
+}
+
+/// Owen-scrambles a 32-bit fixed point number in [0, 1), where each bit is flipped depending on the
+/// ones above it
+fn owen_scramble(value: u32, seed: u32) -> u32 {
+    laine_karras_permutation(value.reverse_bits(), seed).reverse_bits()
+}
+
+/// Finds point `index` of the first two dimensions of the Sobol sequence, as 32-bit fixed point
+/// numbers. The first is the base 2 radical inverse, and the second's generator matrix is Pascal's
+/// triangle mod 2.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut direction: u32 = 1 << 31;
+    for bit in 0..32 {
+        if (index >> bit) & 1 == 1 {
+            x ^= 1 << (31 - bit);
+            y ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+
+    (x, y)
+}
+
+fn to_unit(value: u32) -> f64 {
+    f64::from(value) / 4_294_967_296.0
+}
+
+/// Burley's "Practical Hash-based Owen Scrambling": every pair of dimensions uses the first two
+/// dimensions of the Sobol sequence, with the points Owen-scrambled and taken in a shuffled order
+/// that's different for each pair. Any power-of-two number of samples is still well stratified.
+#[derive(Debug)]
+struct Sobol {
+    index: u32,
+    seed: u64,
+    dimension: u64
+}
+
+impl Sobol {
+    fn next_seeds(&mut self) -> (u32, u32, u32) {
+        let seed = mix(self.seed ^ self.dimension);
+        self.dimension += 1;
+        (dimension_seed(seed, 0), dimension_seed(seed, 1), dimension_seed(seed, 2))
+    }
+}
+
+impl Sampler for Sobol {
+    fn get_1d(&mut self) -> f64 {
+        let (shuffle, scramble, _) = self.next_seeds();
+        let index = owen_scramble(self.index, shuffle);
+        to_unit(owen_scramble(index.reverse_bits(), scramble))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (shuffle, scramble_x, scramble_y) = self.next_seeds();
+        let (x, y) = sobol_2d(owen_scramble(self.index, shuffle));
+        (to_unit(owen_scramble(x, scramble_x)), to_unit(owen_scramble(y, scramble_y)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] =
+        [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+    /// Takes the first few dimensions of every sample of a pixel
+    fn points(kind: SamplerKind, pixel: u64, samples: u32) -> Vec<Vec<(f64, f64)>> {
+        (0..samples).map(|sample| {
+            let mut sampler = kind.sampler(3, pixel, sample, samples);
+            (0..4).map(|_| sampler.get_2d()).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn samplers_are_deterministic() {
+        for &kind in &KINDS {
+            let first = points(kind, 5, 8);
+            assert_eq!(first, points(kind, 5, 8));
+            assert_ne!(first, points(kind, 6, 8));
+            assert!(first.iter().flatten().all(|&(x, y)| (0.0..1.0).contains(&x) && (0.0..1.0).contains(&y)));
+        }
+    }
+
+    #[test]
+    fn samples_are_stratified() {
+        for &kind in &[SamplerKind::Stratified, SamplerKind::Sobol] {
+            for dimension in 0..4 {
+                let mut cells = [0; 16];
+                for sample in points(kind, 1, 16) {
+                    let (x, y) = sample[dimension];
+                    cells[(y * 4.0) as usize * 4 + (x * 4.0) as usize] += 1;
+                }
+                assert!(cells.iter().all(|&count| count == 1), "{:?} in dimension {}", kind, dimension);
+            }
+        }
+
+        // Sample counts that aren't square still fill every cell, and every stratum of each axis
+        for &samples in &[10, 50] {
+            let (cols, rows) = grid(samples);
+            let points = points(SamplerKind::Stratified, 1, samples);
+            for dimension in 0..4 {
+                let mut cells = vec![0; samples as usize];
+                let mut xs = vec![0; samples as usize];
+                let mut ys = vec![0; samples as usize];
+                for sample in &points {
+                    let (x, y) = sample[dimension];
+                    cells[(y * f64::from(rows)) as usize * cols as usize + (x * f64::from(cols)) as usize] += 1;
+                    xs[(x * f64::from(samples)) as usize] += 1;
+                    ys[(y * f64::from(samples)) as usize] += 1;
+                }
+                assert!(cells.iter().chain(&xs).chain(&ys).all(|&count| count == 1), "{} samples", samples);
+            }
+        }
+
+        // Base 2 and 3 radical inverses put one point in each of their strata
+        let mut halves = [0; 2];
+        let mut thirds = [0; 3];
+        for sample in points(SamplerKind::Halton, 1, 6) {
+            let (x, y) = sample[0];
+            halves[(x * 2.0) as usize] += 1;
+            thirds[(y * 3.0) as usize] += 1;
+        }
+        assert_eq!((halves, thirds), ([3, 3], [2, 2, 2]));
+    }
+
+    #[test]
+    fn grids_have_a_cell_per_sample() {
+        assert_eq!([grid(1), grid(7), grid(10), grid(16), grid(50)], [(1, 1), (1, 7), (2, 5), (4, 4), (5, 10)]);
+    }
+
+    #[test]
+    fn permutations_are_complete() {
+        for &len in &[1, 7, 16, 100] {
+            let mut seen: Vec<_> = (0..len).map(|i| permute(i, len, 12345)).collect();
+            seen.sort_unstable();
+            assert_eq!(seen, (0..len).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn less_error_than_independent() {
+        // Estimate the integral of xy over the unit square, which is 1/4, in lots of pixels
+        let error = |kind: SamplerKind| (0..64).map(|pixel| {
+            let estimate = points(kind, pixel, 16).iter()
+                .map(|sample| sample[0].0 * sample[0].1)
+                .sum::<f64>() / 16.0;
+            (estimate - 0.25).powi(2)
+        }).sum::<f64>();
+
+        let independent = error(SamplerKind::Independent);
+        for &kind in &[SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+            assert!(error(kind) < independent / 4.0, "{:?} isn't any better than independent samples", kind);
+        }
+    }
+}
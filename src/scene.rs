@@ -0,0 +1,1322 @@
+use std::collections::HashMap;
+use std::convert;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::{ Path, PathBuf };
+use std::str::Chars;
+use std::sync::Arc;
+
+use crate::ImageConfig;
+use crate::camera::Camera;
+use crate::geom::{
+    World,
+    hit::{ Bounded, BoundedHittable, HittableRefs },
+    objects::{ Icosahedron, Object, Prism, Volume },
+    primitives::{ Plane, Sphere, Triangle }
+};
+use crate::gltf::{ self, GltfError };
+use crate::loader::LoaderError;
+use crate::material::{ self, Material, Metal, NormalDetail, PrincipledParams };
+use crate::math;
+use crate::sampler::SamplerKind;
+use crate::texture::{ Checker, Image, Luminance, Mapping, Noise, NoiseKind, Ramp, TextureError, TextureRef, VertexColor };
+use crate::tonemap::ToneOperator;
+use crate::vec::{ colors, ColorRGB, Point3, Vec3 };
+
+/// Everything needed to render an image, as described by a scene file
+pub struct Scene {
+    pub world: World,
+    pub camera: Camera,
+    pub config: ImageConfig
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize
+}
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    UnexpectedToken(String),
+    UnterminatedString,
+    InvalidNumber(String),
+    UnknownStatement(String),
+    UnknownMaterialType(String),
+    UnknownTextureType(String),
+    UnknownAttribute(String),
+    DuplicateAttribute(String),
+    MissingAttribute(String),
+    InvalidValue(String, &'static str),
+    UndefinedMaterial(String),
+    UndefinedTexture(String),
+    UndefinedShape(String),
+    DuplicateName(String),
+    MissingCamera,
+    Loader(LoaderError),
+    Texture(TextureError)
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    Parse(Position, ParseErrorKind),
+    Gltf(GltfError)
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            ParseErrorKind::UnexpectedChar(c) =>
+                write!(f, "Unexpected character '{}'", c),
+            ParseErrorKind::UnexpectedToken(token) =>
+                write!(f, "Unexpected {}", token),
+            ParseErrorKind::UnterminatedString =>
+                write!(f, "Unterminated string"),
+            ParseErrorKind::InvalidNumber(num) =>
+                write!(f, "Invalid number '{}'", num),
+            ParseErrorKind::UnknownStatement(name) =>
+                write!(f, "Unknown statement '{}'", name),
+            ParseErrorKind::UnknownMaterialType(name) =>
+                write!(f, "Unknown material type '{}'", name),
+            ParseErrorKind::UnknownTextureType(name) =>
+                write!(f, "Unknown texture type '{}'", name),
+            ParseErrorKind::UnknownAttribute(name) =>
+                write!(f, "Unknown attribute '{}'", name),
+            ParseErrorKind::DuplicateAttribute(name) =>
+                write!(f, "Attribute '{}' given more than once", name),
+            ParseErrorKind::MissingAttribute(name) =>
+                write!(f, "Missing required attribute '{}'", name),
+            ParseErrorKind::InvalidValue(name, expected) =>
+                write!(f, "Invalid value for '{}': expected {}", name, expected),
+            ParseErrorKind::UndefinedMaterial(name) =>
+                write!(f, "Material '{}' has not been defined", name),
+            ParseErrorKind::UndefinedTexture(name) =>
+                write!(f, "Texture '{}' has not been defined", name),
+            ParseErrorKind::UndefinedShape(name) =>
+                write!(f, "Shape '{}' has not been defined", name),
+            ParseErrorKind::DuplicateName(name) =>
+                write!(f, "'{}' has already been defined", name),
+            ParseErrorKind::MissingCamera =>
+                write!(f, "Scene has no camera"),
+            ParseErrorKind::Loader(err) =>
+                write!(f, "{}", err),
+            ParseErrorKind::Texture(err) =>
+                write!(f, "{}", err)
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            SceneError::Io(err) =>
+                write!(f, "Could not load scene file: {}", err),
+            SceneError::Parse(pos, kind) =>
+                write!(f, "Could not parse scene at line {}, column {}: {}", pos.line, pos.column, kind),
+            SceneError::Gltf(err) =>
+                write!(f, "{}", err)
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+impl convert::From<io::Error> for SceneError {
+    fn from(err: io::Error) -> Self {
+        SceneError::Io(err)
+    }
+}
+
+impl convert::From<GltfError> for SceneError {
+    fn from(err: GltfError) -> Self {
+        SceneError::Gltf(err)
+    }
+}
+
+fn error<T>(pos: Position, kind: ParseErrorKind) -> Result<T, SceneError> {
+    Err(SceneError::Parse(pos, kind))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    LParen,
+    RParen,
+    Comma,
+    Equals,
+    Newline,
+    Eof
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            Token::Ident(name) => write!(f, "identifier '{}'", name),
+            Token::Number(num) => write!(f, "number {}", num),
+            Token::Str(s) => write!(f, "string \"{}\"", s),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Equals => write!(f, "'='"),
+            Token::Newline => write!(f, "end of line"),
+            Token::Eof => write!(f, "end of file")
+        }
+    }
+}
+
+/// Splits scene source into tokens. Newlines end statements, except inside parentheses.
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    depth: usize
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Lexer<'a> {
+        Lexer { chars: source.chars().peekable(), line: 1, column: 1, depth: 0 }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        Some(c)
+    }
+
+    fn position(&self) -> Position {
+        Position { line: self.line, column: self.column }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<(Token, Position)>, SceneError> {
+        let mut tokens = vec![];
+        loop {
+            let token = self.next_token()?;
+            let done = token.0 == Token::Eof;
+            tokens.push(token);
+            if done { break; }
+        }
+
+        Ok(tokens)
+    }
+
+    fn next_token(&mut self) -> Result<(Token, Position), SceneError> {
+        loop {
+            let pos = self.position();
+            let c = match self.chars.peek() {
+                None => return Ok((Token::Eof, pos)),
+                Some(&c) => c
+            };
+
+            match c {
+                '#' => {
+                    while let Some(&c) = self.chars.peek() {
+                        if c == '\n' { break; }
+                        self.bump();
+                    }
+                },
+                '\n' => {
+                    self.bump();
+                    if self.depth == 0 {
+                        return Ok((Token::Newline, pos));
+                    }
+                },
+                c if c.is_whitespace() => { self.bump(); },
+                '(' => {
+                    self.bump();
+                    self.depth += 1;
+                    return Ok((Token::LParen, pos));
+                },
+                ')' => {
+                    self.bump();
+                    self.depth = self.depth.saturating_sub(1);
+                    return Ok((Token::RParen, pos));
+                },
+                ',' => {
+                    self.bump();
+                    return Ok((Token::Comma, pos));
+                },
+                '=' => {
+                    self.bump();
+                    return Ok((Token::Equals, pos));
+                },
+                '"' => {
+                    self.bump();
+                    let mut s = String::new();
+                    loop {
+                        match self.bump() {
+                            None | Some('\n') => return error(pos, ParseErrorKind::UnterminatedString),
+                            Some('"') => break,
+                            Some(c) => s.push(c)
+                        }
+                    }
+                    return Ok((Token::Str(s), pos));
+                },
+                c if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                    let mut num = String::new();
+                    while let Some(&c) = self.chars.peek() {
+                        if c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.' {
+                            num.push(c);
+                            self.bump();
+                        } else {
+                            break;
+                        }
+                    }
+                    return match num.parse() {
+                        Ok(n) => Ok((Token::Number(n), pos)),
+                        Err(_) => error(pos, ParseErrorKind::InvalidNumber(num))
+                    };
+                },
+                c if c.is_alphabetic() || c == '_' => {
+                    let mut ident = String::new();
+                    while let Some(&c) = self.chars.peek() {
+                        if c.is_alphanumeric() || c == '_' {
+                            ident.push(c);
+                            self.bump();
+                        } else {
+                            break;
+                        }
+                    }
+                    return Ok((Token::Ident(ident), pos));
+                },
+                c => return error(pos, ParseErrorKind::UnexpectedChar(c))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Number(f64),
+    Ident(String),
+    Str(String),
+    Tuple(Vec<(Value, Position)>)
+}
+
+/// A name in a scene file, and where it was
+type Named = (String, Position);
+
+/// Attributes that can be given more than once, each adding to the last, like an object's rotations
+const REPEATABLE: [&str; 1] = ["rotate"];
+
+/// A single line of a scene file: a keyword, followed by positional values and `key=value` pairs
+struct Statement {
+    keyword: String,
+    pos: Position,
+    positional: Vec<(Value, Position)>,
+    attrs: Vec<(String, Position, Value, Position)>
+}
+
+struct Parser {
+    tokens: Vec<(Token, Position)>,
+    index: usize
+}
+
+impl Parser {
+    fn peek(&self) -> &(Token, Position) {
+        &self.tokens[self.index]
+    }
+
+    fn next(&mut self) -> (Token, Position) {
+        let token = self.tokens[self.index].clone();
+        if self.index < self.tokens.len() - 1 {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn statement(&mut self) -> Result<Option<Statement>, SceneError> {
+        let (keyword, pos) = loop {
+            match self.next() {
+                (Token::Newline, _) => continue,
+                (Token::Eof, _) => return Ok(None),
+                (Token::Ident(keyword), pos) => break (keyword, pos),
+                (token, pos) => return error(pos, ParseErrorKind::UnexpectedToken(token.to_string()))
+            }
+        };
+
+        let mut statement = Statement { keyword, pos, positional: vec![], attrs: vec![] };
+        loop {
+            match self.peek().0 {
+                Token::Newline | Token::Eof => break,
+                _ => {}
+            }
+
+            let (value, value_pos) = self.value()?;
+            if let (Token::Equals, _) = self.peek() {
+                self.next();
+                let key = match value {
+                    Value::Ident(key) => key,
+                    _ => return error(value_pos, ParseErrorKind::UnexpectedToken("'='".to_string()))
+                };
+                if !REPEATABLE.contains(&&key[..]) && statement.attrs.iter().any(|attr| attr.0 == key) {
+                    return error(value_pos, ParseErrorKind::DuplicateAttribute(key));
+                }
+                let (value, pos) = self.value()?;
+                statement.attrs.push((key, value_pos, value, pos));
+            } else {
+                statement.positional.push((value, value_pos));
+            }
+        }
+
+        Ok(Some(statement))
+    }
+
+    fn value(&mut self) -> Result<(Value, Position), SceneError> {
+        match self.next() {
+            (Token::Number(n), pos) => Ok((Value::Number(n), pos)),
+            (Token::Ident(ident), pos) => Ok((Value::Ident(ident), pos)),
+            (Token::Str(s), pos) => Ok((Value::Str(s), pos)),
+            (Token::LParen, pos) => {
+                let mut values = vec![self.value()?];
+                loop {
+                    match self.next() {
+                        (Token::Comma, _) => values.push(self.value()?),
+                        (Token::RParen, _) => break,
+                        (token, pos) =>
+                            return error(pos, ParseErrorKind::UnexpectedToken(token.to_string()))
+                    }
+                }
+                Ok((Value::Tuple(values), pos))
+            },
+            (token, pos) => error(pos, ParseErrorKind::UnexpectedToken(token.to_string()))
+        }
+    }
+}
+
+fn to_number(key: &str, value: &Value, pos: Position) -> Result<f64, SceneError> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        _ => error(pos, ParseErrorKind::InvalidValue(key.to_string(), "a number"))
+    }
+}
+
+fn to_vec3(key: &str, value: &Value, pos: Position) -> Result<Vec3, SceneError> {
+    match value {
+        Value::Tuple(values) if values.len() == 3 => {
+            let mut coords = [0.0; 3];
+            for (coord, (value, pos)) in coords.iter_mut().zip(values) {
+                *coord = match value {
+                    Value::Number(n) => *n,
+                    _ => return error(*pos, ParseErrorKind::InvalidValue(key.to_string(), "a number"))
+                };
+            }
+            Ok(Vec3::new(coords[0], coords[1], coords[2]))
+        },
+        _ => error(pos, ParseErrorKind::InvalidValue(key.to_string(), "a vector (x, y, z)"))
+    }
+}
+
+impl Statement {
+    fn take(&mut self, key: &str) -> Option<(Value, Position)> {
+        let index = self.attrs.iter().position(|attr| attr.0 == key)?;
+        let attr = self.attrs.remove(index);
+        Some((attr.2, attr.3))
+    }
+
+    fn require(&mut self, key: &str) -> Result<(Value, Position), SceneError> {
+        match self.take(key) {
+            Some(value) => Ok(value),
+            None => error(self.pos, ParseErrorKind::MissingAttribute(key.to_string()))
+        }
+    }
+
+    fn number(&mut self, key: &str) -> Result<f64, SceneError> {
+        let (value, pos) = self.require(key)?;
+        to_number(key, &value, pos)
+    }
+
+    fn number_or(&mut self, key: &str, default: f64) -> Result<f64, SceneError> {
+        match self.take(key) {
+            Some((value, pos)) => to_number(key, &value, pos),
+            None => Ok(default)
+        }
+    }
+
+    fn count_or(&mut self, key: &str, default: u32) -> Result<u32, SceneError> {
+        match self.take(key) {
+            Some((value, pos)) => {
+                let n = to_number(key, &value, pos)?;
+                if n < 1.0 || n.fract() != 0.0 || n > f64::from(u32::MAX) {
+                    return error(pos, ParseErrorKind::InvalidValue(key.to_string(), "a positive integer"));
+                }
+                Ok(n as u32)
+            },
+            None => Ok(default)
+        }
+    }
+
+    fn integer_or(&mut self, key: &str, default: u64) -> Result<u64, SceneError> {
+        match self.take(key) {
+            Some((value, pos)) => {
+                let n = to_number(key, &value, pos)?;
+                // `u64::MAX as f64` rounds up to 2^64, which is already too big
+                if n < 0.0 || n.fract() != 0.0 || n >= u64::MAX as f64 {
+                    return error(pos, ParseErrorKind::InvalidValue(key.to_string(), "a non-negative integer"));
+                }
+                Ok(n as u64)
+            },
+            None => Ok(default)
+        }
+    }
+
+    fn vec3(&mut self, key: &str) -> Result<Vec3, SceneError> {
+        let (value, pos) = self.require(key)?;
+        to_vec3(key, &value, pos)
+    }
+
+    fn vecs(&mut self, key: &str, count: usize) -> Result<Vec<Vec3>, SceneError> {
+        let (value, pos) = self.require(key)?;
+        match value {
+            Value::Tuple(values) if values.len() == count => {
+                values.iter().map(|(value, pos)| to_vec3(key, value, *pos)).collect()
+            },
+            _ => {
+                let expected = if count == 2 { "a pair of vectors" } else { "a triple of vectors" };
+                error(pos, ParseErrorKind::InvalidValue(key.to_string(), expected))
+            }
+        }
+    }
+
+    fn ident(&mut self, key: &str) -> Result<Option<(String, Position)>, SceneError> {
+        match self.take(key) {
+            Some((Value::Ident(name), pos)) => Ok(Some((name, pos))),
+            Some((_, pos)) => error(pos, ParseErrorKind::InvalidValue(key.to_string(), "a name")),
+            None => Ok(None)
+        }
+    }
+
+    fn bool_or(&mut self, key: &str, default: bool) -> Result<bool, SceneError> {
+        match self.take(key) {
+            Some((Value::Ident(ref b), _)) if b == "true" => Ok(true),
+            Some((Value::Ident(ref b), _)) if b == "false" => Ok(false),
+            Some((_, pos)) => error(pos, ParseErrorKind::InvalidValue(key.to_string(), "true or false")),
+            None => Ok(default)
+        }
+    }
+
+    /// Takes the name and type that start `material` and `texture` statements
+    fn name_and_type(&mut self, what: &str) -> Result<(Named, Named), SceneError> {
+        let (name, kind) = match (self.positional.first(), self.positional.get(1)) {
+            (Some((Value::Ident(name), pos)), Some((Value::Ident(kind), kind_pos))) =>
+                ((name.clone(), *pos), (kind.clone(), *kind_pos)),
+            _ => return error(self.pos, ParseErrorKind::MissingAttribute(format!("{} name and type", what)))
+        };
+        self.positional.drain(..2);
+
+        Ok((name, kind))
+    }
+
+    fn path(&mut self, key: &str, base_dir: &Path) -> Result<(PathBuf, Position), SceneError> {
+        match self.require(key)? {
+            (Value::Str(path), pos) => Ok((base_dir.join(path), pos)),
+            (_, pos) => error(pos, ParseErrorKind::InvalidValue(key.to_string(), "a quoted path"))
+        }
+    }
+
+    /// Ensures every attribute on the statement has been consumed
+    fn finish(self) -> Result<(), SceneError> {
+        if let Some((value, pos)) = self.positional.into_iter().next() {
+            let found = match value {
+                Value::Number(n) => Token::Number(n).to_string(),
+                Value::Ident(name) => Token::Ident(name).to_string(),
+                Value::Str(s) => Token::Str(s).to_string(),
+                Value::Tuple(_) => "tuple".to_string()
+            };
+            return error(pos, ParseErrorKind::UnexpectedToken(found));
+        }
+
+        match self.attrs.into_iter().next() {
+            Some((key, pos, _, _)) => error(pos, ParseErrorKind::UnknownAttribute(key)),
+            None => Ok(())
+        }
+    }
+}
+
+struct CameraSettings {
+    location: Point3,
+    look_at: Point3,
+    fov_deg: f64,
+    aperture: f64
+}
+
+/// Builds up a scene statement by statement
+struct SceneBuilder<'a> {
+    base_dir: &'a Path,
+    config: ImageConfig,
+    camera: Option<CameraSettings>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    textures: HashMap<String, TextureRef<ColorRGB>>,
+    shapes: HashMap<String, Arc<dyn BoundedHittable>>,
+    hittables: HittableRefs
+}
+
+impl<'a> SceneBuilder<'a> {
+    fn new(base_dir: &'a Path) -> SceneBuilder<'a> {
+        SceneBuilder {
+            base_dir,
+            config: ImageConfig::default(),
+            camera: None,
+            materials: HashMap::new(),
+            textures: HashMap::new(),
+            shapes: HashMap::new(),
+            hittables: vec![]
+        }
+    }
+
+    fn statement(&mut self, mut statement: Statement) -> Result<(), SceneError> {
+        match &statement.keyword[..] {
+            "image" => {
+                self.config.width = statement.count_or("width", self.config.width)?;
+                self.config.height = statement.count_or("height", self.config.height)?;
+                self.config.samples = statement.count_or("samples", self.config.samples)?;
+                self.config.max_depth = statement.count_or("max_depth", self.config.max_depth)?;
+
+                if let Some((name, pos)) = statement.ident("sampler")? {
+                    self.config.sampler = match SamplerKind::from_name(&name) {
+                        Some(sampler) => sampler,
+                        None => return error(pos, ParseErrorKind::InvalidValue(
+                            "sampler".to_string(), "independent, stratified, halton or sobol"
+                        ))
+                    };
+                }
+
+                let tone_map = &mut self.config.tone_map;
+                if let Some((name, pos)) = statement.ident("tonemap")? {
+                    tone_map.operator = match ToneOperator::from_name(&name) {
+                        Some(operator) => operator,
+                        None => return error(pos, ParseErrorKind::InvalidValue(
+                            "tonemap".to_string(), "clamp, reinhard or aces"
+                        ))
+                    };
+                }
+                tone_map.exposure = statement.number_or("exposure", tone_map.exposure)?;
+                if let Some((value, pos)) = statement.take("white") {
+                    match to_number("white", &value, pos)? {
+                        white if white > 0.0 => tone_map.white_point = Some(white),
+                        _ => return error(pos, ParseErrorKind::InvalidValue(
+                            "white".to_string(), "a positive number"
+                        ))
+                    }
+                }
+            },
+            "camera" => {
+                self.camera = Some(CameraSettings {
+                    location: statement.vec3("from")?,
+                    look_at: statement.vec3("at")?,
+                    fov_deg: statement.number("fov")?,
+                    aperture: statement.number_or("aperture", 0.0)?
+                });
+            },
+            "background" => {
+                if statement.attrs.iter().any(|attr| attr.0 == "color") {
+                    let color = statement.vec3("color")?;
+                    self.config.background = Arc::new(move |_| color.clone());
+                } else {
+                    let top = statement.vec3("top")?;
+                    let bottom = statement.vec3("bottom")?;
+                    self.config.background = Arc::new(move |t| math::lerp(top.clone(), bottom.clone(), t));
+                }
+            },
+            "material" => {
+                let (name, kind) = statement.name_and_type("material")?;
+                if self.materials.contains_key(&name.0) {
+                    return error(name.1, ParseErrorKind::DuplicateName(name.0));
+                }
+
+                let material = self.material(&mut statement, kind)?;
+                self.materials.insert(name.0, material);
+            },
+            "texture" => {
+                let (name, kind) = statement.name_and_type("texture")?;
+                if self.textures.contains_key(&name.0) {
+                    return error(name.1, ParseErrorKind::DuplicateName(name.0));
+                }
+
+                let texture = self.texture(&mut statement, kind)?;
+                self.textures.insert(name.0, texture);
+            },
+            "sphere" | "plane" | "triangle" | "prism" | "icosahedron" | "object" | "volume" => {
+                let name = statement.ident("name")?;
+                let visible = statement.bool_or("visible", true)?;
+                let shape = self.shape(&mut statement)?;
+
+                if let Some((name, pos)) = name {
+                    if self.shapes.contains_key(&name) {
+                        return error(pos, ParseErrorKind::DuplicateName(name));
+                    }
+                    self.shapes.insert(name, shape.clone());
+                }
+                if visible {
+                    self.hittables.push(shape);
+                }
+            },
+            _ => {
+                let keyword = statement.keyword.clone();
+                return error(statement.pos, ParseErrorKind::UnknownStatement(keyword));
+            }
+        }
+
+        statement.finish()
+    }
+
+    fn material(&mut self, statement: &mut Statement, kind: Named)
+        -> Result<Arc<dyn Material>, SceneError>
+    {
+        let material: Arc<dyn Material> = match &kind.0[..] {
+            "diffuse" => Arc::new(material::DiffuseLambert::new(
+                self.color(statement, "albedo")?
+            )),
+            "reflective" => Arc::new(material::Reflective::new(
+                self.color(statement, "albedo")?,
+                self.scalar_or(statement, "roughness", 0.0)?
+            )),
+            "conductor" => {
+                let (eta, k) = match statement.ident("metal")? {
+                    Some((name, pos)) => match Metal::from_name(&name) {
+                        Some(metal) => metal.refractive_index(),
+                        None => return error(pos, ParseErrorKind::InvalidValue(
+                            "metal".to_string(), "gold, silver, copper or aluminium"
+                        ))
+                    },
+                    None => (statement.vec3("eta")?, statement.vec3("k")?)
+                };
+                Arc::new(material::Conductor::new(
+                    eta, k,
+                    self.scalar_or(statement, "roughness", 0.0)?,
+                    statement.number_or("anisotropy", 0.0)?
+                ))
+            },
+            "translucent" => {
+                let translucent = material::Translucent::new(
+                    self.color(statement, "albedo")?,
+                    statement.number_or("ior", 1.0)?,
+                    self.scalar_or(statement, "roughness", 0.0)?
+                );
+                Arc::new(match statement.take("absorption") {
+                    Some((value, pos)) => translucent.with_absorption(to_vec3("absorption", &value, pos)?),
+                    None => translucent
+                })
+            },
+            "principled" => {
+                let defaults = PrincipledParams::default();
+                Arc::new(material::Principled::new(PrincipledParams {
+                    base_color: self.color(statement, "base_color")?,
+                    metallic: self.scalar(statement, "metallic")?.unwrap_or(defaults.metallic),
+                    roughness: self.scalar(statement, "roughness")?.unwrap_or(defaults.roughness),
+                    anisotropy: statement.number_or("anisotropy", defaults.anisotropy)?,
+                    specular: statement.number_or("specular", defaults.specular)?,
+                    sheen: statement.number_or("sheen", defaults.sheen)?,
+                    sheen_tint: statement.number_or("sheen_tint", defaults.sheen_tint)?,
+                    clearcoat: statement.number_or("clearcoat", defaults.clearcoat)?,
+                    clearcoat_roughness: statement.number_or("clearcoat_roughness", defaults.clearcoat_roughness)?,
+                    transmission: statement.number_or("transmission", defaults.transmission)?,
+                    ior: statement.number_or("ior", defaults.ior)?
+                }))
+            },
+            "mix" => Arc::new(material::Mix::new(
+                self.lookup_material(statement, "first")?,
+                self.lookup_material(statement, "second")?,
+                self.scalar_or(statement, "weight", 0.5)?
+            )),
+            "coated" => {
+                let coated = material::Coated::new(
+                    self.lookup_material(statement, "base")?,
+                    statement.number_or("ior", 1.5)?,
+                    statement.number_or("roughness", 0.0)?
+                );
+                Arc::new(match statement.take("absorption") {
+                    Some((value, pos)) => coated.with_absorption(to_vec3("absorption", &value, pos)?),
+                    None => coated
+                })
+            },
+            "emissive" => Arc::new(material::Emissive::new(
+                self.color(statement, "albedo")?,
+                statement.number("intensity")?
+            )),
+            _ => return error(kind.1, ParseErrorKind::UnknownMaterialType(kind.0))
+        };
+
+        // Any material can have its shading normals tilted by a normal map or a bump map
+        let normal_map = match statement.ident("normal_map")? {
+            Some((name, pos)) => Some(self.lookup_texture(name, pos)?),
+            None => None
+        };
+        let bump = self.scalar(statement, "bump")?;
+        let detail = match (normal_map, bump) {
+            (Some(map), None) => NormalDetail::Map(map),
+            (None, Some(bump)) => NormalDetail::Bump(bump, statement.number_or("bump_height", 0.01)?),
+            (None, None) => return Ok(material),
+            (Some(_), Some(_)) => return error(statement.pos, ParseErrorKind::InvalidValue(
+                "bump".to_string(), "no bump map on a material with a normal map"
+            ))
+        };
+
+        Ok(Arc::new(material::Bumped::new(material, detail)))
+    }
+
+    fn texture(&mut self, statement: &mut Statement, kind: Named)
+        -> Result<TextureRef<ColorRGB>, SceneError>
+    {
+        let texture: TextureRef<ColorRGB> = match &kind.0[..] {
+            "checker" => {
+                let mapping = match statement.ident("mapping")? {
+                    None => Mapping::Uv,
+                    Some((name, _)) if name == "uv" => Mapping::Uv,
+                    Some((name, _)) if name == "solid" => Mapping::Solid,
+                    Some((_, pos)) => return error(pos, ParseErrorKind::InvalidValue(
+                        "mapping".to_string(), "uv or solid"
+                    ))
+                };
+                Arc::new(Checker::new(
+                    self.color(statement, "even")?,
+                    self.color(statement, "odd")?,
+                    statement.number_or("scale", 1.0)?,
+                    mapping
+                ))
+            },
+            "image" => {
+                let (path, pos) = statement.path("file", self.base_dir)?;
+                match Image::load(&path, statement.bool_or("srgb", true)?) {
+                    Ok(image) => Arc::new(image),
+                    Err(err) => return error(pos, ParseErrorKind::Texture(err))
+                }
+            },
+            "noise" | "turbulence" | "marble" => {
+                let noise = Noise::new(
+                    NoiseKind::from_name(&kind.0).unwrap(),
+                    statement.number_or("scale", 1.0)?,
+                    statement.integer_or("seed", 0)?
+                );
+                let low = match statement.take("low") {
+                    Some((value, pos)) => to_vec3("low", &value, pos)?,
+                    None => colors::BLACK
+                };
+                let high = match statement.take("high") {
+                    Some((value, pos)) => to_vec3("high", &value, pos)?,
+                    None => colors::WHITE
+                };
+                Arc::new(Ramp::new(low, high, Arc::new(noise)))
+            },
+            "vertex_color" => Arc::new(VertexColor::new(match statement.take("fallback") {
+                Some((value, pos)) => to_vec3("fallback", &value, pos)?,
+                None => colors::WHITE
+            })),
+            _ => return error(kind.1, ParseErrorKind::UnknownTextureType(kind.0))
+        };
+
+        Ok(texture)
+    }
+
+    /// Finds the texture with the given name
+    fn lookup_texture(&self, name: String, pos: Position) -> Result<TextureRef<ColorRGB>, SceneError> {
+        match self.textures.get(&name) {
+            Some(texture) => Ok(texture.clone()),
+            None => error(pos, ParseErrorKind::UndefinedTexture(name))
+        }
+    }
+
+    /// A color attribute, given as (r, g, b) or the name of a texture
+    fn color(&self, statement: &mut Statement, key: &str) -> Result<TextureRef<ColorRGB>, SceneError> {
+        match statement.require(key)? {
+            (Value::Ident(name), pos) => self.lookup_texture(name, pos),
+            (value, pos) => Ok(Arc::new(to_vec3(key, &value, pos)?))
+        }
+    }
+
+    /// A number attribute, or the name of a texture whose brightness gives it
+    fn scalar(&self, statement: &mut Statement, key: &str) -> Result<Option<TextureRef<f64>>, SceneError> {
+        match statement.take(key) {
+            Some((Value::Ident(name), pos)) => Ok(Some(Arc::new(Luminance(self.lookup_texture(name, pos)?)))),
+            Some((value, pos)) => Ok(Some(Arc::new(to_number(key, &value, pos)?))),
+            None => Ok(None)
+        }
+    }
+
+    fn scalar_or(&self, statement: &mut Statement, key: &str, default: f64) -> Result<TextureRef<f64>, SceneError> {
+        Ok(self.scalar(statement, key)?.unwrap_or_else(|| Arc::new(default)))
+    }
+
+    /// Finds the material named by the attribute `key`
+    fn lookup_material(&self, statement: &mut Statement, key: &str) -> Result<Arc<dyn Material>, SceneError> {
+        match statement.ident(key)? {
+            Some((name, pos)) => match self.materials.get(&name) {
+                Some(material) => Ok(material.clone()),
+                None => error(pos, ParseErrorKind::UndefinedMaterial(name))
+            },
+            None => error(statement.pos, ParseErrorKind::MissingAttribute(key.to_string()))
+        }
+    }
+
+    fn shape(&mut self, statement: &mut Statement) -> Result<Arc<dyn BoundedHittable>, SceneError> {
+        let material = self.lookup_material(statement, "material")?;
+
+        let shape: Arc<dyn BoundedHittable> = match &statement.keyword[..] {
+            "sphere" => Arc::new(Sphere::new(
+                statement.vec3("center")?,
+                statement.number("radius")?,
+                material
+            )),
+            "plane" => {
+                let mut span = statement.vecs("span", 2)?;
+                let j = span.pop().unwrap();
+                let i = span.pop().unwrap();
+                Arc::new(Plane::new(statement.vec3("center")?, (i, j), material))
+            },
+            "triangle" => {
+                let mut corners = statement.vecs("corners", 3)?;
+                let c = corners.pop().unwrap();
+                let b = corners.pop().unwrap();
+                let a = corners.pop().unwrap();
+                Arc::new(Triangle::new((a, b, c), material))
+            },
+            "prism" => {
+                let mut span = statement.vecs("span", 3)?;
+                let k = span.pop().unwrap();
+                let j = span.pop().unwrap();
+                let i = span.pop().unwrap();
+                Arc::new(Prism::new(statement.vec3("center")?, (i, j, k), material))
+            },
+            "icosahedron" => Arc::new(Icosahedron::new(
+                statement.vec3("center")?,
+                statement.number("radius")?,
+                material
+            )),
+            "object" => {
+                let center = statement.vec3("center")?;
+                let scale = statement.number_or("scale", 1.0)?;
+
+                let mut rotations = vec![];
+                while let Some((value, pos)) = statement.take("rotate") {
+                    match value {
+                        Value::Tuple(ref values) if values.len() == 2 => {
+                            let angle = to_number("rotate", &values[0].0, values[0].1)?;
+                            let axis = to_vec3("rotate", &values[1].0, values[1].1)?;
+                            rotations.push((angle.to_radians(), axis));
+                        },
+                        _ => return error(pos, ParseErrorKind::InvalidValue(
+                            "rotate".to_string(), "a pair (degrees, (x, y, z))"
+                        ))
+                    }
+                }
+
+                let (path, pos) = statement.path("file", self.base_dir)?;
+                match Object::new(center, scale, rotations, &path, material) {
+                    Ok(object) => Arc::new(object),
+                    Err(err) => return error(pos, ParseErrorKind::Loader(err))
+                }
+            },
+            "volume" => {
+                let boundary = match statement.ident("boundary")? {
+                    Some((name, pos)) => match self.shapes.get(&name) {
+                        Some(shape) => shape.clone(),
+                        None => return error(pos, ParseErrorKind::UndefinedShape(name))
+                    },
+                    None => return error(statement.pos, ParseErrorKind::MissingAttribute("boundary".to_string()))
+                };
+                Arc::new(Volume::new(boundary, statement.number("density")?, material))
+            },
+            _ => unreachable!()
+        };
+
+        Ok(shape)
+    }
+
+    fn build(self, end: Position) -> Result<Scene, SceneError> {
+        let camera = match self.camera {
+            Some(camera) => Camera::new(
+                camera.location, &camera.look_at, camera.fov_deg, camera.aperture,
+                self.config.width, self.config.height
+            ),
+            None => return error(end, ParseErrorKind::MissingCamera)
+        };
+
+        Ok(Scene {
+            world: World::new(self.hittables),
+            camera,
+            config: self.config
+        })
+    }
+}
+
+/// Parses a scene from a string. Model paths are resolved relative to `base_dir`.
+pub fn parse(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    let tokens = Lexer::new(source).tokenize()?;
+    let end = tokens[tokens.len() - 1].1;
+    let mut parser = Parser { tokens, index: 0 };
+    let mut builder = SceneBuilder::new(base_dir);
+
+    while let Some(statement) = parser.statement()? {
+        builder.statement(statement)?;
+    }
+
+    builder.build(end)
+}
+
+/// Loads a scene from a glTF file, seen through its first camera. Scenes without one are framed
+/// from the front.
+pub fn load_gltf(filepath: &Path) -> Result<Scene, SceneError> {
+    // glTF's default material is white, fully metallic and fully rough
+    let default_material = Arc::new(material::Principled::new(PrincipledParams {
+        base_color: Arc::new(colors::WHITE),
+        metallic: Arc::new(1.0),
+        roughness: Arc::new(1.0),
+        ..PrincipledParams::default()
+    }));
+    let gltf = gltf::load(filepath, default_material)?;
+    if gltf.triangles.is_empty() {
+        return Err(SceneError::Gltf(GltfError::Format("the scene has no triangles".to_string())));
+    }
+    if gltf.cameras.len() > 1 {
+        eprintln!("Warning: Seeing the scene through the first of its {} cameras.", gltf.cameras.len());
+    }
+
+    let mut config = ImageConfig::default();
+    let hittables: HittableRefs = gltf.triangles.into_iter()
+        .map(|tri| Arc::new(tri) as Arc<dyn BoundedHittable>)
+        .collect();
+    let world = World::new(hittables);
+
+    let (location, look_at, up, yfov_deg) = match gltf.cameras.into_iter().next() {
+        Some(camera) => {
+            if let Some(aspect_ratio) = camera.aspect_ratio {
+                config.height = ((f64::from(config.width) / aspect_ratio).round() as u32).max(1);
+            }
+            (camera.location, camera.look_at, camera.up, camera.yfov_deg)
+        },
+        None => {
+            // Far enough back for the whole bounding sphere to fit in view
+            let yfov_deg: f64 = 40.0;
+            let bounds = world.bounding_box();
+            let look_at = bounds.center().clone();
+            let radius = (0.5 * bounds.diagonal().norm()).max(1e-3);
+            let distance = radius / (0.5 * yfov_deg).to_radians().sin();
+            (&look_at + distance * Vec3::K, look_at, Vec3::J, yfov_deg)
+        }
+    };
+
+    let camera = Camera::from_vertical_fov(location, &look_at, yfov_deg, 0.0, config.width, config.height)
+        .with_up(up);
+
+    Ok(Scene { world, camera, config })
+}
+
+/// Loads a scene from a file. Model paths are resolved relative to the file's directory. .gltf and
+/// .glb files are loaded with `load_gltf`.
+pub fn load(filepath: &Path) -> Result<Scene, SceneError> {
+    if let Some("gltf") | Some("glb") = filepath.extension().and_then(|extension| extension.to_str()) {
+        return load_gltf(filepath);
+    }
+
+    let source = fs::read_to_string(filepath)?;
+    let base_dir = filepath.parent().unwrap_or_else(|| Path::new(""));
+    parse(&source, base_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::hit::Hittable;
+    use crate::math::Rand;
+    use crate::vec::{ colors, Coord };
+
+    fn parse_str(source: &str) -> Result<Scene, SceneError> {
+        parse(source, Path::new(""))
+    }
+
+    fn error_at(result: Result<Scene, SceneError>) -> (usize, usize, ParseErrorKind) {
+        match result {
+            Err(SceneError::Parse(pos, kind)) => (pos.line, pos.column, kind),
+            Err(err) => panic!("Expected parse error, got {}", err),
+            Ok(_) => panic!("Expected parse error, but scene parsed")
+        }
+    }
+
+    #[test]
+    fn parse_simple_scene() {
+        let scene = parse_str(
+            "# A red ball\n\
+             image width=320 height=240 samples=4 max_depth=8 sampler=stratified\n\
+             image tonemap=aces exposure=0.5 white=8\n\
+             camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             background color=(0, 0, 0)\n\
+             material red diffuse albedo=(0.8, 0.3, 0.4)\n\
+             material wine translucent albedo=(1, 1, 1) ior=1.33 absorption=(0.2, 3, 2)\n\
+             sphere center=(0, 0, -1) radius=0.5 material=red\n\
+             plane center=(0, -0.5, -1) span=(\n    (100, 0, 0),\n    (0, 0, 100)\n) material=red\n"
+        ).unwrap();
+
+        assert_eq!(scene.config.width, 320);
+        assert_eq!(scene.config.height, 240);
+        assert_eq!(scene.config.samples, 4);
+        assert_eq!(scene.config.max_depth, 8);
+        assert_eq!(scene.config.sampler, SamplerKind::Stratified);
+        assert_eq!(scene.config.tone_map.operator, ToneOperator::Aces);
+        assert_eq!(scene.config.tone_map.exposure, 0.5);
+        assert_eq!(scene.config.tone_map.white_point, Some(8.0));
+        assert_eq!((scene.config.background)(0.5), colors::BLACK);
+        assert_eq!(scene.world.hittables().len(), 2);
+        assert!(scene.world.surface_area() > 0.0);
+    }
+
+    #[test]
+    fn conductors() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material gold conductor metal=gold roughness=0.3 anisotropy=0.5\n\
+             material custom conductor eta=(0.2, 0.9, 1.1) k=(3.9, 2.5, 2.1)\n\
+             sphere center=(0, 0, -1) radius=0.5 material=gold\n"
+        );
+        assert!(scene.is_ok());
+
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material lead conductor metal=lead\n"
+        ));
+        assert_eq!((line, column), (2, 31));
+        assert!(matches!(kind, ParseErrorKind::InvalidValue(key, _) if key == "metal"));
+    }
+
+    #[test]
+    fn principled_materials() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material paint principled base_color=(0.6, 0.1, 0.1) roughness=0.4 clearcoat=1\n\
+             material velvet principled base_color=(0.3, 0.1, 0.4) roughness=1 sheen=1 sheen_tint=0.8\n\
+             sphere center=(0, 0, -1) radius=0.5 material=paint\n"
+        );
+        assert!(scene.is_ok());
+
+        let (line, _, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material paint principled base_color=(0.6, 0.1, 0.1) metalness=1\n"
+        ));
+        assert_eq!(line, 2);
+        assert!(matches!(kind, ParseErrorKind::UnknownAttribute(key) if key == "metalness"));
+    }
+
+    #[test]
+    fn layered_materials() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material wood diffuse albedo=(0.5, 0.3, 0.1)\n\
+             material rust diffuse albedo=(0.4, 0.15, 0.05)\n\
+             material iron conductor eta=(2.9, 2.9, 2.6) k=(3.1, 2.9, 2.8) roughness=0.4\n\
+             material varnish coated base=wood ior=1.5 roughness=0.05 absorption=(0.05, 0.1, 0.3)\n\
+             material rusty mix first=iron second=rust weight=0.4\n\
+             sphere center=(0, 0, -1) radius=0.5 material=varnish\n"
+        );
+        assert!(scene.is_ok());
+
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material varnish coated base=wood\n"
+        ));
+        assert_eq!((line, column), (2, 30));
+        assert!(matches!(kind, ParseErrorKind::UndefinedMaterial(name) if name == "wood"));
+    }
+
+    #[test]
+    fn textures() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             texture tiles checker even=(0.9, 0.9, 0.9) odd=(0.1, 0.1, 0.1) scale=8\n\
+             texture veins marble scale=4 low=(0.2, 0.2, 0.25) high=(0.95, 0.95, 0.9)\n\
+             texture patches checker even=tiles odd=veins mapping=solid\n\
+             material floor diffuse albedo=patches\n\
+             material scuffed conductor metal=silver roughness=veins\n\
+             material blend mix first=floor second=scuffed weight=tiles\n\
+             sphere center=(0, 0, -1) radius=0.5 material=blend\n"
+        );
+        assert!(scene.is_ok());
+
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material floor diffuse albedo=tiles\n"
+        ));
+        assert_eq!((line, column), (2, 31));
+        assert!(matches!(kind, ParseErrorKind::UndefinedTexture(name) if name == "tiles"));
+
+        let (line, _, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             texture wood image file=\"no-such-image.png\"\n"
+        ));
+        assert_eq!(line, 2);
+        assert!(matches!(kind, ParseErrorKind::Texture(TextureError::Io(_))));
+
+        for seed in &["-3", "1.7"] {
+            let (line, column, kind) = error_at(parse_str(&format!(
+                "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+                 texture veins marble seed={}\n", seed
+            )));
+            assert_eq!((line, column), (2, 27));
+            assert!(matches!(kind, ParseErrorKind::InvalidValue(key, _) if key == "seed"));
+        }
+    }
+
+    #[test]
+    fn normal_detail() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             texture dents noise scale=20\n\
+             texture tiles checker even=(0.5, 0.5, 1) odd=(0.6, 0.5, 0.9) scale=8\n\
+             material pitted conductor metal=gold roughness=0.2 bump=dents bump_height=0.02\n\
+             material tiled diffuse albedo=(0.8, 0.8, 0.8) normal_map=tiles\n\
+             sphere center=(0, 0, -1) radius=0.5 material=pitted\n"
+        );
+        assert!(scene.is_ok());
+
+        let (line, _, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             texture dents noise scale=20\n\
+             material both diffuse albedo=(1, 1, 1) normal_map=dents bump=dents\n"
+        ));
+        assert_eq!(line, 3);
+        assert!(matches!(kind, ParseErrorKind::InvalidValue(key, _) if key == "bump"));
+    }
+
+    #[test]
+    fn hidden_shapes_bound_volumes() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material white diffuse albedo=(1, 1, 1)\n\
+             sphere name=ball visible=false center=(0, 0, -1) radius=0.5 material=white\n\
+             volume boundary=ball density=0.5 material=white\n"
+        ).unwrap();
+
+        assert_eq!(scene.world.hittables().len(), 1);
+    }
+
+    #[test]
+    fn undefined_material() {
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             sphere center=(0, 0, -1) radius=0.5 material=gold\n"
+        ));
+
+        assert_eq!((line, column), (2, 46));
+        assert!(matches!(kind, ParseErrorKind::UndefinedMaterial(name) if name == "gold"));
+    }
+
+    #[test]
+    fn invalid_vector() {
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0) at=(0, 0, -1) fov=30\n"
+        ));
+
+        assert_eq!((line, column), (1, 13));
+        assert!(matches!(kind, ParseErrorKind::InvalidValue(key, _) if key == "from"));
+    }
+
+    #[test]
+    fn unknown_attribute() {
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30 zoom=2\n"
+        ));
+
+        assert_eq!((line, column), (1, 44));
+        assert!(matches!(kind, ParseErrorKind::UnknownAttribute(key) if key == "zoom"));
+    }
+
+    #[test]
+    fn shipped_scenes_parse() {
+        for path in &["scenes/spheres.scene", "scenes/cornell.scene", "scenes/balls.scene"] {
+            if let Err(err) = load(Path::new(path)) {
+                panic!("{}: {}", path, err);
+            }
+        }
+
+        // The Cornell box's commented-out variations, with the bunny standing in for Lucy, who
+        // isn't shipped
+        let cornell = fs::read_to_string("scenes/cornell.scene").unwrap();
+        let (scene, variations) = cornell.split_once("# Variations used for the other renders in the README\n").unwrap();
+        let variations: String = variations.lines()
+            .map(|line| format!("{}\n", line.trim_start_matches("# ").replace("lucy.obj", "bunny.obj")))
+            .collect();
+        let variations = format!("{}{}", scene, variations);
+        if let Err(err) = parse(&variations, Path::new("scenes")) {
+            panic!("cornell.scene variations: {}", err);
+        }
+    }
+
+    #[test]
+    fn repeated_rotations() {
+        let scene = parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30\n\
+             material white diffuse albedo=(1, 1, 1)\n\
+             object file=\"models/bunny.obj\" center=(0, 0, -1) rotate=(90, (-1, 0, 0)) rotate=(15, (0, 1, 0)) material=white\n"
+        ).unwrap();
+        assert_eq!(scene.world.hittables().len(), 1);
+
+        let (line, column, kind) = error_at(parse_str(
+            "camera from=(0, 0, 1) at=(0, 0, -1) fov=30 fov=40\n"
+        ));
+        assert_eq!((line, column), (1, 44));
+        assert!(matches!(kind, ParseErrorKind::DuplicateAttribute(key) if key == "fov"));
+    }
+
+    #[test]
+    fn gltf_scenes() {
+        let document = |nodes: &str| format!(r#"{{
+            "asset": {{ "version": "2.0" }},
+            "nodes": [{}],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }} }}] }}],
+            "cameras": [{{ "type": "perspective", "perspective": {{ "yfov": 0.5, "aspectRatio": 2, "znear": 0.1 }} }}],
+            "accessors": [{{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }}],
+            "bufferViews": [{{ "buffer": 0, "byteLength": 36 }}],
+            "buffers": [{{
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA",
+                "byteLength": 36
+            }}]
+        }}"#, nodes);
+        let dir = std::env::temp_dir().join(format!("raytracer-scene-gltf-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("camera.gltf"), document(r#"{ "mesh": 0 }, { "camera": 0, "translation": [0, 0, 4] }"#)).unwrap();
+        fs::write(dir.join("framed.gltf"), document(r#"{ "mesh": 0 }"#)).unwrap();
+        fs::write(dir.join("empty.gltf"), document(r#"{ "camera": 0 }"#)).unwrap();
+        let with_camera = load(&dir.join("camera.gltf"));
+        let framed = load(&dir.join("framed.gltf"));
+        let empty = load(&dir.join("empty.gltf"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Scenes are seen through their camera, at its aspect ratio
+        let scene = with_camera.unwrap();
+        assert_eq!(scene.world.hittables().len(), 1);
+        assert_eq!((scene.config.width, scene.config.height), (640, 320));
+        assert_eq!(scene.camera.location(), &Point3::new(0.0, 0.0, 4.0));
+        assert_eq!(scene.camera.look_at(), &Point3::new(0.0, 0.0, 3.0));
+        // and keep its vertical field of view at any other aspect ratio
+        for &(width, height) in &[(640, 320), (300, 600)] {
+            let top = scene.camera.with_resolution(width, height).ray(0.5, 0.0, &mut Rand::seeded(0)).dir;
+            let angle = top.unit().dot(&-Vec3::K).acos();
+            assert!((angle - 0.25).abs() < 1e-9, "{}x{} sees {} radians up", width, height, angle);
+        }
+
+        // or else from in front
+        let scene = framed.unwrap();
+        assert_eq!(scene.camera.look_at(), &Point3::new(0.5, 0.5, 0.0));
+        assert!(scene.camera.location()[Coord::Z] > 1.0);
+
+        assert!(matches!(empty, Err(SceneError::Gltf(GltfError::Format(_)))));
+    }
+
+    #[test]
+    fn missing_camera() {
+        let (line, _, kind) = error_at(parse_str("image width=10 height=10\n"));
+
+        assert_eq!(line, 2);
+        assert!(matches!(kind, ParseErrorKind::MissingCamera));
+    }
+}
@@ -0,0 +1,412 @@
+use std::convert;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::Texture;
+use crate::geom::hit::Hit;
+use crate::math::lerp;
+use crate::output::{ png, zlib };
+use crate::tonemap::{ luminance, srgb_eotf };
+use crate::vec::{ Color, ColorRGB };
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(io::Error),
+    Format(String),
+    Unsupported(String)
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            TextureError::Io(err) =>
+                write!(f, "Could not load image: {}", err),
+            TextureError::Format(msg) =>
+                write!(f, "Could not read image: {}", msg),
+            TextureError::Unsupported(msg) =>
+                write!(f, "Unsupported image: {}", msg)
+        }
+    }
+}
+
+impl Error for TextureError {}
+
+impl convert::From<io::Error> for TextureError {
+    fn from(err: io::Error) -> Self {
+        TextureError::Io(err)
+    }
+}
+
+fn format_error<T>(msg: &str) -> Result<T, TextureError> {
+    Err(TextureError::Format(msg.to_string()))
+}
+
+/// The most pixels an image can have, so that a file's header can't ask for more memory than
+/// there is
+const MAX_PIXELS: usize = 1 << 28;
+
+/// A texture looked up from a PPM or PNG image by uv coordinates, which wrap around at its edges
+#[derive(Debug)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    /// Linear colors, by row from the top
+    pixels: Vec<ColorRGB>
+}
+
+impl Image {
+    /// Loads an image. Images of colors are usually sRGB-encoded, but ones of other quantities, like
+    /// roughness, store them as they are, so `srgb` should be false for those.
+    pub fn load(path: &Path, srgb: bool) -> Result<Image, TextureError> {
+        Image::decode(&fs::read(path)?, srgb)
+    }
+
+    /// Reads an image from the bytes of a PNG or PPM file, e.g. one embedded in a model
+    pub fn decode(bytes: &[u8], srgb: bool) -> Result<Image, TextureError> {
+        let (width, height, mut pixels) = if bytes.starts_with(&png::SIGNATURE) {
+            decode_png(bytes)?
+        } else if bytes.starts_with(b"P3") || bytes.starts_with(b"P6") {
+            decode_ppm(bytes)?
+        } else {
+            return Err(TextureError::Unsupported("expected a PNG or PPM image".to_string()));
+        };
+
+        if srgb {
+            for pixel in pixels.iter_mut() {
+                *pixel = ColorRGB::new(
+                    srgb_eotf(pixel[Color::R]), srgb_eotf(pixel[Color::G]), srgb_eotf(pixel[Color::B])
+                );
+            }
+        }
+
+        Ok(Image { width, height, pixels })
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<ColorRGB>) -> Image {
+        assert_eq!(pixels.len(), width * height, "Image needs {} pixels", width * height);
+        Image { width, height, pixels }
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> &ColorRGB {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        &self.pixels[y * self.width + x]
+    }
+}
+
+/// Interpolates between the four pixels nearest to the uv coordinates, with v going up the image
+impl Texture<ColorRGB> for Image {
+    fn value(&self, hit: &Hit) -> ColorRGB {
+        let x = hit.uv.0 * self.width as f64 - 0.5;
+        let y = (1.0 - hit.uv.1) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor() as i64, y.floor() as i64);
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+
+        lerp(
+            lerp(self.pixel(x0, y0).clone(), self.pixel(x0 + 1, y0).clone(), tx),
+            lerp(self.pixel(x0, y0 + 1).clone(), self.pixel(x0 + 1, y0 + 1).clone(), tx),
+            ty
+        )
+    }
+}
+
+impl Texture<f64> for Image {
+    fn value(&self, hit: &Hit) -> f64 {
+        luminance(&Texture::<ColorRGB>::value(self, hit))
+    }
+}
+
+/// Reads a PPM, in ASCII (P3) or binary (P6)
+fn decode_ppm(bytes: &[u8]) -> Result<(usize, usize, Vec<ColorRGB>), TextureError> {
+    // The header is the magic number, width, height and maximum value, separated by whitespace
+    // and comments
+    let mut index = 2;
+    let mut header = [0_usize; 3];
+    for value in header.iter_mut() {
+        loop {
+            match bytes.get(index) {
+                Some(b'#') => while bytes.get(index).is_some_and(|&c| c != b'\n') { index += 1; },
+                Some(c) if c.is_ascii_whitespace() => index += 1,
+                _ => break
+            }
+        }
+        let start = index;
+        while bytes.get(index).is_some_and(u8::is_ascii_digit) {
+            index += 1;
+        }
+        *value = match std::str::from_utf8(&bytes[start..index]).ok().and_then(|n| n.parse().ok()) {
+            Some(n) if n > 0 => n,
+            _ => return format_error("bad PPM header")
+        };
+    }
+    let [width, height, max] = header;
+    if max > 65535 {
+        return format_error("bad PPM maximum value");
+    }
+
+    let count = match width.checked_mul(height).and_then(|pixels| pixels.checked_mul(3)) {
+        Some(count) => count,
+        None => return format_error("PPM is too large")
+    };
+    let samples: Vec<usize> = if bytes[1] == b'3' {
+        let text = String::from_utf8_lossy(&bytes[index..]);
+        match text.split_ascii_whitespace().take(count).map(|n| n.parse().ok()).collect() {
+            Some(samples) => samples,
+            None => return format_error("bad PPM sample")
+        }
+    } else {
+        // A single whitespace character separates the header from the pixels
+        let data = bytes.get(index + 1..).unwrap_or(&[]);
+        if max < 256 {
+            data.iter().take(count).map(|&b| usize::from(b)).collect()
+        } else {
+            data.chunks_exact(2).take(count).map(|pair| usize::from(u16::from_be_bytes([pair[0], pair[1]]))).collect()
+        }
+    };
+    if samples.len() < count {
+        return format_error("PPM ends early");
+    }
+
+    let scale = 1.0 / max as f64;
+    let pixels = samples.chunks_exact(3)
+        .map(|rgb| ColorRGB::new(scale * rgb[0] as f64, scale * rgb[1] as f64, scale * rgb[2] as f64))
+        .collect();
+    Ok((width, height, pixels))
+}
+
+/// Reads a non-interlaced PNG of any color type and bit depth
+fn decode_png(bytes: &[u8]) -> Result<(usize, usize, Vec<ColorRGB>), TextureError> {
+    let mut index = png::SIGNATURE.len();
+    let mut header = None;
+    let mut palette = vec![];
+    let mut data = vec![];
+
+    while index + 8 <= bytes.len() {
+        let length = u32::from_be_bytes([bytes[index], bytes[index + 1], bytes[index + 2], bytes[index + 3]]) as usize;
+        let kind = &bytes[index + 4..index + 8];
+        let contents = match bytes.get(index + 8..index + 8 + length) {
+            Some(contents) => contents,
+            None => return format_error("PNG ends early")
+        };
+        index += length + 12;
+
+        match kind {
+            b"IHDR" if length == 13 => header = Some(contents.to_vec()),
+            b"PLTE" => palette = contents.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2]]).collect(),
+            b"IDAT" => data.extend_from_slice(contents),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let header = match header {
+        Some(header) => header,
+        None => return format_error("PNG has no header")
+    };
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlaced) = (usize::from(header[8]), header[9], header[12] != 0);
+    if interlaced {
+        return Err(TextureError::Unsupported("interlaced PNGs can't be read".to_string()));
+    }
+
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return format_error("bad PNG color type")
+    };
+    if width == 0 || height == 0 || ![1, 2, 4, 8, 16].contains(&depth) {
+        return format_error("bad PNG header");
+    }
+
+    // Every row starts with a byte naming its filter. The sizes come from the header, so they're
+    // checked before anything is allocated for them.
+    let pixel_count = width.checked_mul(height).filter(|&pixels| pixels <= MAX_PIXELS);
+    let row_bytes = width.checked_mul(channels * depth).map(|bits| bits.div_ceil(8));
+    let size = row_bytes.and_then(|row_bytes| (row_bytes + 1).checked_mul(height));
+    let (row_bytes, size) = match (pixel_count, row_bytes, size) {
+        (Some(_), Some(row_bytes), Some(size)) => (row_bytes, size),
+        _ => return format_error("PNG is too large")
+    };
+
+    let data = match zlib::decompress(&data, size) {
+        Some(data) => data,
+        None => return format_error("corrupt PNG data")
+    };
+    if data.len() < size {
+        return format_error("PNG ends early");
+    }
+    let rows = unfilter(&data, row_bytes, (channels * depth).div_ceil(8), height);
+
+    let max = ((1 << depth) - 1) as f64;
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in rows.chunks_exact(row_bytes) {
+        let sample = |i: usize| match depth {
+            16 => usize::from(u16::from_be_bytes([row[2 * i], row[2 * i + 1]])),
+            8 => usize::from(row[i]),
+            _ => {
+                // Samples narrower than a byte are packed from the high bits down
+                let bit = i * depth;
+                usize::from(row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1)
+            }
+        };
+
+        for x in 0..width {
+            let pixel = match color_type {
+                3 => match palette.get(sample(x)) {
+                    Some(&[r, g, b]) => ColorRGB::new(f64::from(r), f64::from(g), f64::from(b)) * (1.0 / 255.0),
+                    None => return format_error("PNG color missing from palette")
+                },
+                0 | 4 => {
+                    let gray = sample(channels * x) as f64 / max;
+                    ColorRGB::new(gray, gray, gray)
+                },
+                _ => ColorRGB::new(
+                    sample(channels * x) as f64 / max,
+                    sample(channels * x + 1) as f64 / max,
+                    sample(channels * x + 2) as f64 / max
+                )
+            };
+            pixels.push(pixel);
+        }
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// Undoes the filter each scanline was stored with, given the number of bytes in a pixel
+fn unfilter(data: &[u8], row_bytes: usize, pixel_bytes: usize, height: usize) -> Vec<u8> {
+    let mut rows = vec![0; row_bytes * height];
+    for y in 0..height {
+        let filter = data[y * (row_bytes + 1)];
+        let line = &data[y * (row_bytes + 1) + 1..(y + 1) * (row_bytes + 1)];
+        let (done, rest) = rows.split_at_mut(y * row_bytes);
+        let above = if y > 0 { &done[(y - 1) * row_bytes..] } else { &[][..] };
+        let row = &mut rest[..row_bytes];
+
+        for i in 0..row_bytes {
+            let left = if i >= pixel_bytes { row[i - pixel_bytes] } else { 0 };
+            let up = above.get(i).copied().unwrap_or(0);
+            let up_left = if i >= pixel_bytes { above.get(i - pixel_bytes).copied().unwrap_or(0) } else { 0 };
+            let predicted = match filter {
+                1 => left,
+                2 => up,
+                3 => ((u16::from(left) + u16::from(up)) / 2) as u8,
+                4 => png::paeth(left, up, up_left),
+                _ => 0
+            };
+            row[i] = line[i].wrapping_add(predicted);
+        }
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::output::{ ImageFormat, write_image };
+    use crate::texture::tests::hit_at;
+    use crate::tonemap::ToneMapping;
+    use crate::vec::{ colors, Point3 };
+
+    fn round_trip(format: ImageFormat, name: &str) -> Image {
+        let mut image = Framebuffer::new(3, 2);
+        image.pixels_mut()[0] = ColorRGB::new(1.0, 0.0, 0.0);
+        image.pixels_mut()[5] = ColorRGB::new(0.0, 0.0, 1.0);
+        image.pixels_mut()[4] = ColorRGB::new(0.5, 0.5, 0.5);
+
+        let path = std::env::temp_dir().join(format!("raytracer-texture-{}-{}.{}", name, std::process::id(), format.extension()));
+        write_image(&path, format, &image, &ToneMapping::default()).unwrap();
+        let texture = Image::load(&path, true).unwrap();
+        fs::remove_file(&path).unwrap();
+        texture
+    }
+
+    #[test]
+    fn reads_written_images() {
+        for &(format, name) in &[(ImageFormat::Png, "png"), (ImageFormat::PpmBinary, "p6"), (ImageFormat::PpmAscii, "p3")] {
+            let texture = round_trip(format, name);
+            assert_eq!((texture.width, texture.height), (3, 2));
+            assert_eq!(texture.pixels[0], ColorRGB::new(1.0, 0.0, 0.0));
+            assert_eq!(texture.pixels[5], ColorRGB::new(0.0, 0.0, 1.0));
+            assert_eq!(texture.pixels[1], colors::BLACK);
+            // 8-bit sRGB stores mid-gray to within a step or so
+            assert!((texture.pixels[4][Color::G] - 0.5).abs() < 0.01, "Read {}", texture.pixels[4]);
+        }
+    }
+
+    #[test]
+    fn short_ppms_are_errors() {
+        let error = |bytes: &[u8]| match decode_ppm(bytes) {
+            Err(TextureError::Format(msg)) => msg,
+            result => panic!("Expected a format error, got {:?}", result.map(|(width, height, _)| (width, height)))
+        };
+        assert_eq!(error(b"P6 1 1 255"), "PPM ends early");
+        assert_eq!(error(b"P6 1 1 255\n\xff\x00"), "PPM ends early");
+        assert_eq!(error(b"P3 2 1 255\n255 0 0\n"), "PPM ends early");
+        assert_eq!(error(b"P3 1 1 255\n255 x 0\n"), "bad PPM sample");
+        assert_eq!(error(b"P3 1 1 255\n255 -1 0\n"), "bad PPM sample");
+        assert_eq!(error(b"P6 99999999999 99999999999 255\n"), "PPM is too large");
+    }
+
+    #[test]
+    fn lookups_interpolate_and_wrap() {
+        let image = Image::from_pixels(2, 1, vec![colors::BLACK, colors::WHITE]);
+        let at = |u: f64| Texture::<f64>::value(&image, &hit_at(Point3::O, (u, 0.5)));
+        assert!(crate::math::f_eq(at(0.25), 0.0));
+        assert!(crate::math::f_eq(at(0.5), 0.5));
+        assert!(crate::math::f_eq(at(0.75), 1.0));
+        assert!(crate::math::f_eq(at(1.0), 0.5));
+    }
+
+    /// Strings chunks together into a PNG, leaving their checksums zero since they aren't checked
+    fn png_file(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut png_bytes = png::SIGNATURE.to_vec();
+        for (kind, data) in chunks {
+            png_bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png_bytes.extend_from_slice(*kind);
+            png_bytes.extend_from_slice(data);
+            png_bytes.extend_from_slice(&[0; 4]);
+        }
+        png_bytes
+    }
+
+    #[test]
+    fn reads_palettes_and_packed_samples() {
+        // A 3x1 image with a 2-bit palette of red, green and blue, each row filtered with Sub
+        let png_bytes = png_file(&[
+            (b"IHDR", &[0, 0, 0, 3, 0, 0, 0, 1, 2, 3, 0, 0, 0]),
+            (b"PLTE", &[255, 0, 0, 0, 255, 0, 0, 0, 255]),
+            (b"IDAT", &zlib::compress(&[1, 0b0001_1000])),
+            (b"IEND", &[])
+        ]);
+
+        let (width, height, pixels) = decode_png(&png_bytes).unwrap();
+        assert_eq!((width, height), (3, 1));
+        assert_eq!(pixels, vec![ColorRGB::new(1.0, 0.0, 0.0), ColorRGB::new(0.0, 1.0, 0.0), ColorRGB::new(0.0, 0.0, 1.0)]);
+    }
+
+    #[test]
+    fn huge_pngs_are_errors() {
+        let error = |header: &[u8], data: &[u8]| {
+            match decode_png(&png_file(&[(b"IHDR", header), (b"IDAT", &zlib::compress(data)), (b"IEND", &[])])) {
+                Err(TextureError::Format(msg)) => msg,
+                result => panic!("Expected a format error, got {:?}", result.map(|(width, height, _)| (width, height)))
+            }
+        };
+
+        // A 16-bit RGBA header too big to even count the bytes of
+        let huge = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 16, 6, 0, 0, 0];
+        assert_eq!(error(&huge, &[0; 16]), "PNG is too large");
+        // A 2x2 gray image whose data inflates to far more than its 6 bytes
+        let small = [0, 0, 0, 2, 0, 0, 0, 2, 8, 0, 0, 0, 0];
+        assert_eq!(error(&small, &[0; 100_000]), "corrupt PNG data");
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::geom::hit::Hit;
+use crate::math::lerp;
+use crate::tonemap::luminance;
+use crate::vec::{ Color, ColorRGB, Coord };
+
+pub mod image;
+pub mod noise;
+pub use self::image::{ Image, TextureError };
+pub use self::noise::{ Noise, NoiseKind, Perlin };
+
+/// A material parameter that varies over a surface, looked up at each hit by its uv coordinates,
+/// point or normal
+pub trait Texture<T>: Send + Sync + fmt::Debug {
+    fn value(&self, hit: &Hit) -> T;
+
+    /// The texture's value, if it's the same everywhere
+    fn constant(&self) -> Option<T> { None }
+}
+
+/// A texture shared between materials, or between several parameters of one
+pub type TextureRef<T> = Arc<dyn Texture<T>>;
+
+impl Texture<ColorRGB> for ColorRGB {
+    fn value(&self, _: &Hit) -> ColorRGB {
+        self.clone()
+    }
+
+    fn constant(&self) -> Option<ColorRGB> {
+        Some(self.clone())
+    }
+}
+
+impl Texture<f64> for f64 {
+    fn value(&self, _: &Hit) -> f64 {
+        *self
+    }
+
+    fn constant(&self) -> Option<f64> {
+        Some(*self)
+    }
+}
+
+impl<T> Texture<T> for TextureRef<T> {
+    fn value(&self, hit: &Hit) -> T {
+        self.as_ref().value(hit)
+    }
+
+    fn constant(&self) -> Option<T> {
+        self.as_ref().constant()
+    }
+}
+
+/// Reads a color texture as a scalar one, by its luminance
+#[derive(Debug)]
+pub struct Luminance(pub TextureRef<ColorRGB>);
+
+impl Texture<f64> for Luminance {
+    fn value(&self, hit: &Hit) -> f64 {
+        luminance(&self.0.value(hit))
+    }
+
+    fn constant(&self) -> Option<f64> {
+        self.0.constant().map(|color| luminance(&color))
+    }
+}
+
+/// Which coordinates a texture is laid out in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    /// Over the surface, by its uv coordinates
+    Uv,
+    /// Through space, as if the object were carved out of a solid block
+    Solid
+}
+
+/// Alternating squares, or cubes, of two other textures
+#[derive(Debug)]
+pub struct Checker<T> {
+    even: TextureRef<T>,
+    odd: TextureRef<T>,
+    /// How many squares fit in a unit of uv, or of space
+    scale: f64,
+    mapping: Mapping
+}
+
+impl<T> Checker<T> {
+    pub fn new(even: TextureRef<T>, odd: TextureRef<T>, scale: f64, mapping: Mapping) -> Checker<T> {
+        Checker { even, odd, scale, mapping }
+    }
+}
+
+impl<T: fmt::Debug> Texture<T> for Checker<T> {
+    fn value(&self, hit: &Hit) -> T {
+        let cell = |x: f64| (self.scale * x).floor() as i64;
+        let parity = match self.mapping {
+            Mapping::Uv => cell(hit.uv.0) + cell(hit.uv.1),
+            Mapping::Solid => cell(hit.point[Coord::X]) + cell(hit.point[Coord::Y]) + cell(hit.point[Coord::Z])
+        };
+
+        if parity.rem_euclid(2) == 0 { self.even.value(hit) } else { self.odd.value(hit) }
+    }
+}
+
+/// Blends between two colors by a scalar texture, e.g. to color noise
+#[derive(Debug)]
+pub struct Ramp {
+    low: ColorRGB,
+    high: ColorRGB,
+    by: TextureRef<f64>
+}
+
+impl Ramp {
+    pub fn new(low: ColorRGB, high: ColorRGB, by: TextureRef<f64>) -> Ramp {
+        Ramp { low, high, by }
+    }
+}
+
+impl Texture<ColorRGB> for Ramp {
+    fn value(&self, hit: &Hit) -> ColorRGB {
+        lerp(self.low.clone(), self.high.clone(), self.by.value(hit))
+    }
+}
+
+/// A color texture multiplied by a constant color
+#[derive(Debug)]
+pub struct Tint {
+    texture: TextureRef<ColorRGB>,
+    color: ColorRGB
+}
+
+impl Tint {
+    pub fn new(texture: TextureRef<ColorRGB>, color: ColorRGB) -> Tint {
+        Tint { texture, color }
+    }
+}
+
+impl Texture<ColorRGB> for Tint {
+    fn value(&self, hit: &Hit) -> ColorRGB {
+        &self.color * self.texture.value(hit)
+    }
+
+    fn constant(&self) -> Option<ColorRGB> {
+        self.texture.constant().map(|color| &self.color * color)
+    }
+}
+
+/// One channel of a color texture, scaled, for maps that pack several numbers into one image
+#[derive(Debug)]
+pub struct Channel {
+    texture: TextureRef<ColorRGB>,
+    channel: Color,
+    scale: f64
+}
+
+impl Channel {
+    pub fn new(texture: TextureRef<ColorRGB>, channel: Color, scale: f64) -> Channel {
+        Channel { texture, channel, scale }
+    }
+}
+
+impl Texture<f64> for Channel {
+    fn value(&self, hit: &Hit) -> f64 {
+        self.scale * self.texture.value(hit)[self.channel]
+    }
+
+    fn constant(&self) -> Option<f64> {
+        self.texture.constant().map(|color| self.scale * color[self.channel])
+    }
+}
+
+/// The colors of a mesh's vertices, blended across its faces. Surfaces without them take
+/// `fallback` instead.
+#[derive(Debug)]
+pub struct VertexColor {
+    fallback: ColorRGB
+}
+
+impl VertexColor {
+    pub fn new(fallback: ColorRGB) -> VertexColor {
+        VertexColor { fallback }
+    }
+}
+
+impl Texture<ColorRGB> for VertexColor {
+    fn value(&self, hit: &Hit) -> ColorRGB {
+        hit.vertex_color.clone().unwrap_or_else(|| self.fallback.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::DiffuseLambert;
+    use crate::math::f_eq;
+    use crate::vec::{ colors, Point3, Vec3 };
+
+    pub fn hit_at(point: Point3, uv: (f64, f64)) -> Hit {
+        Hit::new(point, Vec3::J, 1.0, true, Arc::new(DiffuseLambert::new(colors::WHITE))).with_uv(uv)
+    }
+
+    #[test]
+    fn checkers_alternate() {
+        let checker = Checker::new(Arc::new(1.0), Arc::new(0.0), 2.0, Mapping::Uv);
+        assert!(f_eq(checker.value(&hit_at(Point3::O, (0.1, 0.1))), 1.0));
+        assert!(f_eq(checker.value(&hit_at(Point3::O, (0.6, 0.1))), 0.0));
+        assert!(f_eq(checker.value(&hit_at(Point3::O, (0.6, 0.6))), 1.0));
+
+        let solid = Checker::new(Arc::new(1.0), Arc::new(0.0), 1.0, Mapping::Solid);
+        assert!(f_eq(solid.value(&hit_at(Point3::new(0.5, 0.5, 0.5), (0.0, 0.0))), 1.0));
+        assert!(f_eq(solid.value(&hit_at(Point3::new(-0.5, 0.5, 0.5), (0.0, 0.0))), 0.0));
+    }
+
+    #[test]
+    fn constants_are_known() {
+        let color: TextureRef<ColorRGB> = Arc::new(ColorRGB::new(0.2, 0.4, 0.6));
+        assert_eq!(color.constant(), Some(ColorRGB::new(0.2, 0.4, 0.6)));
+        assert!(Luminance(color).constant().is_some());
+
+        let checker: TextureRef<f64> = Arc::new(Checker::new(Arc::new(1.0), Arc::new(0.0), 1.0, Mapping::Uv));
+        assert_eq!(checker.constant(), None);
+    }
+
+    #[test]
+    fn tints_and_channels() {
+        let color: TextureRef<ColorRGB> = Arc::new(ColorRGB::new(0.2, 0.4, 0.6));
+        let tint = Tint::new(color.clone(), ColorRGB::new(0.5, 1.0, 2.0));
+        assert_eq!(tint.value(&hit_at(Point3::O, (0.0, 0.0))), ColorRGB::new(0.1, 0.4, 1.2));
+        assert!(f_eq(Channel::new(color.clone(), Color::G, 0.5).value(&hit_at(Point3::O, (0.0, 0.0))), 0.2));
+        assert!(f_eq(Channel::new(color, Color::B, 1.0).constant().unwrap(), 0.6));
+    }
+
+    #[test]
+    fn vertex_colors_fall_back() {
+        let paint = VertexColor::new(colors::WHITE);
+        let red = ColorRGB::new(1.0, 0.0, 0.0);
+        assert_eq!(paint.value(&hit_at(Point3::O, (0.0, 0.0)).with_vertex_color(red.clone())), red);
+        assert_eq!(paint.value(&hit_at(Point3::O, (0.0, 0.0))), colors::WHITE);
+    }
+}
@@ -0,0 +1,153 @@
+use super::Texture;
+use crate::geom::hit::Hit;
+use crate::math::{ self, Rand };
+use crate::vec::{ Coord, Point3, Vec3 };
+
+/// Perlin's gradient noise: smooth, random-looking values between -1 and 1 that vary over about a
+/// unit of space
+#[derive(Debug)]
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutations: [Vec<usize>; 3]
+}
+
+/// The patterns built out of Perlin noise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoiseKind {
+    /// The noise itself, brought into [0, 1]
+    Noise,
+    /// Several octaves of noise added up, for a rougher look
+    Turbulence,
+    /// Bands along z, wavered by turbulence
+    Marble
+}
+
+/// A solid texture of Perlin noise, from 0 to 1
+#[derive(Debug)]
+pub struct Noise {
+    perlin: Perlin,
+    kind: NoiseKind,
+    /// How many features fit in a unit of space
+    scale: f64
+}
+
+impl Perlin {
+    const POINT_COUNT: usize = 256;
+    const OCTAVES: u32 = 7;
+
+    /// Creates noise from `seed`, so the same seed always gives the same pattern
+    pub fn new(seed: u64) -> Perlin {
+        let mut rand = Rand::seeded(seed);
+        let gradients = (0..Perlin::POINT_COUNT).map(|_| Vec3::random_unit(&mut rand)).collect();
+        let mut permutation = || {
+            let mut points: Vec<usize> = (0..Perlin::POINT_COUNT).collect();
+            for i in (1..points.len()).rev() {
+                let j = (math::rand_f64(&mut rand) * (i + 1) as f64) as usize;
+                points.swap(i, j.min(i));
+            }
+            points
+        };
+
+        Perlin { gradients, permutations: [permutation(), permutation(), permutation()] }
+    }
+
+    pub fn noise(&self, point: &Point3) -> f64 {
+        let coords = [point[Coord::X], point[Coord::Y], point[Coord::Z]];
+        let cell = coords.map(|x| x.floor() as i64);
+        let offset = [coords[0] - coords[0].floor(), coords[1] - coords[1].floor(), coords[2] - coords[2].floor()];
+        // Hermite smoothing hides the grid the gradients sit on
+        let smooth = offset.map(|t| t * t * (3.0 - 2.0 * t));
+
+        let mut total = 0.0;
+        for corner in 0..8 {
+            let (i, j, k) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let index = |axis: usize, step: usize| ((cell[axis] + step as i64) & (Perlin::POINT_COUNT as i64 - 1)) as usize;
+            let gradient = &self.gradients[
+                self.permutations[0][index(0, i)] ^ self.permutations[1][index(1, j)] ^ self.permutations[2][index(2, k)]
+            ];
+
+            let weight = |step: usize, t: f64| if step == 1 { t } else { 1.0 - t };
+            let to_point = Vec3::new(offset[0] - i as f64, offset[1] - j as f64, offset[2] - k as f64);
+            total += weight(i, smooth[0]) * weight(j, smooth[1]) * weight(k, smooth[2]) * gradient.dot(&to_point);
+        }
+
+        total
+    }
+
+    /// Octaves of noise, each at twice the frequency and half the strength of the one before
+    pub fn turbulence(&self, point: &Point3) -> f64 {
+        let mut total = 0.0;
+        let (mut point, mut weight) = (point.clone(), 1.0);
+        for _ in 0..Perlin::OCTAVES {
+            total += weight * self.noise(&point);
+            weight *= 0.5;
+            point = 2.0 * point;
+        }
+
+        total.abs()
+    }
+}
+
+impl Noise {
+    pub fn new(kind: NoiseKind, scale: f64, seed: u64) -> Noise {
+        Noise { perlin: Perlin::new(seed), kind, scale }
+    }
+}
+
+impl Texture<f64> for Noise {
+    fn value(&self, hit: &Hit) -> f64 {
+        let point = self.scale * &hit.point;
+        let value = match self.kind {
+            NoiseKind::Noise => 0.5 * (1.0 + self.perlin.noise(&point)),
+            NoiseKind::Turbulence => self.perlin.turbulence(&point),
+            NoiseKind::Marble => 0.5 * (1.0 + (point[Coord::Z] + 10.0 * self.perlin.turbulence(&point)).sin())
+        };
+
+        math::f_clamp(value, 0.0, 1.0)
+    }
+}
+
+impl NoiseKind {
+    /// Picks a kind of noise from its name, e.g. "marble"
+    pub fn from_name(name: &str) -> Option<NoiseKind> {
+        match name {
+            "noise" => Some(NoiseKind::Noise),
+            "turbulence" => Some(NoiseKind::Turbulence),
+            "marble" => Some(NoiseKind::Marble),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::f_eq;
+    use crate::texture::tests::hit_at;
+
+    #[test]
+    fn noise_is_smooth_and_repeatable() {
+        let perlin = Perlin::new(7);
+        // The noise is zero at the corners of its grid, and changes little over short distances
+        assert!(f_eq(perlin.noise(&Point3::new(3.0, -2.0, 5.0)), 0.0));
+        let point = Point3::new(0.3, 1.7, -2.2);
+        let nearby = Point3::new(0.3001, 1.7, -2.2);
+        assert!((perlin.noise(&point) - perlin.noise(&nearby)).abs() < 1e-3);
+        assert!(f_eq(perlin.noise(&point), Perlin::new(7).noise(&point)));
+
+        let values: Vec<f64> = (0..1000).map(|i| perlin.noise(&Point3::new(0.37 * f64::from(i), 0.5, 0.5))).collect();
+        assert!(values.iter().all(|value| value.abs() <= 1.0));
+        assert!(values.iter().any(|&value| value > 0.1) && values.iter().any(|&value| value < -0.1));
+    }
+
+    #[test]
+    fn textures_stay_in_range() {
+        for &kind in &[NoiseKind::Noise, NoiseKind::Turbulence, NoiseKind::Marble] {
+            let noise = Noise::new(kind, 4.0, 0);
+            for i in 0..200 {
+                let value = noise.value(&hit_at(Point3::new(0.1 * f64::from(i), 0.2, -0.03 * f64::from(i)), (0.0, 0.0)));
+                assert!((0.0..=1.0).contains(&value));
+            }
+        }
+    }
+}
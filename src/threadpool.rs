@@ -69,12 +69,33 @@ impl ThreadPool {
     {
         self.last_job = Some(Box::new(f));
     }
+
+    /// Waits for every queued job to finish, returning the panic of any worker that panicked
+    pub fn join(mut self) -> thread::Result<()> {
+        for _ in &self.workers {
+            // Workers that panicked have already dropped their end of the channel
+            let _ = self.sender.send(Message::Terminate);
+        }
+
+        let mut result = Ok(());
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                if let Err(err) = thread.join() {
+                    result = Err(err);
+                }
+            }
+        }
+
+        result
+    }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        for worker in &self.workers {
+            if worker.thread.is_some() {
+                self.sender.send(Message::Terminate).unwrap();
+            }
         }
 
         for worker in &mut self.workers {
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// A rectangular block of pixels, rendered as a unit by one worker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
+}
+
+/// The order in which tiles are handed out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TileOrder {
+    /// Left to right, top to bottom
+    Scanline,
+    /// Outwards from the center of the image, so the middle fills in first
+    Spiral,
+    /// Along a Hilbert curve, so consecutive tiles are mostly neighbours. The curve fills a square
+    /// grid a power of two wide, so on other grids it sometimes leaves the image and comes back
+    /// somewhere else.
+    Hilbert
+}
+
+impl TileOrder {
+    /// Picks an order from its name, e.g. "hilbert"
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match &name.to_ascii_lowercase()[..] {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None
+        }
+    }
+}
+
+/// Walks the tile grid in a square spiral from its center, returning the grid coordinates of every
+/// tile
+fn spiral(cols: i64, rows: i64) -> Vec<(i64, i64)> {
+    let mut coords = Vec::with_capacity((cols * rows) as usize);
+    let (mut x, mut y) = ((cols - 1) / 2, (rows - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+
+    let visit = |x: i64, y: i64, coords: &mut Vec<(i64, i64)>| {
+        if x >= 0 && x < cols && y >= 0 && y < rows {
+            coords.push((x, y));
+        }
+    };
+
+    visit(x, y, &mut coords);
+    let mut leg = 0;
+    while coords.len() < (cols * rows) as usize {
+        // Legs of the spiral grow by one every second turn: 1, 1, 2, 2, 3, 3, ...
+        let (dx, dy) = directions[leg % 4];
+        for _ in 0..(leg / 2 + 1) {
+            x += dx;
+            y += dy;
+            visit(x, y, &mut coords);
+        }
+        leg += 1;
+    }
+
+    coords
+}
+
+/// Finds the distance along a Hilbert curve filling an `n`x`n` grid, where `n` is a power of two
+fn hilbert_index(n: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        d += u64::from(s) * u64::from(s) * u64::from((3 * rx) ^ ry);
+
+        // Rotate the quadrant so the curve inside it has the right orientation
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    d
+}
+
+/// Splits an image into tiles of at most `size`x`size` pixels, in the given order
+pub fn tiles(width: u32, height: u32, size: u32, order: TileOrder) -> Vec<Tile> {
+    let size = size.max(1);
+    let cols = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+
+    let tile = |col: u32, row: u32| Tile {
+        x: col * size,
+        y: row * size,
+        width: u32::min(size, width - col * size),
+        height: u32::min(size, height - row * size)
+    };
+
+    match order {
+        TileOrder::Scanline => (0..rows).flat_map(|row| (0..cols).map(move |col| (col, row)))
+            .map(|(col, row)| tile(col, row))
+            .collect(),
+        TileOrder::Spiral => spiral(i64::from(cols), i64::from(rows)).into_iter()
+            .map(|(col, row)| tile(col as u32, row as u32))
+            .collect(),
+        TileOrder::Hilbert => {
+            let n = u32::max(cols, rows).next_power_of_two();
+            let mut coords: Vec<_> = (0..rows).flat_map(|row| (0..cols).map(move |col| (col, row))).collect();
+            coords.sort_by_key(|&(col, row)| hilbert_index(n, col, row));
+            coords.into_iter().map(|(col, row)| tile(col, row)).collect()
+        }
+    }
+}
+
+/// Per-worker queues of tiles. Workers take tiles from the front of their own queue, and once
+/// it's empty, steal from the back of everyone else's.
+pub struct TileQueues {
+    queues: Vec<Mutex<VecDeque<Tile>>>
+}
+
+impl TileQueues {
+    /// Deals tiles out to `workers` queues round-robin, so each queue keeps the tiles' order
+    pub fn new(tiles: Vec<Tile>, workers: usize) -> TileQueues {
+        let mut queues = vec![VecDeque::new(); workers.max(1)];
+        let count = queues.len();
+        for (i, tile) in tiles.into_iter().enumerate() {
+            queues[i % count].push_back(tile);
+        }
+
+        TileQueues { queues: queues.into_iter().map(Mutex::new).collect() }
+    }
+
+    /// Gets the next tile for a worker, or `None` once every queue is empty
+    pub fn next(&self, worker: usize) -> Option<Tile> {
+        if let Some(tile) = self.queues[worker].lock().unwrap().pop_front() {
+            return Some(tile);
+        }
+
+        (1..self.queues.len())
+            .map(|offset| (worker + offset) % self.queues.len())
+            .find_map(|victim| self.queues[victim].lock().unwrap().pop_back())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn covered_pixels(tiles: &[Tile], width: u32, height: u32) -> Vec<u32> {
+        let mut counts = vec![0; (width * height) as usize];
+        for tile in tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    counts[(y * width + x) as usize] += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn tiles_cover_image_once() {
+        for &order in &[TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = tiles(70, 45, 16, order);
+            assert_eq!(tiles.len(), 5 * 3);
+            assert!(covered_pixels(&tiles, 70, 45).iter().all(|&count| count == 1));
+        }
+    }
+
+    #[test]
+    fn spiral_starts_in_center() {
+        let tiles = tiles(48, 48, 16, TileOrder::Spiral);
+        assert_eq!((tiles[0].x, tiles[0].y), (16, 16));
+        assert_eq!((tiles[1].x, tiles[1].y), (32, 16));
+    }
+
+    /// How many times consecutive tiles `size` apart aren't next to each other
+    fn hilbert_jumps(width: u32, height: u32, size: u32) -> usize {
+        tiles(width, height, size, TileOrder::Hilbert).windows(2)
+            .filter(|pair| (pair[0].x as i64 - pair[1].x as i64).abs() + (pair[0].y as i64 - pair[1].y as i64).abs() != size as i64)
+            .count()
+    }
+
+    #[test]
+    fn hilbert_tiles_are_adjacent() {
+        assert_eq!(hilbert_jumps(64, 64, 8), 0);
+
+        // On a 13x7 grid, the curve only has to jump back into the image a few times
+        let jumps = hilbert_jumps(100, 56, 8);
+        assert!(jumps > 0 && jumps <= 4, "{} jumps", jumps);
+        assert_eq!(hilbert_jumps(24, 16, 8), 1);
+    }
+
+    #[test]
+    fn workers_steal_when_idle() {
+        let queues = TileQueues::new(tiles(32, 16, 16, TileOrder::Scanline), 2);
+        assert_eq!(queues.next(0).map(|tile| tile.x), Some(0));
+        assert_eq!(queues.next(0).map(|tile| tile.x), Some(16));
+        assert_eq!(queues.next(0), None);
+        assert_eq!(queues.next(1), None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::math::f_clamp;
+use crate::vec::{ Color, ColorRGB };
+
+/// Curves for squeezing unbounded scene radiance into the displayable [0, 1] range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneOperator {
+    /// Scales by the white point and clips anything brighter
+    Clamp,
+    /// Extended Reinhard on luminance, which keeps hues intact as colors brighten
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, applied per channel
+    Aces
+}
+
+impl ToneOperator {
+    /// Picks an operator from its name, e.g. "aces"
+    pub fn from_name(name: &str) -> Option<ToneOperator> {
+        match &name.to_ascii_lowercase()[..] {
+            "clamp" | "none" => Some(ToneOperator::Clamp),
+            "reinhard" => Some(ToneOperator::Reinhard),
+            "aces" | "filmic" => Some(ToneOperator::Aces),
+            _ => None
+        }
+    }
+}
+
+/// The display transform applied when writing 8-bit images: exposure, then a tone curve, then
+/// the sRGB transfer function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneOperator,
+    /// Exposure adjustment in stops
+    pub exposure: f64,
+    /// The (exposed) radiance that maps to display white. `None` uses the operator's own
+    /// default: 1 for clamping, and no upper bound for Reinhard and ACES.
+    pub white_point: Option<f64>
+}
+
+impl Default for ToneMapping {
+    fn default() -> ToneMapping {
+        ToneMapping { operator: ToneOperator::Clamp, exposure: 0.0, white_point: None }
+    }
+}
+
+/// Relative luminance of a linear Rec. 709 color
+pub fn luminance(color: &ColorRGB) -> f64 {
+    0.2126 * color[Color::R] + 0.7152 * color[Color::G] + 0.0722 * color[Color::B]
+}
+
+/// The sRGB opto-electronic transfer function, mapping linear [0, 1] to encoded [0, 1]
+pub fn srgb_oetf(linear: f64) -> f64 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The inverse of `srgb_oetf`, for reading colors stored in sRGB images
+pub fn srgb_eotf(encoded: f64) -> f64 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn aces(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+impl ToneMapping {
+    /// Maps linear radiance to linear display values in [0, 1]
+    pub fn tone_map(&self, color: &ColorRGB) -> ColorRGB {
+        let exposed = color * 2_f64.powf(self.exposure);
+        let r = f64::max(0.0, exposed[Color::R]);
+        let g = f64::max(0.0, exposed[Color::G]);
+        let b = f64::max(0.0, exposed[Color::B]);
+
+        let mapped = match self.operator {
+            ToneOperator::Clamp => {
+                let white = self.white_point.unwrap_or(1.0);
+                ColorRGB::new(r / white, g / white, b / white)
+            },
+            ToneOperator::Reinhard => {
+                let color = ColorRGB::new(r, g, b);
+                let lum = luminance(&color);
+                if lum <= 0.0 {
+                    return ColorRGB::new(0.0, 0.0, 0.0);
+                }
+
+                let white_sq = self.white_point.map_or(f64::INFINITY, |white| white * white);
+                let mapped_lum = lum * (1.0 + lum / white_sq) / (1.0 + lum);
+                color * (mapped_lum / lum)
+            },
+            ToneOperator::Aces => {
+                let white = self.white_point.map_or(1.0, aces);
+                ColorRGB::new(aces(r) / white, aces(g) / white, aces(b) / white)
+            }
+        };
+
+        ColorRGB::new(
+            f_clamp(mapped[Color::R], 0.0, 1.0),
+            f_clamp(mapped[Color::G], 0.0, 1.0),
+            f_clamp(mapped[Color::B], 0.0, 1.0)
+        )
+    }
+
+    /// Maps linear radiance to sRGB-encoded display values in [0, 1]
+    pub fn apply(&self, color: &ColorRGB) -> ColorRGB {
+        let mapped = self.tone_map(color);
+        ColorRGB::new(
+            srgb_oetf(mapped[Color::R]),
+            srgb_oetf(mapped[Color::G]),
+            srgb_oetf(mapped[Color::B])
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::f_eq;
+
+    #[test]
+    fn srgb_curve() {
+        assert!(f_eq(srgb_oetf(0.0), 0.0));
+        assert!(f_eq(srgb_oetf(1.0), 1.0));
+        assert!(f_eq(srgb_oetf(0.001), 0.01292));
+        assert!(f_eq(srgb_oetf(0.18), 0.461356));
+        assert!(f_eq(srgb_eotf(srgb_oetf(0.18)), 0.18));
+    }
+
+    #[test]
+    fn operators_stay_in_range() {
+        let bright = ColorRGB::new(16.3, 16.3, 4.0);
+        for &operator in &[ToneOperator::Clamp, ToneOperator::Reinhard, ToneOperator::Aces] {
+            let mapped = ToneMapping { operator, ..Default::default() }.apply(&bright);
+            for &channel in &[Color::R, Color::G, Color::B] {
+                assert!(mapped[channel] >= 0.0 && mapped[channel] <= 1.0);
+            }
+        }
+
+        // Reinhard compresses the highlight instead of clipping it, keeping the hue
+        let reinhard = ToneMapping { operator: ToneOperator::Reinhard, ..Default::default() };
+        let mapped = reinhard.tone_map(&bright);
+        assert!(mapped[Color::R] < 1.0);
+        assert!(f_eq(mapped[Color::R] / mapped[Color::B], 16.3 / 4.0));
+    }
+
+    #[test]
+    fn white_point_and_exposure() {
+        let white = ColorRGB::new(4.0, 4.0, 4.0);
+        for &operator in &[ToneOperator::Clamp, ToneOperator::Reinhard, ToneOperator::Aces] {
+            let mapping = ToneMapping { operator, exposure: 0.0, white_point: Some(4.0) };
+            assert!(f_eq(mapping.tone_map(&white)[Color::G], 1.0));
+
+            // One stop down halves the input, leaving it below white
+            let darker = ToneMapping { exposure: -1.0, ..mapping };
+            assert!(darker.tone_map(&white)[Color::G] < 0.99);
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use rand::distributions::Distribution;
 use std::{ ops::{self}, clone, cmp, convert, fmt };
 
 use super::math;
+use super::material::{ LobeFlags, MediumStack };
 use super::geom::{
     World,
-    hit::{ Hit, Hittable }
+    hit::{ Hit, Hittable, HittableGroup }
 };
 
 /// A struct for a 3-dimensional floating-point vector
@@ -24,7 +24,7 @@ pub struct Ray {
     pub dir: Vec3
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub enum Color {
     R, G, B
 }
@@ -35,6 +35,11 @@ pub enum Coord {
 }
 
 impl Ray {
+    /// The number of bounces every path makes before Russian roulette can end it
+    const ROULETTE_DEPTH: u32 = 3;
+    /// Even bright paths are ended sometimes, so that paths between mirrors don't go on forever
+    const MAX_SURVIVAL: f64 = 0.95;
+
     pub fn new(origin: &Point3, dir: &Vec3) -> Ray {
         Ray {
             origin: Point3::new(
@@ -48,39 +53,131 @@ impl Ray {
         &self.origin + &(t * &self.dir)
     }
 
-    pub fn get_color(&self, world: &World, bg: &dyn Fn(f64) -> ColorRGB, depth: u32, rand: &mut math::Rand)
-        -> ColorRGB
+    /// Follows the ray's path through the world and finds the light that arrives back along it.
+    /// Light is found both by scattering into emissive surfaces and, at every surface that isn't
+    /// purely specular, by sampling a point on one of `lights` and checking it's in view. The two
+    /// are combined with multiple importance sampling.
+    pub fn get_color(&self, world: &World, lights: &HittableGroup, bg: &dyn Fn(f64) -> ColorRGB, depth: u32,
+        rand: &mut math::Rand) -> ColorRGB
     {
-        let mut color = colors::WHITE;
+        let mut color = colors::BLACK;
+        let mut throughput = colors::WHITE;
         let mut ray: Ray = self.clone();
+        // Where the ray last scattered, and the density of its direction unless it was specular
+        let mut last_scatter: Option<(Point3, f64)> = None;
+        let mut media = MediumStack::new();
 
-        for _ in 0..depth {
-            match world.is_hit(&ray, Hit::FP_OFFSET, f64::INFINITY, rand) {
+        for bounce in 0..depth {
+            let mut hit = match world.is_hit(&ray, Hit::FP_OFFSET, f64::INFINITY, rand) {
                 None => {
                     let t = 0.5 * (1.0 - ray.dir[Coord::Y]);
-                    color *= bg(t);
-                    break;
+                    return color + throughput * bg(t);
                 },
-                Some(hit) => {
-                    match hit.material.scatter(&ray, &hit, rand) {
-                        None => {
-                            color *= hit.material.attenuation() * hit.material.emit();
-                            break;
-                        },
-                        Some(scattered) => {
-                            ray = scattered;
-                            color *= hit.material.attenuation();
-                            color += hit.material.emit();
-                        }
-                    }
+                Some(hit) => hit
+            };
+            let wo = -&ray.dir;
+            hit.exterior_ior = media.exterior_ior(&hit);
+            if let Some(medium) = media.current() {
+                throughput *= medium.transmittance(hit.t);
+            }
+
+            if hit.material.is_emissive() {
+                let weight = match &last_scatter {
+                    Some((origin, scatter_pdf)) => math::power_heuristic(*scatter_pdf, light_pdf(lights, origin, &hit)),
+                    None => 1.0
+                };
+                color += weight * &throughput * hit.material.emit(&hit);
+            }
+
+            color += &throughput * sample_light(world, lights, &media, &wo, &hit, rand);
+
+            let sample = match hit.material.sample(&wo, &hit, rand) {
+                Some(sample) => sample,
+                None => return color
+            };
+            last_scatter = if sample.flags.is_specular() { None } else { Some((hit.point.clone(), sample.pdf)) };
+            throughput *= &sample.weight;
+            ray = Ray::new(&hit.point, &sample.wi);
+            if sample.flags.contains(LobeFlags::TRANSMISSION) {
+                media.cross(&hit);
+            }
+
+            // Past the first few bounces, paths carrying little light are ended at random, and the
+            // ones that carry on make up for them, so the image stays the same on average
+            if bounce + 1 >= Ray::ROULETTE_DEPTH {
+                let survival = [Color::R, Color::G, Color::B].iter()
+                    .map(|&channel| throughput[channel])
+                    .fold(0.0, f64::max)
+                    .min(Ray::MAX_SURVIVAL);
+                if math::sample_1d(rand) >= survival {
+                    return color;
                 }
+                throughput *= 1.0 / survival;
             }
         }
 
+        // Paths cut off at the maximum depth never find any more light
         color
     }
 }
 
+/// The density, per unit solid angle seen from `origin`, with which sampling `lights` picks the
+/// point `hit` found. Hits inside volumes have no surface normal, and are never picked.
+fn light_pdf(lights: &HittableGroup, origin: &Point3, hit: &Hit) -> f64 {
+    let to_hit = &hit.point - origin;
+    let cos_theta = hit.geometric_normal.dot(&to_hit.unit()).abs();
+    let area = lights.surface_area();
+    if cos_theta <= 0.0 || area <= 0.0 {
+        return 0.0;
+    }
+
+    to_hit.dot(&to_hit) / (cos_theta * area)
+}
+
+/// Picks a point on `lights`, uniformly by area, and finds the light it sends to `hit` and on
+/// towards `wo`, weighted against finding the same light by sampling the BSDF. Light in view is in
+/// the same medium as `hit`, and is absorbed by it on the way.
+fn sample_light(world: &World, lights: &HittableGroup, media: &MediumStack, wo: &Vec3, hit: &Hit,
+    rand: &mut math::Rand) -> ColorRGB
+{
+    let flags = hit.material.flags();
+    if !flags.contains(LobeFlags::DIFFUSE) && !flags.contains(LobeFlags::GLOSSY) {
+        return colors::BLACK;
+    }
+
+    let point = match lights.sample_surface(math::sample_2d(rand)) {
+        Some(point) => point,
+        None => return colors::BLACK
+    };
+    let to_light = &point - &hit.point;
+    let dist = to_light.norm();
+    let wi = to_light.unit();
+
+    let response = hit.material.eval(wo, &wi, hit);
+    if [Color::R, Color::G, Color::B].iter().all(|&channel| response[channel] <= 0.0) {
+        return colors::BLACK;
+    }
+
+    // Anything in between casts a shadow, including the far side of the light itself
+    let shadow_ray = Ray::new(&hit.point, &wi);
+    let light_hit = match world.is_hit(&shadow_ray, Hit::FP_OFFSET, dist * (1.0 + 1e-6), rand) {
+        Some(light_hit) if light_hit.t > dist * (1.0 - 1e-6) && light_hit.material.is_emissive() => light_hit,
+        _ => return colors::BLACK
+    };
+
+    let light_pdf = light_pdf(lights, &hit.point, &light_hit);
+    if light_pdf <= 0.0 {
+        return colors::BLACK;
+    }
+
+    let weight = math::power_heuristic(light_pdf, hit.material.pdf(wo, &wi, hit)) / light_pdf;
+    let light = weight * response * light_hit.material.emit(&light_hit);
+    match media.current() {
+        Some(medium) => light * medium.transmittance(light_hit.t),
+        None => light
+    }
+}
+
 impl Vec3 {
     pub const I: Vec3 = Vec3 ( 1.0, 0.0, 0.0 );
     pub const J: Vec3 = Vec3 ( 0.0, 1.0, 0.0 );
@@ -148,29 +245,47 @@ impl Vec3 {
         let cos_theta_i = f64::abs(self_unit.dot(normal));
         // Total internal reflection or Fresnel reflection
         if index_i >= index_r && sin_theta_i > index_r/index_i
-            || rand.dist.sample(&mut rand.rng) < math::schlick(cos_theta_i, index_i, index_r)
+            || math::sample_1d(rand) < math::schlick(cos_theta_i, index_i, index_r)
         {
             return self.reflect(normal);
         }
 
         let theta_r = (sin_theta_i * index_i / index_r).asin();
-        let refracted_perp = theta_r.tan() * self.cross(&-normal).cross(&normal).unit();
+        let refracted_perp = theta_r.tan() * self.cross(&-normal).cross(normal).unit();
         -normal + refracted_perp
     }
 
+    /// Picks a direction uniformly over the unit sphere, from the next two dimensions of the sample
     pub fn random_unit(rand: &mut math::Rand) -> Vec3 {
-        // Under the assumption that the input dist is 0 to 1
-        let x = 2.0 * rand.dist.sample(&mut rand.rng) - 1.0;
-        let y = 2.0 * rand.dist.sample(&mut rand.rng) - 1.0;
-        let z = 2.0 * rand.dist.sample(&mut rand.rng) - 1.0;
-        Vec3::new(x, y, z).unit()
+        let (u, v) = math::sample_2d(rand);
+        Vec3::on_unit_sphere(u, v)
+    }
+
+    /// Maps a point in the unit square to a point on the unit sphere, uniformly by area
+    pub fn on_unit_sphere(u: f64, v: f64) -> Vec3 {
+        // Heights on a sphere are uniformly distributed (Archimedes' hat-box theorem)
+        let z = 1.0 - 2.0 * u;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
     }
 
+    /// Picks a point uniformly in the unit disc in the xy-plane, from the next two dimensions of
+    /// the sample
     pub fn random_in_unit_disc(rand: &mut math::Rand) -> Vec3 {
-        // Under the assumption that the input dist is 0 to 1
-        let x = 2.0 * rand.dist.sample(&mut rand.rng) - 1.0;
-        let y = 2.0 * rand.dist.sample(&mut rand.rng) - 1.0;
-        Vec3::new(x, y, 0.0).unit()
+        // Shirley and Chiu's concentric mapping, which keeps strata of the square as strata of the disc
+        let (u, v) = math::sample_2d(rand);
+        let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return Vec3::O;
+        }
+
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, std::f64::consts::FRAC_PI_4 * (y / x))
+        } else {
+            (y, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (x / y))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
     }
 
     pub fn rotate(&self, angle: f64, axis: &Vec3) -> Vec3 {
@@ -662,8 +777,7 @@ mod tests {
     #[test]
     fn refract_vec() {
         let constant = rand::distributions::Uniform::from(0.9..1.0);
-        let rng = rand::thread_rng();
-        let mut rand = math::Rand { dist: constant, rng };
+        let mut rand = math::Rand { dist: constant, ..math::Rand::seeded(0) };
 
         let normal = Vec3::J;
         let incident = Vec3::new(1.0, -1.0, 0.0);
@@ -675,8 +789,7 @@ mod tests {
     #[test]
     fn refract_another_vec() {
         let constant = rand::distributions::Uniform::from(0.9..1.0);
-        let rng = rand::thread_rng();
-        let mut rand = math::Rand { dist: constant, rng };
+        let mut rand = math::Rand { dist: constant, ..math::Rand::seeded(0) };
 
         let normal = Vec3::new(0.5, 0.4, 0.3).unit();
         let incident = Vec3::new(0.7, 0.9, 1.0);
@@ -689,8 +802,7 @@ mod tests {
     #[test]
     fn total_internal_reflection() {
         let constant = rand::distributions::Uniform::from(0.9..1.0);
-        let rng = rand::thread_rng();
-        let mut rand = math::Rand { dist: constant, rng };
+        let mut rand = math::Rand { dist: constant, ..math::Rand::seeded(0) };
 
         let normal = Vec3::J;
         let incident = Vec3::new(1.0, -1.0, 0.0);
@@ -701,12 +813,96 @@ mod tests {
     #[test]
     fn schlick() {
         let constant = rand::distributions::Uniform::from(0.0..1.0);
-        let rng = rand::thread_rng();
-        let mut rand = math::Rand { dist: constant, rng };
+        let mut rand = math::Rand { dist: constant, ..math::Rand::seeded(0) };
 
         let normal = Vec3::J;
         let incident = Vec3::I;
         let refracted = incident.refract(&normal, 1.0, 1.5, &mut rand);
         assert_eq!(refracted, incident.reflect(&normal));
     }
+
+    #[test]
+    fn light_sampling_is_unbiased() {
+        use crate::geom::primitives::Sphere;
+        use crate::material::{ DiffuseLambert, Emissive };
+        use std::sync::Arc;
+
+        // A grey ball inside a glowing sphere reflects half the light that reaches it from every
+        // direction, whether or not the glowing sphere is sampled directly
+        let world = HittableGroup::new(vec![
+            Arc::new(Sphere::new(Point3::O, 10.0, Arc::new(Emissive::new(colors::WHITE, 2.0)))),
+            Arc::new(Sphere::new(Point3::O, 1.0, Arc::new(DiffuseLambert::new(0.5 * colors::WHITE))))
+        ]);
+        let ray = Ray::new(&Point3::new(0.0, 0.0, 5.0), &-Vec3::K);
+
+        for lights in [world.emitters(), HittableGroup::new(vec![])] {
+            let mut rand = math::Rand::seeded(1);
+            let mut total = colors::BLACK;
+            for _ in 0..2000 {
+                total += ray.get_color(&world, &lights, &|_| colors::BLACK, 10, &mut rand);
+            }
+            let mean = total * (1.0 / 2000.0);
+            assert!((mean[Color::G] - 1.0).abs() < 0.02, "Got {}, not 1", mean);
+        }
+    }
+
+    #[test]
+    fn glass_conserves_energy() {
+        use crate::geom::primitives::Sphere;
+        use crate::material::{ Emissive, Translucent };
+        use std::sync::Arc;
+
+        // Clear glass in an evenly glowing room looks the same as the room, and a glass that
+        // absorbs red light looks cyan
+        let clear = Translucent::new(colors::WHITE, 1.5, 0.0);
+        let tinted = Translucent::new(colors::WHITE, 1.5, 0.0).with_absorption(ColorRGB::new(2.0, 0.0, 0.0));
+        for glass in [clear, tinted] {
+            let world = HittableGroup::new(vec![
+                Arc::new(Sphere::new(Point3::O, 10.0, Arc::new(Emissive::new(colors::WHITE, 1.0)))),
+                Arc::new(Sphere::new(Point3::O, 1.0, Arc::new(glass)))
+            ]);
+            let lights = world.emitters();
+            let ray = Ray::new(&Point3::new(0.0, 0.0, 5.0), &-Vec3::K);
+
+            let mut rand = math::Rand::seeded(4);
+            let mut total = colors::BLACK;
+            for _ in 0..2000 {
+                total += ray.get_color(&world, &lights, &|_| colors::BLACK, 50, &mut rand);
+            }
+            let mean = total * (1.0 / 2000.0);
+            assert!((mean[Color::G] - 1.0).abs() < 0.02, "Got {}", mean);
+            assert!((mean[Color::B] - 1.0).abs() < 0.02, "Got {}", mean);
+            assert!(mean[Color::R] > 0.98 || mean[Color::R] < 0.1, "Got {}", mean);
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        use crate::geom::primitives::Sphere;
+        use crate::material::{ DiffuseLambert, Emissive };
+        use std::sync::Arc;
+
+        // Inside a closed grey room lit by a small ball, light bounces around many times. Every
+        // point on the walls sees the ball over a fraction k = (0.1/1)^2 of their cosine-weighted
+        // view, so their radiance L satisfies L = albedo * (k * 100 + (1 - k) * L).
+        let world = HittableGroup::new(vec![
+            Arc::new(Sphere::new(Point3::O, 0.1, Arc::new(Emissive::new(colors::WHITE, 100.0)))),
+            Arc::new(Sphere::new(Point3::O, 1.0, Arc::new(DiffuseLambert::new(0.75 * colors::WHITE))))
+        ]);
+        let lights = world.emitters();
+        let ray = Ray::new(&Point3::new(0.0, 0.0, 0.5), &Vec3::K);
+        let expected = 0.75 * 0.01 * 100.0 / (1.0 - 0.75 * 0.99);
+
+        let mut rand = math::Rand::seeded(2);
+        let mut total = colors::BLACK;
+        for _ in 0..4000 {
+            total += ray.get_color(&world, &lights, &|_| colors::BLACK, 1000, &mut rand);
+        }
+        let mean = total * (1.0 / 4000.0);
+        assert!((mean[Color::G] / expected - 1.0).abs() < 0.03, "Got {}, not {}", mean, expected);
+
+        // Paths cut off at the maximum depth add nothing
+        let unlit = ray.get_color(&world, &HittableGroup::new(vec![]), &|_| colors::WHITE, 1, &mut rand);
+        assert_eq!(unlit, colors::BLACK);
+    }
 }